@@ -0,0 +1,735 @@
+//! Machine-language monitor.
+//!
+//! Drives a headless `cpu::CPU` from commands read on stdin, one per line,
+//! so it works over SSH and with a script piped in:
+//!
+//!     monitor [--variant 6502|2a03|65c02] [file [address]]
+//!
+//! Numbers are hexadecimal, with or without a leading `$`. Type `?` for the
+//! list of commands.
+
+use cpu::debug::{Access, Breakpoint, BreakpointHit, BreakpointId};
+use cpu::disasm::{self, Symbols};
+use cpu::profile::Profiler;
+use cpu::{CpuFlags, CpuVariant, Mem, CPU, DEFAULT_ORIGIN};
+use std::convert::TryFrom;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process;
+
+/// Lines of each table in the profile report
+const PROFILE_LINES: usize = 20;
+
+const HELP: &str = "\
+l file [addr]         load a binary, at $0600 by default
+a addr instruction    assemble one instruction
+r [reg value]         show registers, or set A X Y SP P PC
+z [count]             step into
+n                     step over a JSR
+g [addr]              continue until a breakpoint or BRK
+reset                 reset through the reset vector
+m [start [end]]       hex dump memory
+> addr byte...        write bytes to memory
+d [start [end]]       disassemble
+f start end byte...   fill a range with a pattern
+t start end dest      copy a range
+c start end dest      compare a range with another
+b addr                break before executing an address
+b if condition        break when a condition holds, like A == $40 && Z
+w [r|w] start [end]   break after an access to a range
+bl                    list breakpoints
+bd id                 delete a breakpoint
+p                     start profiling, or show the profile so far
+p off                 stop profiling
+p flame file          write the profile as collapsed stacks for a flame graph
+x                     quit";
+
+struct Monitor {
+    cpu: CPU,
+    /// Where `m` and `d` continue from when given no address
+    next_dump: u16,
+    next_disassembly: u16,
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1).peekable();
+    let mut variant = CpuVariant::default();
+    if args.peek().map(String::as_str) == Some("--variant") {
+        args.next();
+        variant = match args.next().map(|name| name.parse()) {
+            Some(Ok(variant)) => variant,
+            _ => {
+                eprintln!("--variant takes 6502, 2a03 or 65c02");
+                process::exit(2);
+            }
+        };
+    }
+
+    let mut cpu = CPU::with_variant(variant);
+    // a BRK hands control back to the monitor
+    cpu.stop_on_brk = true;
+    let mut monitor = Monitor {
+        cpu,
+        next_dump: DEFAULT_ORIGIN,
+        next_disassembly: DEFAULT_ORIGIN,
+    };
+
+    let load: Vec<String> = args.collect();
+    if !load.is_empty() {
+        let command = format!("l {}", load.join(" "));
+        if let Err(e) = monitor.command(&command) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+
+    let interactive = io::stdin().is_terminal();
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        if interactive {
+            print!(". ");
+            io::stdout().flush().unwrap();
+        }
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        match monitor.command(&line) {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(e) => println!("? {}", e),
+        }
+    }
+}
+
+/// A command line, parsed and checked before anything is executed
+#[derive(Debug, PartialEq)]
+enum Command {
+    Nothing,
+    Help,
+    Quit,
+    Load {
+        path: String,
+        addr: u16,
+    },
+    Assemble {
+        addr: u16,
+        instruction: String,
+    },
+    Registers,
+    SetRegister {
+        register: Register,
+        value: u16,
+    },
+    Step(u16),
+    Next,
+    Go(Option<u16>),
+    Reset,
+    Dump {
+        start: Option<u16>,
+        end: Option<u16>,
+    },
+    Write {
+        addr: u16,
+        bytes: Vec<u8>,
+    },
+    Disassemble {
+        start: Option<u16>,
+        end: Option<u16>,
+    },
+    Fill {
+        start: u16,
+        end: u16,
+        pattern: Vec<u8>,
+    },
+    Transfer {
+        start: u16,
+        end: u16,
+        dest: u16,
+    },
+    Compare {
+        start: u16,
+        end: u16,
+        dest: u16,
+    },
+    Break(Breakpoint),
+    ListBreakpoints,
+    DeleteBreakpoint(BreakpointId),
+    Profile,
+    ProfileOff,
+    ProfileFlame(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register {
+    A,
+    X,
+    Y,
+    StackPointer,
+    Status,
+    ProgramCounter,
+}
+
+fn parse(line: &str) -> Result<Command, String> {
+    let line = line.trim();
+    let (command, rest) = match line.find(char::is_whitespace) {
+        Some(end) => (&line[..end], line[end..].trim()),
+        None => (line, ""),
+    };
+    let args: Vec<&str> = rest.split_whitespace().collect();
+
+    let command = match command {
+        "" => Command::Nothing,
+        "?" | "help" => Command::Help,
+        "x" | "q" => Command::Quit,
+        "l" => Command::Load {
+            path: args.first().ok_or("missing file")?.to_string(),
+            addr: optional(args.get(1))?.unwrap_or(DEFAULT_ORIGIN),
+        },
+        "a" => {
+            let addr = number(args.first().ok_or("missing address")?)?;
+            let instruction = rest[args[0].len()..].trim().to_string();
+            Command::Assemble { addr, instruction }
+        }
+        "r" => match args[..] {
+            [] => Command::Registers,
+            [register, value] => {
+                let value = number(value)?;
+                let register = match register.to_ascii_uppercase().as_str() {
+                    "A" => Register::A,
+                    "X" => Register::X,
+                    "Y" => Register::Y,
+                    "SP" => Register::StackPointer,
+                    "P" => Register::Status,
+                    "PC" => Register::ProgramCounter,
+                    _ => return Err(format!("unknown register {}", register)),
+                };
+                if register != Register::ProgramCounter && value > 0xff {
+                    return Err(format!("{:X} does not fit in a byte", value));
+                }
+                Command::SetRegister { register, value }
+            }
+            _ => return Err("r takes a register and a value".to_string()),
+        },
+        "z" => Command::Step(optional(args.first())?.unwrap_or(1)),
+        "n" => Command::Next,
+        "g" => Command::Go(optional(args.first())?),
+        "reset" => Command::Reset,
+        "m" | "d" => {
+            let (start, end) = match args.len() {
+                0 | 1 => (optional(args.first())?, None),
+                _ => {
+                    let (start, end) = range(&args)?;
+                    (Some(start), Some(end))
+                }
+            };
+            if command == "m" {
+                Command::Dump { start, end }
+            } else {
+                Command::Disassemble { start, end }
+            }
+        }
+        ">" => {
+            let (addr, bytes) = args.split_first().ok_or("missing address")?;
+            Command::Write {
+                addr: number(addr)?,
+                bytes: byte_values(bytes)?,
+            }
+        }
+        "f" => {
+            let (start, end) = range(&args)?;
+            let pattern = byte_values(&args[2..])?;
+            if pattern.is_empty() {
+                return Err("missing fill bytes".to_string());
+            }
+            Command::Fill {
+                start,
+                end,
+                pattern,
+            }
+        }
+        "t" | "c" => {
+            let (start, end) = range(&args)?;
+            let dest = number(args.get(2).ok_or("missing destination")?)?;
+            if command == "t" {
+                Command::Transfer { start, end, dest }
+            } else {
+                Command::Compare { start, end, dest }
+            }
+        }
+        "b" => Command::Break(match rest.strip_prefix("if") {
+            Some(condition) => {
+                Breakpoint::Condition(condition.parse().map_err(|e| format!("{}", e))?)
+            }
+            None => Breakpoint::Execute(number(args.first().ok_or("missing address")?)?),
+        }),
+        "w" => {
+            let (access, args) = match args.first() {
+                Some(&"r") => (Access::Read, &args[1..]),
+                Some(&"w") => (Access::Write, &args[1..]),
+                _ => (Access::ReadWrite, &args[..]),
+            };
+            let (start, end) = match args.len() {
+                0 => return Err("missing address".to_string()),
+                1 => {
+                    let addr = number(args[0])?;
+                    (addr, addr)
+                }
+                _ => range(args)?,
+            };
+            Command::Break(Breakpoint::Watch {
+                range: start..=end,
+                access,
+            })
+        }
+        "bl" => Command::ListBreakpoints,
+        "bd" => {
+            let id = args.first().ok_or("missing breakpoint")?;
+            let id = id
+                .parse()
+                .map_err(|_| format!("invalid breakpoint {}", id))?;
+            Command::DeleteBreakpoint(id)
+        }
+        "p" => match args.first() {
+            None => Command::Profile,
+            Some(&"off") => Command::ProfileOff,
+            Some(&"flame") => Command::ProfileFlame(args.get(1).ok_or("missing file")?.to_string()),
+            Some(other) => return Err(format!("unknown profile command {}", other)),
+        },
+        _ => return Err(format!("unknown command {}, ? lists them", command)),
+    };
+    Ok(command)
+}
+
+impl Monitor {
+    /// Executes one command line, returns whether to quit.
+    fn command(&mut self, line: &str) -> Result<bool, String> {
+        match parse(line)? {
+            Command::Nothing => {}
+            Command::Help => println!("{}", HELP),
+            Command::Quit => return Ok(true),
+            Command::Load { path, addr } => self.load(&path, addr)?,
+            Command::Assemble { addr, instruction } => self.assemble(addr, &instruction)?,
+            Command::Registers => self.show_position(),
+            Command::SetRegister { register, value } => {
+                let cpu = &mut self.cpu;
+                match register {
+                    Register::A => cpu.register_a = value as u8,
+                    Register::X => cpu.register_x = value as u8,
+                    Register::Y => cpu.register_y = value as u8,
+                    Register::StackPointer => cpu.stack_pointer = value as u8,
+                    Register::Status => cpu.status = CpuFlags::from_bits_truncate(value as u8),
+                    Register::ProgramCounter => cpu.program_counter = value,
+                }
+                self.show_position();
+            }
+            Command::Step(count) => {
+                for _ in 0..count {
+                    self.cpu.step().map_err(|e| e.to_string())?;
+                }
+                self.show_position();
+            }
+            Command::Next => self.next()?,
+            Command::Go(addr) => {
+                if let Some(addr) = addr {
+                    self.cpu.program_counter = addr;
+                }
+                let hit = self.cpu.run().map_err(|e| e.to_string())?;
+                self.report(hit);
+            }
+            Command::Reset => {
+                self.cpu.reset();
+                self.show_position();
+            }
+            Command::Dump { start, end } => self.dump(start, end),
+            Command::Write { addr, bytes } => {
+                for (i, byte) in bytes.into_iter().enumerate() {
+                    self.cpu.mem_write(addr.wrapping_add(i as u16), byte);
+                }
+            }
+            Command::Disassemble { start, end } => self.disassemble(start, end),
+            Command::Fill {
+                start,
+                end,
+                pattern,
+            } => {
+                for (addr, byte) in (start..=end).zip(pattern.iter().cycle()) {
+                    self.cpu.mem_write(addr, *byte);
+                }
+            }
+            Command::Transfer { start, end, dest } => {
+                let bytes: Vec<u8> = (start..=end).map(|addr| self.cpu.mem_peek(addr)).collect();
+                for (i, byte) in bytes.into_iter().enumerate() {
+                    self.cpu.mem_write(dest.wrapping_add(i as u16), byte);
+                }
+            }
+            Command::Compare { start, end, dest } => {
+                for (i, addr) in (start..=end).enumerate() {
+                    let other = dest.wrapping_add(i as u16);
+                    let (a, b) = (self.cpu.mem_peek(addr), self.cpu.mem_peek(other));
+                    if a != b {
+                        println!("{:04X} {:02X}  {:04X} {:02X}", addr, a, other, b);
+                    }
+                }
+            }
+            Command::Break(breakpoint) => {
+                let kind = match breakpoint {
+                    Breakpoint::Watch { .. } => "watchpoint",
+                    _ => "breakpoint",
+                };
+                let id = self.cpu.add_breakpoint(breakpoint);
+                println!("{} {}", kind, id);
+            }
+            Command::ListBreakpoints => {
+                for (id, breakpoint) in self.cpu.breakpoints() {
+                    let description = match breakpoint {
+                        Breakpoint::Execute(addr) => format!("exec {:04X}", addr),
+                        Breakpoint::Watch { range, access } => {
+                            format!("{:?} {:04X}-{:04X}", access, range.start(), range.end())
+                        }
+                        Breakpoint::Condition(condition) => format!("if {}", condition),
+                    };
+                    println!("{:3}  {}", id, description);
+                }
+            }
+            Command::DeleteBreakpoint(id) => {
+                self.cpu
+                    .remove_breakpoint(id)
+                    .ok_or(format!("no breakpoint {}", id))?;
+            }
+            Command::Profile => match self.cpu.profiler() {
+                Some(profiler) => print!("{}", profiler.report(PROFILE_LINES)),
+                None => {
+                    self.cpu.set_profiler(Some(Profiler::new()));
+                    println!("profiling");
+                }
+            },
+            Command::ProfileOff => self.cpu.set_profiler(None),
+            Command::ProfileFlame(path) => {
+                let profiler = self.cpu.profiler().ok_or("not profiling, p starts")?;
+                std::fs::write(&path, profiler.collapsed_stacks())
+                    .map_err(|e| format!("{}: {}", path, e))?;
+            }
+        }
+        Ok(false)
+    }
+
+    fn load(&mut self, path: &str, addr: u16) -> Result<(), String> {
+        let program = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        if addr as usize + program.len() > 0x10000 {
+            return Err(format!("{} does not fit at {:04X}", path, addr));
+        }
+        for (i, byte) in program.iter().enumerate() {
+            self.cpu.mem_write(addr + i as u16, *byte);
+        }
+        self.cpu.program_counter = addr;
+        self.next_dump = addr;
+        self.next_disassembly = addr;
+        println!(
+            "loaded {:04X}-{:04X}",
+            addr,
+            addr as usize + program.len().max(1) - 1
+        );
+        Ok(())
+    }
+
+    /// Assembles a single instruction with `cpu::asm`.
+    fn assemble(&mut self, addr: u16, instruction: &str) -> Result<(), String> {
+        let source = format!(".org ${:04X}\n {}", addr, instruction);
+        let program = cpu::asm::assemble(&source).map_err(|e| e.message)?;
+        for (i, byte) in program.bytes.iter().enumerate() {
+            self.cpu
+                .mem_write(program.origin.wrapping_add(i as u16), *byte);
+        }
+        self.print_instruction(addr);
+        Ok(())
+    }
+
+    /// Steps over a subroutine call by running to the instruction after the JSR.
+    fn next(&mut self) -> Result<(), String> {
+        let pc = self.cpu.program_counter;
+        if self.cpu.mem_peek(pc) != 0x20 {
+            self.cpu.step().map_err(|e| e.to_string())?;
+            self.show_position();
+            return Ok(());
+        }
+
+        let target = self
+            .cpu
+            .add_breakpoint(Breakpoint::Execute(pc.wrapping_add(3)));
+        // the JSR itself must run even with a breakpoint on it
+        self.cpu.step().map_err(|e| e.to_string())?;
+        let result = self.cpu.run();
+        self.cpu.remove_breakpoint(target);
+        match result.map_err(|e| e.to_string())? {
+            Some(hit) if hit.id == target => self.show_position(),
+            hit => self.report(hit),
+        }
+        Ok(())
+    }
+
+    fn report(&mut self, hit: Option<BreakpointHit>) {
+        match hit {
+            Some(BreakpointHit {
+                id,
+                pc,
+                access: Some(access),
+            }) => {
+                let verb = if access.kind == Access::Write {
+                    "wrote"
+                } else {
+                    "read"
+                };
+                println!(
+                    "watchpoint {}: {:04X} {} {:02X} at {:04X}",
+                    id, pc, verb, access.value, access.address
+                );
+            }
+            Some(BreakpointHit { id, .. }) => println!("breakpoint {}", id),
+            None => println!("BRK"),
+        }
+        self.show_position();
+    }
+
+    /// Prints the registers and the next instruction.
+    fn show_position(&mut self) {
+        let cpu = &self.cpu;
+        println!("  PC  A  X  Y  SP NV-BDIZC  CYC");
+        println!(
+            " {:04X} {:02X} {:02X} {:02X} {:02X} {} {}",
+            cpu.program_counter,
+            cpu.register_a,
+            cpu.register_x,
+            cpu.register_y,
+            cpu.stack_pointer,
+            cpu.status,
+            cpu.cycles
+        );
+        let pc = cpu.program_counter;
+        self.print_instruction(pc);
+    }
+
+    fn print_instruction(&self, addr: u16) -> u16 {
+        let instruction = disasm::decode(&self.cpu, self.cpu.variant(), addr);
+        print!("{}", disasm::listing(&[instruction], &Symbols::new()));
+        instruction.next()
+    }
+
+    fn dump(&mut self, start: Option<u16>, end: Option<u16>) {
+        let start = start.unwrap_or(self.next_dump);
+        let end = end.unwrap_or_else(|| start.saturating_add(0x7f));
+        let mut addr = start;
+        loop {
+            let row_end = end.min(addr | 0x0f);
+            let bytes: Vec<u8> = (addr..=row_end)
+                .map(|addr| self.cpu.mem_peek(addr))
+                .collect();
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let text: String = bytes
+                .iter()
+                .map(|&byte| {
+                    if (0x20..0x7f).contains(&byte) {
+                        byte as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            println!("{:04X}  {:47}  {}", addr, hex.join(" "), text);
+            if row_end == end {
+                break;
+            }
+            addr = row_end + 1;
+        }
+        self.next_dump = end.wrapping_add(1);
+    }
+
+    fn disassemble(&mut self, start: Option<u16>, end: Option<u16>) {
+        let mut addr = start.unwrap_or(self.next_disassembly);
+        match end {
+            Some(end) => {
+                while addr <= end {
+                    let next = self.print_instruction(addr);
+                    // wrapped past $FFFF
+                    if next < addr {
+                        break;
+                    }
+                    addr = next;
+                }
+            }
+            None => {
+                for _ in 0..16 {
+                    addr = self.print_instruction(addr);
+                }
+            }
+        }
+        self.next_disassembly = addr;
+    }
+}
+
+fn number(text: &str) -> Result<u16, String> {
+    let digits = text.strip_prefix('$').unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid number {}", text))
+}
+
+fn byte_value(text: &str) -> Result<u8, String> {
+    let value = number(text)?;
+    u8::try_from(value).map_err(|_| format!("{} does not fit in a byte", text))
+}
+
+fn optional(text: Option<&&str>) -> Result<Option<u16>, String> {
+    text.map(|text| number(text)).transpose()
+}
+
+fn byte_values(args: &[&str]) -> Result<Vec<u8>, String> {
+    args.iter().map(|byte| byte_value(byte)).collect()
+}
+
+fn range(args: &[&str]) -> Result<(u16, u16), String> {
+    let start = number(args.first().ok_or("missing start")?)?;
+    let end = number(args.get(1).ok_or("missing end")?)?;
+    if end < start {
+        return Err("the range ends before it starts".to_string());
+    }
+    Ok((start, end))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(number("c000"), Ok(0xc000));
+        assert_eq!(number("$FF"), Ok(0xff));
+        assert_eq!(number("10000"), Err("invalid number 10000".to_string()));
+        assert_eq!(number("$"), Err("invalid number $".to_string()));
+        assert_eq!(
+            byte_value("100"),
+            Err("100 does not fit in a byte".to_string())
+        );
+    }
+
+    #[test]
+    fn test_ranges() {
+        assert_eq!(
+            parse("m 0600 06ff"),
+            Ok(Command::Dump {
+                start: Some(0x0600),
+                end: Some(0x06ff)
+            })
+        );
+        assert_eq!(
+            parse("d $0600"),
+            Ok(Command::Disassemble {
+                start: Some(0x0600),
+                end: None
+            })
+        );
+        assert_eq!(
+            parse("m"),
+            Ok(Command::Dump {
+                start: None,
+                end: None
+            })
+        );
+        let backwards = Err("the range ends before it starts".to_string());
+        assert_eq!(parse("m 0700 0600"), backwards);
+        assert_eq!(parse("d 0700 0600"), backwards);
+        assert_eq!(parse("f 0700 0600 00"), backwards);
+        assert_eq!(parse("w r 0700 0600"), backwards);
+        assert_eq!(parse("t 0600"), Err("missing end".to_string()));
+        assert_eq!(parse("c"), Err("missing start".to_string()));
+    }
+
+    #[test]
+    fn test_commands() {
+        assert_eq!(parse("  "), Ok(Command::Nothing));
+        assert_eq!(parse("z"), Ok(Command::Step(1)));
+        assert_eq!(parse("z 10"), Ok(Command::Step(0x10)));
+        assert_eq!(
+            parse("l game.bin"),
+            Ok(Command::Load {
+                path: "game.bin".to_string(),
+                addr: DEFAULT_ORIGIN
+            })
+        );
+        assert_eq!(
+            parse("a 0600 lda #$01"),
+            Ok(Command::Assemble {
+                addr: 0x0600,
+                instruction: "lda #$01".to_string()
+            })
+        );
+        assert_eq!(
+            parse("> 0200 01 $02"),
+            Ok(Command::Write {
+                addr: 0x0200,
+                bytes: vec![1, 2]
+            })
+        );
+        assert_eq!(
+            parse("t 0600 06ff 0800"),
+            Ok(Command::Transfer {
+                start: 0x0600,
+                end: 0x06ff,
+                dest: 0x0800
+            })
+        );
+        assert_eq!(
+            parse("r pc c000"),
+            Ok(Command::SetRegister {
+                register: Register::ProgramCounter,
+                value: 0xc000
+            })
+        );
+        assert_eq!(
+            parse("b 0610"),
+            Ok(Command::Break(Breakpoint::Execute(0x0610)))
+        );
+        assert_eq!(
+            parse("w w 0200 02ff"),
+            Ok(Command::Break(Breakpoint::Watch {
+                range: 0x0200..=0x02ff,
+                access: Access::Write
+            }))
+        );
+        assert_eq!(
+            parse("w 00ff"),
+            Ok(Command::Break(Breakpoint::Watch {
+                range: 0x00ff..=0x00ff,
+                access: Access::ReadWrite
+            }))
+        );
+        assert_eq!(
+            parse("b if A == $40 && Z"),
+            Ok(Command::Break(Breakpoint::Condition(
+                "A == $40 && Z".parse().unwrap()
+            )))
+        );
+        assert_eq!(parse("bd 3"), Ok(Command::DeleteBreakpoint(3)));
+        assert_eq!(
+            parse("p flame out.txt"),
+            Ok(Command::ProfileFlame("out.txt".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_bad_input() {
+        let error = |message: &str| Err(message.to_string());
+        assert_eq!(parse("r a 100"), error("100 does not fit in a byte"));
+        assert_eq!(parse("r q 1"), error("unknown register q"));
+        assert_eq!(parse("r a"), error("r takes a register and a value"));
+        assert_eq!(parse("> 0200 1ff"), error("1ff does not fit in a byte"));
+        assert_eq!(parse("> zz"), error("invalid number zz"));
+        assert_eq!(parse("f 0600 06ff"), error("missing fill bytes"));
+        assert_eq!(parse("t 0600 06ff"), error("missing destination"));
+        assert_eq!(parse("b"), error("missing address"));
+        assert_eq!(parse("w r"), error("missing address"));
+        assert!(parse("b if A ==").is_err());
+        assert_eq!(parse("bd x"), error("invalid breakpoint x"));
+        assert_eq!(parse("p flame"), error("missing file"));
+        assert_eq!(parse("p on"), error("unknown profile command on"));
+        assert_eq!(parse("jump"), error("unknown command jump, ? lists them"));
+    }
+}
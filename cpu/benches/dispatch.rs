@@ -0,0 +1,40 @@
+use cpu::CPU;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+const INSTRUCTIONS: u64 = 10_000;
+
+// A loop mixing loads, stores, arithmetic and indexed addressing
+const PROGRAM: &[u8] = &[
+    0xa5, 0x10, // LDA $10
+    0x18, // CLC
+    0x69, 0x03, // ADC #$03
+    0x85, 0x10, // STA $10
+    0xa6, 0x11, // LDX $11
+    0xe8, // INX
+    0x86, 0x11, // STX $11
+    0xb1, 0x20, // LDA ($20),Y
+    0x49, 0x5a, // EOR #$5a
+    0x99, 0x00, 0x02, // STA $0200,Y
+    0xc8, // INY
+    0x4c, 0x00, 0x06, // JMP $0600
+];
+
+fn bench_step(c: &mut Criterion) {
+    let mut cpu = CPU::new();
+    cpu.load(PROGRAM.to_vec());
+    cpu.reset();
+
+    let mut group = c.benchmark_group("cpu");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    group.bench_function("step", |b| {
+        b.iter(|| {
+            for _ in 0..INSTRUCTIONS {
+                black_box(cpu.step().unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_step);
+criterion_main!(benches);
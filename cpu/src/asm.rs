@@ -0,0 +1,854 @@
+//! Two-pass assembler.
+//!
+//! Accepts the usual 6502 syntax:
+//!
+//! ```text
+//! screen = $0200          ; constant
+//!         .org $0600
+//! start:  ldx #<screen    ; < and > take the low and high byte
+//! @loop:  sta screen,x    ; local label, scoped to the previous label
+//!         inx
+//!         bne @loop
+//!         jmp start
+//! table:  .byte 1, 2, "text"
+//!         .word start, table + 2
+//! ```
+//!
+//! Expressions take numbers in decimal, `$hex`, `%binary` or `'c'`, symbols,
+//! `*` for the current address, parentheses and the operators `+ - * / & | ^ << >>`.
+//!
+//! Instructions are encoded with [`CPU_OPS_CODES`], preferring the documented
+//! opcode when an undocumented one has the same mnemonic and mode. Operands
+//! known on the first pass to fit in a byte get the zero page form, so
+//! zero page symbols have to be defined before they are used. Code before
+//! the first `.org` goes to [`DEFAULT_ORIGIN`].
+
+use crate::opcode::{OpCode, CPU_OPS_CODES};
+use crate::{AddressingMode, DEFAULT_ORIGIN};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+
+/// An assembled program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    /// Address of the first byte
+    pub origin: u16,
+    /// Everything from the lowest to the highest address written, gaps left by `.org` are zeroes
+    pub bytes: Vec<u8>,
+    /// Labels and constants
+    pub symbols: HashMap<String, u16>,
+    /// Names in `symbols` that are labels, the others are `=` constants
+    pub labels: HashSet<String>,
+    /// Address of the first byte emitted by each source line that emits any,
+    /// by line number starting at 1
+    pub lines: BTreeMap<usize, u16>,
+}
+
+/// Why a source could not be assembled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// Line of the error, starting at 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+/// Assembles `source` into a program image.
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+    let lines = parse(source)?;
+
+    let mut assembler = Assembler {
+        symbols: HashMap::new(),
+        labels: HashSet::new(),
+        modes: HashMap::new(),
+        pc: DEFAULT_ORIGIN as u32,
+        memory: vec![0; 0x10000],
+        written: None,
+        lines: BTreeMap::new(),
+        final_pass: false,
+    };
+    assembler.pass(&lines)?;
+    assembler.final_pass = true;
+    assembler.pc = DEFAULT_ORIGIN as u32;
+    assembler.pass(&lines)?;
+
+    let (origin, bytes) = match assembler.written {
+        Some((start, end)) => (
+            start,
+            assembler.memory[start as usize..=end as usize].to_vec(),
+        ),
+        None => (DEFAULT_ORIGIN, Vec::new()),
+    };
+    let symbols = assembler
+        .symbols
+        .into_iter()
+        .map(|(name, value)| (name, value as u16))
+        .collect();
+    Ok(Program {
+        origin,
+        bytes,
+        symbols,
+        labels: assembler.labels,
+        lines: assembler.lines,
+    })
+}
+
+struct Line<'a> {
+    number: usize,
+    /// Label local labels are attached to
+    scope: String,
+    label: Option<String>,
+    statement: Statement<'a>,
+}
+
+enum Statement<'a> {
+    Empty,
+    Constant(String, &'a str),
+    Org(&'a str),
+    Byte(Vec<&'a str>),
+    Word(Vec<&'a str>),
+    Instruction(String, Operand),
+}
+
+/// Operand of an instruction, with the expressions it contains
+enum Operand {
+    None,
+    Accumulator,
+    Immediate(String),
+    Direct(String),
+    IndexedX(String),
+    IndexedY(String),
+    IndirectX(String),
+    IndirectY(String),
+    Indirect(String),
+}
+
+fn parse(source: &str) -> Result<Vec<Line<'_>>, AsmError> {
+    let mut lines = Vec::new();
+    let mut scope = String::new();
+
+    for (i, text) in source.lines().enumerate() {
+        let number = i + 1;
+        let error = |message: String| AsmError {
+            line: number,
+            message,
+        };
+        let mut rest = strip_comment(text).trim();
+
+        let mut label = None;
+        if let Some(colon) = rest.find(':') {
+            let name = rest[..colon].trim();
+            if is_identifier(name) {
+                if !name.starts_with('@') {
+                    scope = name.to_string();
+                }
+                label = Some(qualify(&scope, name));
+                rest = rest[colon + 1..].trim();
+            }
+        }
+
+        let (word, operand) = match rest.find(char::is_whitespace) {
+            Some(end) => (&rest[..end], rest[end..].trim()),
+            None => (rest, ""),
+        };
+
+        let statement = if rest.is_empty() {
+            Statement::Empty
+        } else if let Some(expr) = operand.strip_prefix('=') {
+            if !is_identifier(word) || word.starts_with('@') {
+                return Err(error(format!("invalid constant name {}", word)));
+            }
+            Statement::Constant(word.to_string(), expr.trim())
+        } else if word.starts_with('.') {
+            match word.to_ascii_lowercase().as_str() {
+                ".org" => Statement::Org(operand),
+                ".byte" | ".db" => Statement::Byte(split_list(operand)),
+                ".word" | ".dw" => Statement::Word(split_list(operand)),
+                _ => return Err(error(format!("unknown directive {}", word))),
+            }
+        } else {
+            let mnemonic = word.to_ascii_uppercase();
+            let operand = parse_operand(operand);
+            Statement::Instruction(mnemonic, operand)
+        };
+
+        lines.push(Line {
+            number,
+            scope: scope.clone(),
+            label,
+            statement,
+        });
+    }
+    Ok(lines)
+}
+
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (c, quote) {
+            ('"', None) | ('\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (';', None) => return &text[..i],
+            _ => {}
+        }
+    }
+    text
+}
+
+fn is_identifier(name: &str) -> bool {
+    let body = name.strip_prefix('@').unwrap_or(name);
+    let mut chars = body.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Local labels are stored under the name of their scope
+fn qualify(scope: &str, name: &str) -> String {
+    if name.starts_with('@') {
+        format!("{}{}", scope, name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Splits a `.byte` or `.word` list on the commas that are not quoted
+fn split_list(list: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in list.char_indices() {
+        match (c, quote) {
+            ('"', None) | ('\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (',', None) => {
+                items.push(list[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(list[start..].trim());
+    items
+}
+
+/// Whether the parenthesis opening `operand` is the one closing it, so
+/// `($10)` is indirect while `($10)+(2)` is a grouped expression
+fn enclosed(operand: &str) -> bool {
+    if !operand.starts_with('(') {
+        return false;
+    }
+    let mut depth = 0;
+    for (i, c) in operand.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return i == operand.len() - 1;
+        }
+    }
+    false
+}
+
+fn parse_operand(operand: &str) -> Operand {
+    let operand: String = operand.split_whitespace().collect();
+    let upper = operand.to_ascii_uppercase();
+    let inner = |suffix: usize| operand[1..operand.len() - suffix].to_string();
+
+    if operand.is_empty() {
+        Operand::None
+    } else if upper == "A" {
+        Operand::Accumulator
+    } else if let Some(expr) = operand.strip_prefix('#') {
+        Operand::Immediate(expr.to_string())
+    } else if operand.starts_with('(') && upper.ends_with(",X)") {
+        Operand::IndirectX(inner(3))
+    } else if operand.starts_with('(') && upper.ends_with("),Y") {
+        Operand::IndirectY(inner(3))
+    } else if enclosed(&operand) {
+        Operand::Indirect(inner(1))
+    } else if upper.ends_with(",X") {
+        Operand::IndexedX(operand[..operand.len() - 2].to_string())
+    } else if upper.ends_with(",Y") {
+        Operand::IndexedY(operand[..operand.len() - 2].to_string())
+    } else {
+        Operand::Direct(operand)
+    }
+}
+
+struct Assembler {
+    symbols: HashMap<String, i64>,
+    labels: HashSet<String>,
+    /// Mode picked for each instruction on the first pass, by line
+    modes: HashMap<usize, AddressingMode>,
+    pc: u32,
+    memory: Vec<u8>,
+    /// Lowest and highest address written
+    written: Option<(u16, u16)>,
+    lines: BTreeMap<usize, u16>,
+    /// Symbols must all be known on the final pass, which emits the bytes
+    final_pass: bool,
+}
+
+impl Assembler {
+    fn pass(&mut self, lines: &[Line]) -> Result<(), AsmError> {
+        for line in lines {
+            let pc = self.pc;
+            self.line(line).map_err(|message| AsmError {
+                line: line.number,
+                message,
+            })?;
+            if self.final_pass && self.pc != pc && !matches!(line.statement, Statement::Org(_)) {
+                self.lines.insert(line.number, pc as u16);
+            }
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, line: &Line) -> Result<(), String> {
+        if let Some(label) = &line.label {
+            self.define(label, self.pc as i64)?;
+            self.labels.insert(label.clone());
+        }
+
+        match &line.statement {
+            Statement::Empty => {}
+            Statement::Constant(name, expr) => {
+                if let Some(value) = self.eval(expr, &line.scope)? {
+                    self.define(name, value)?;
+                }
+            }
+            Statement::Org(expr) => {
+                let origin = self.eval(expr, &line.scope)?;
+                let origin = origin.ok_or(".org must not use symbols defined later")?;
+                self.pc = check_range(origin, 0, 0xffff)? as u32;
+            }
+            Statement::Byte(items) => {
+                for item in items {
+                    if item.len() >= 2 && item.starts_with('"') && item.ends_with('"') {
+                        for byte in item[1..item.len() - 1].bytes() {
+                            self.emit(byte)?;
+                        }
+                    } else {
+                        let value = self.eval(item, &line.scope)?;
+                        self.emit_value(value, 1)?;
+                    }
+                }
+            }
+            Statement::Word(items) => {
+                for item in items {
+                    let value = self.eval(item, &line.scope)?;
+                    self.emit_value(value, 2)?;
+                }
+            }
+            Statement::Instruction(mnemonic, operand) => {
+                self.instruction(line, mnemonic, operand)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, value: i64) -> Result<(), String> {
+        // every symbol is defined again on the final pass
+        if !self.final_pass && self.symbols.contains_key(name) {
+            return Err(format!("{} is already defined", name));
+        }
+        self.symbols.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    fn instruction(
+        &mut self,
+        line: &Line,
+        mnemonic: &str,
+        operand: &Operand,
+    ) -> Result<(), String> {
+        let expr = match operand {
+            Operand::None | Operand::Accumulator => None,
+            Operand::Immediate(expr)
+            | Operand::Direct(expr)
+            | Operand::IndexedX(expr)
+            | Operand::IndexedY(expr)
+            | Operand::IndirectX(expr)
+            | Operand::IndirectY(expr)
+            | Operand::Indirect(expr) => Some(expr),
+        };
+        let value = match expr {
+            Some(expr) => self.eval(expr, &line.scope)?,
+            None => None,
+        };
+
+        let mode = match self.modes.get(&line.number) {
+            Some(mode) => *mode,
+            None => {
+                let mode = select_mode(mnemonic, operand, value)?;
+                self.modes.insert(line.number, mode);
+                mode
+            }
+        };
+        let opcode = find(mnemonic, mode)
+            .ok_or_else(|| format!("{} does not support {:?} addressing", mnemonic, mode))?;
+
+        self.emit(opcode.code)?;
+        match mode {
+            AddressingMode::Implied | AddressingMode::Accumulator => Ok(()),
+            AddressingMode::Relative => {
+                let offset = value.map(|target| target - (self.pc as i64 + 1));
+                // checked on every pass, a backward offset is known on the first one
+                if let Some(offset) = offset {
+                    let excess = if offset < 0 {
+                        -128 - offset
+                    } else {
+                        offset - 127
+                    };
+                    if excess > 0 {
+                        return Err(format!("branch out of range by {} bytes", excess));
+                    }
+                }
+                self.emit_value(offset, 1)
+            }
+            AddressingMode::Immediate => self.emit_value(value, 1),
+            _ if opcode.len == 2 => {
+                let value = value.map(|value| check_range(value, 0, 0xff)).transpose()?;
+                self.emit_value(value, 1)
+            }
+            _ => {
+                let value = value
+                    .map(|value| check_range(value, 0, 0xffff))
+                    .transpose()?;
+                self.emit_value(value, 2)
+            }
+        }
+    }
+
+    fn emit(&mut self, byte: u8) -> Result<(), String> {
+        if self.pc > 0xffff {
+            return Err("program does not fit below $10000".to_string());
+        }
+        if self.final_pass {
+            let addr = self.pc as u16;
+            self.memory[addr as usize] = byte;
+            self.written = Some(match self.written {
+                Some((start, end)) => (start.min(addr), end.max(addr)),
+                None => (addr, addr),
+            });
+        }
+        self.pc += 1;
+        Ok(())
+    }
+
+    /// Emits a byte or a little endian word, `value` is only needed on the final pass
+    fn emit_value(&mut self, value: Option<i64>, size: u8) -> Result<(), String> {
+        let value = match (value, self.final_pass) {
+            (Some(value), _) if size == 1 => check_range(value, -0x80, 0xff)?,
+            (Some(value), _) => check_range(value, -0x8000, 0xffff)?,
+            (None, false) => 0,
+            (None, true) => unreachable!("undefined symbols are reported by eval"),
+        };
+        self.emit(value as u8)?;
+        if size == 2 {
+            self.emit((value >> 8) as u8)?;
+        }
+        Ok(())
+    }
+
+    /// Evaluates an expression, `None` when it uses a symbol that is not defined
+    /// yet on the first pass.
+    fn eval(&self, expr: &str, scope: &str) -> Result<Option<i64>, String> {
+        let mut parser = ExprParser {
+            chars: expr.chars().filter(|c| !c.is_whitespace()).collect(),
+            pos: 0,
+            assembler: self,
+            scope,
+        };
+        let value = parser.or()?;
+        if parser.pos != parser.chars.len() {
+            return Err(format!(
+                "unexpected {:?} in {}",
+                parser.chars[parser.pos], expr
+            ));
+        }
+        Ok(value)
+    }
+}
+
+fn check_range(value: i64, min: i64, max: i64) -> Result<i64, String> {
+    if value < min || value > max {
+        let sign = if value < 0 { "-" } else { "" };
+        return Err(format!(
+            "{}${:x} does not fit in the operand",
+            sign,
+            value.unsigned_abs()
+        ));
+    }
+    Ok(value)
+}
+
+/// Looks `mnemonic` up in the opcode table, documented opcodes first
+fn find(mnemonic: &str, mode: AddressingMode) -> Option<&'static OpCode> {
+    CPU_OPS_CODES
+        .iter()
+        .filter(|op| op.mnemonic == mnemonic && op.mode == mode)
+        .min_by_key(|op| op.undocumented)
+}
+
+fn select_mode(
+    mnemonic: &str,
+    operand: &Operand,
+    value: Option<i64>,
+) -> Result<AddressingMode, String> {
+    if !CPU_OPS_CODES.iter().any(|op| op.mnemonic == mnemonic) {
+        return Err(format!("unknown instruction {}", mnemonic));
+    }
+    let has = |mode| find(mnemonic, mode).is_some();
+    // a value not known yet may end up anywhere, it gets the absolute form when there is one
+    let zero_page_or = |zero_page, absolute| {
+        let fits = matches!(value, Some(value) if (0..=0xff).contains(&value));
+        if has(zero_page) && (fits || !has(absolute)) {
+            zero_page
+        } else {
+            absolute
+        }
+    };
+
+    let mode = match operand {
+        Operand::None if !has(AddressingMode::Implied) => AddressingMode::Accumulator,
+        Operand::None => AddressingMode::Implied,
+        Operand::Accumulator => AddressingMode::Accumulator,
+        Operand::Immediate(_) => AddressingMode::Immediate,
+        Operand::Direct(_) if has(AddressingMode::Relative) => AddressingMode::Relative,
+        Operand::Direct(_) => zero_page_or(AddressingMode::ZeroPage, AddressingMode::Absolute),
+        Operand::IndexedX(_) => {
+            zero_page_or(AddressingMode::ZeroPage_X, AddressingMode::Absolute_X)
+        }
+        Operand::IndexedY(_) => {
+            zero_page_or(AddressingMode::ZeroPage_Y, AddressingMode::Absolute_Y)
+        }
+        Operand::IndirectX(_) => AddressingMode::Indirect_X,
+        Operand::IndirectY(_) => AddressingMode::Indirect_Y,
+        Operand::Indirect(_) if has(AddressingMode::Indirect) => AddressingMode::Indirect,
+        // the 65C02 `(zp)` form, which the NMOS opcode table does not have
+        Operand::Indirect(_) => return Err("(zp) addressing is not supported".to_string()),
+    };
+    Ok(mode)
+}
+
+/// Recursive descent over an expression, lowest precedence first
+struct ExprParser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    assembler: &'a Assembler,
+    scope: &'a str,
+}
+
+type Value = Result<Option<i64>, String>;
+
+impl ExprParser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        let len = token.chars().count();
+        let matches = self.chars.len() >= self.pos + len
+            && self.chars[self.pos..self.pos + len]
+                .iter()
+                .copied()
+                .eq(token.chars());
+        if matches {
+            self.pos += len;
+        }
+        matches
+    }
+
+    fn binary<F>(&mut self, operators: &[&str], operand: F) -> Value
+    where
+        F: Fn(&mut Self) -> Value,
+    {
+        let mut left = operand(self)?;
+        'outer: loop {
+            for &operator in operators {
+                if self.eat(operator) {
+                    let right = operand(self)?;
+                    left = match (left, right) {
+                        (Some(l), Some(r)) => Some(apply(operator, l, r)?),
+                        _ => None,
+                    };
+                    continue 'outer;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn or(&mut self) -> Value {
+        self.binary(&["|"], Self::xor)
+    }
+
+    fn xor(&mut self) -> Value {
+        self.binary(&["^"], Self::and)
+    }
+
+    fn and(&mut self) -> Value {
+        self.binary(&["&"], Self::shift)
+    }
+
+    fn shift(&mut self) -> Value {
+        self.binary(&["<<", ">>"], Self::sum)
+    }
+
+    fn sum(&mut self) -> Value {
+        self.binary(&["+", "-"], Self::product)
+    }
+
+    fn product(&mut self) -> Value {
+        self.binary(&["*", "/"], Self::unary)
+    }
+
+    fn unary(&mut self) -> Value {
+        if self.eat("<") {
+            Ok(self.unary()?.map(|value| value & 0xff))
+        } else if self.eat(">") {
+            Ok(self.unary()?.map(|value| (value >> 8) & 0xff))
+        } else if self.eat("-") {
+            Ok(self.unary()?.map(|value| -value))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Value {
+        let c = self.peek().ok_or("missing operand")?;
+        if self.eat("(") {
+            let value = self.or()?;
+            if !self.eat(")") {
+                return Err("missing )".to_string());
+            }
+            Ok(value)
+        } else if self.eat("*") {
+            Ok(Some(self.assembler.pc as i64))
+        } else if self.eat("$") {
+            self.number(16)
+        } else if self.eat("%") {
+            self.number(2)
+        } else if c.is_ascii_digit() {
+            self.number(10)
+        } else if self.eat("'") {
+            let value = self.peek().ok_or("missing character")?;
+            self.pos += 1;
+            if !self.eat("'") {
+                return Err("missing '".to_string());
+            }
+            Ok(Some(value as i64))
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '@' {
+            let start = self.pos;
+            self.pos += 1;
+            while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '_') {
+                self.pos += 1;
+            }
+            let name: String = self.chars[start..self.pos].iter().collect();
+            let name = qualify(self.scope, &name);
+            match self.assembler.symbols.get(&name) {
+                Some(value) => Ok(Some(*value)),
+                None if self.assembler.final_pass => Err(format!("undefined symbol {}", name)),
+                None => Ok(None),
+            }
+        } else {
+            Err(format!("unexpected {:?}", c))
+        }
+    }
+
+    fn number(&mut self, radix: u32) -> Value {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_digit(radix)) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        i64::from_str_radix(&digits, radix)
+            .map(Some)
+            .map_err(|_| format!("invalid number {:?}", digits))
+    }
+}
+
+fn apply(operator: &str, left: i64, right: i64) -> Result<i64, String> {
+    Ok(match operator {
+        "|" => left | right,
+        "^" => left ^ right,
+        "&" => left & right,
+        "<<" => left << (right & 0x3f),
+        ">>" => left >> (right & 0x3f),
+        "+" => left + right,
+        "-" => left - right,
+        "*" => left * right,
+        _ if right == 0 => return Err("division by zero".to_string()),
+        _ => left / right,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[rustfmt::skip]
+    const SNAKE: [u8; 309] = [
+        0x20, 0x06, 0x06, 0x20, 0x38, 0x06, 0x20, 0x0d, 0x06, 0x20, 0x2a, 0x06, 0x60, 0xa9, 0x02, 0x85,
+        0x02, 0xa9, 0x04, 0x85, 0x03, 0xa9, 0x11, 0x85, 0x10, 0xa9, 0x10, 0x85, 0x12, 0xa9, 0x0f, 0x85,
+        0x14, 0xa9, 0x04, 0x85, 0x11, 0x85, 0x13, 0x85, 0x15, 0x60, 0xa5, 0xfe, 0x85, 0x00, 0xa5, 0xfe,
+        0x29, 0x03, 0x18, 0x69, 0x02, 0x85, 0x01, 0x60, 0x20, 0x4d, 0x06, 0x20, 0x8d, 0x06, 0x20, 0xc3,
+        0x06, 0x20, 0x19, 0x07, 0x20, 0x20, 0x07, 0x20, 0x2d, 0x07, 0x4c, 0x38, 0x06, 0xa5, 0xff, 0xc9,
+        0x77, 0xf0, 0x0d, 0xc9, 0x64, 0xf0, 0x14, 0xc9, 0x73, 0xf0, 0x1b, 0xc9, 0x61, 0xf0, 0x22, 0x60,
+        0xa9, 0x04, 0x24, 0x02, 0xd0, 0x26, 0xa9, 0x01, 0x85, 0x02, 0x60, 0xa9, 0x08, 0x24, 0x02, 0xd0,
+        0x1b, 0xa9, 0x02, 0x85, 0x02, 0x60, 0xa9, 0x01, 0x24, 0x02, 0xd0, 0x10, 0xa9, 0x04, 0x85, 0x02,
+        0x60, 0xa9, 0x02, 0x24, 0x02, 0xd0, 0x05, 0xa9, 0x08, 0x85, 0x02, 0x60, 0x60, 0x20, 0x94, 0x06,
+        0x20, 0xa8, 0x06, 0x60, 0xa5, 0x00, 0xc5, 0x10, 0xd0, 0x0d, 0xa5, 0x01, 0xc5, 0x11, 0xd0, 0x07,
+        0xe6, 0x03, 0xe6, 0x03, 0x20, 0x2a, 0x06, 0x60, 0xa2, 0x02, 0xb5, 0x10, 0xc5, 0x10, 0xd0, 0x06,
+        0xb5, 0x11, 0xc5, 0x11, 0xf0, 0x09, 0xe8, 0xe8, 0xe4, 0x03, 0xf0, 0x06, 0x4c, 0xaa, 0x06, 0x4c,
+        0x35, 0x07, 0x60, 0xa6, 0x03, 0xca, 0x8a, 0xb5, 0x10, 0x95, 0x12, 0xca, 0x10, 0xf9, 0xa5, 0x02,
+        0x4a, 0xb0, 0x09, 0x4a, 0xb0, 0x19, 0x4a, 0xb0, 0x1f, 0x4a, 0xb0, 0x2f, 0xa5, 0x10, 0x38, 0xe9,
+        0x20, 0x85, 0x10, 0x90, 0x01, 0x60, 0xc6, 0x11, 0xa9, 0x01, 0xc5, 0x11, 0xf0, 0x28, 0x60, 0xe6,
+        0x10, 0xa9, 0x1f, 0x24, 0x10, 0xf0, 0x1f, 0x60, 0xa5, 0x10, 0x18, 0x69, 0x20, 0x85, 0x10, 0xb0,
+        0x01, 0x60, 0xe6, 0x11, 0xa9, 0x06, 0xc5, 0x11, 0xf0, 0x0c, 0x60, 0xc6, 0x10, 0xa5, 0x10, 0x29,
+        0x1f, 0xc9, 0x1f, 0xf0, 0x01, 0x60, 0x4c, 0x35, 0x07, 0xa0, 0x00, 0xa5, 0xfe, 0x91, 0x00, 0x60,
+        0xa6, 0x03, 0xa9, 0x00, 0x81, 0x10, 0xa2, 0x00, 0xa9, 0x01, 0x81, 0x10, 0x60, 0xa6, 0xff, 0xea,
+        0xea, 0xca, 0xd0, 0xfb, 0x60,
+    ];
+
+    #[test]
+    fn test_assemble_snake() {
+        let program = assemble(include_str!("../tests/fixtures/snake.asm")).unwrap();
+        assert_eq!(program.origin, 0x0600);
+        assert_eq!(program.bytes, SNAKE.to_vec());
+        assert_eq!(program.symbols["loop"], 0x0638);
+        assert_eq!(program.symbols["readKeys@upKey"], 0x0660);
+        assert_eq!(program.symbols["gameOver"], 0x0735);
+        assert!(program.labels.contains("gameOver"));
+        assert!(!program.labels.contains("appleL"));
+        assert_eq!(program.lines.values().next(), Some(&0x0600));
+        assert_eq!(program.lines.values().last(), Some(&0x0734));
+    }
+
+    #[test]
+    fn test_expressions_and_directives() {
+        let source = "
+            table = $1234
+                    .org $c000
+            start:  lda #<table         ; low byte
+                    ldx #>table
+                    lda table + 1,y
+                    lda (start - $c000 + 2),y
+                    lda #'A' | %100000
+                    .byte 1, -1, \"ab;\", (2 + 3) * 2
+                    .word start, *
+            ";
+        let program = assemble(source).unwrap();
+        assert_eq!(program.origin, 0xc000);
+        assert_eq!(
+            program.bytes,
+            vec![
+                0xa9, 0x34, 0xa2, 0x12, 0xb9, 0x35, 0x12, 0xb1, 0x02, 0xa9, 0x61, 0x01, 0xff, b'a',
+                b'b', b';', 0x0a, 0x00, 0xc0, 0x13, 0xc0,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_zero_page_and_forward_references() {
+        let source = "
+            zp = $10
+                    lda zp
+                    lda zp,x
+                    ldx zp,y
+                    lda later
+                    jmp (later)
+                    bne @back
+            @back:  rol a
+                    rol
+            later:
+            ";
+        let program = assemble(source).unwrap();
+        assert_eq!(
+            program.bytes,
+            vec![
+                0xa5, 0x10, 0xb5, 0x10, 0xb6, 0x10, 0xad, 0x10, 0x06, 0x6c, 0x10, 0x06, 0xd0, 0x00,
+                0x2a, 0x2a,
+            ]
+        );
+        let lines: Vec<_> = program.lines.into_iter().collect();
+        assert_eq!(
+            lines,
+            vec![
+                (3, 0x0600),
+                (4, 0x0602),
+                (5, 0x0604),
+                (6, 0x0606),
+                (7, 0x0609),
+                (8, 0x060c),
+                (9, 0x060e),
+                (10, 0x060f)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_indirect_operands() {
+        let bytes = |source| assemble(source).unwrap().bytes;
+        assert_eq!(bytes("jmp ($1234)"), vec![0x6c, 0x34, 0x12]);
+        // parentheses that only group part of the operand
+        assert_eq!(bytes("lda ($10)+(1)"), vec![0xa5, 0x11]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source| assemble(source).unwrap_err();
+        assert_eq!(
+            error("nop\n  lda missing"),
+            AsmError {
+                line: 2,
+                message: "undefined symbol missing".to_string()
+            }
+        );
+        assert_eq!(error("foo").message, "unknown instruction FOO");
+        assert_eq!(
+            error("inx #1").message,
+            "INX does not support Immediate addressing"
+        );
+        assert_eq!(error("a: nop\na: nop").message, "a is already defined");
+        assert_eq!(
+            error("lda #$100").message,
+            "$100 does not fit in the operand"
+        );
+        assert_eq!(
+            error("  bne far\n  .org $0700\nfar:").message,
+            "branch out of range by 127 bytes"
+        );
+        assert_eq!(
+            error("  .org $0600\nback:\n  .org $0700\n  bne back").message,
+            "branch out of range by 130 bytes"
+        );
+        assert_eq!(
+            error("  bne $0600\n  .org $0700\n  bne $0600").message,
+            "branch out of range by 130 bytes"
+        );
+        assert_eq!(
+            error("lda #-$81").message,
+            "-$81 does not fit in the operand"
+        );
+        assert_eq!(
+            error("lda ($10)").message,
+            "(zp) addressing is not supported"
+        );
+        assert_eq!(
+            error("  .align 2").to_string(),
+            "line 1: unknown directive .align"
+        );
+    }
+}
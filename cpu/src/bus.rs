@@ -0,0 +1,223 @@
+use crate::state::{self, StateReader, StateWriter};
+use crate::Mem;
+use std::io;
+use std::ops::RangeInclusive;
+
+/// Flat 64KiB of read/write memory covering the whole address space.
+pub struct Ram {
+    memory: Box<[u8]>,
+}
+
+impl Ram {
+    pub fn new() -> Self {
+        Ram {
+            memory: vec![0; 0x10000].into_boxed_slice(),
+        }
+    }
+}
+
+impl Default for Ram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mem for Ram {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+    }
+
+    fn mem_peek(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn save_sections(&self, state: &mut StateWriter) -> io::Result<()> {
+        state.section(*b"RAM ", &state::compress(&self.memory))
+    }
+
+    fn check_sections(&self, state: &mut StateReader) -> io::Result<()> {
+        let memory = ram_section(state)?;
+        if memory.len() != self.memory.len() {
+            return Err(state::invalid("bad RAM section"));
+        }
+        Ok(())
+    }
+
+    fn load_sections(&mut self, state: &mut StateReader) -> io::Result<()> {
+        let memory = ram_section(state)?;
+        self.memory.copy_from_slice(&memory);
+        Ok(())
+    }
+}
+
+/// The RAM section, stored raw before version 2
+fn ram_section(state: &mut StateReader) -> io::Result<Vec<u8>> {
+    let payload = state.section(*b"RAM ")?;
+    if state.version() < 2 {
+        Ok(payload)
+    } else {
+        state::decompress(&payload)
+    }
+}
+
+/// Read-only memory. Writes are silently ignored, as on real hardware.
+/// Addresses are relative to wherever the ROM is mapped and mirror
+/// when the mapped window is larger than the image.
+pub struct Rom {
+    data: Vec<u8>,
+}
+
+impl Rom {
+    pub fn new(data: Vec<u8>) -> Self {
+        assert!(!data.is_empty(), "ROM image can not be empty");
+        Rom { data }
+    }
+}
+
+impl Mem for Rom {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.mem_peek(addr)
+    }
+
+    fn mem_write(&mut self, _addr: u16, _data: u8) {}
+
+    fn mem_peek(&self, addr: u16) -> u8 {
+        self.data[addr as usize % self.data.len()]
+    }
+}
+
+/// A bus that routes accesses to devices mapped on address ranges, falling
+/// back to RAM for everything that is not mapped.
+///
+/// Devices see addresses relative to the start of their range, so the same
+/// device can be mapped anywhere. When ranges overlap the device mapped last wins.
+pub struct MappedBus {
+    ram: Ram,
+    devices: Vec<(RangeInclusive<u16>, Box<dyn Mem>)>,
+}
+
+impl MappedBus {
+    pub fn new() -> Self {
+        MappedBus {
+            ram: Ram::new(),
+            devices: Vec::new(),
+        }
+    }
+
+    pub fn map<D: Mem + 'static>(&mut self, range: RangeInclusive<u16>, device: D) {
+        self.devices.push((range, Box::new(device)));
+    }
+
+    fn device_index(&self, addr: u16) -> Option<usize> {
+        self.devices
+            .iter()
+            .rposition(|(range, _)| range.contains(&addr))
+    }
+}
+
+impl Default for MappedBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mem for MappedBus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        match self.device_index(addr) {
+            Some(idx) => {
+                let (range, device) = &mut self.devices[idx];
+                device.mem_read(addr - range.start())
+            }
+            None => self.ram.mem_read(addr),
+        }
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        match self.device_index(addr) {
+            Some(idx) => {
+                let (range, device) = &mut self.devices[idx];
+                device.mem_write(addr - range.start(), data)
+            }
+            None => self.ram.mem_write(addr, data),
+        }
+    }
+
+    fn mem_peek(&self, addr: u16) -> u8 {
+        match self.device_index(addr) {
+            Some(idx) => {
+                let (range, device) = &self.devices[idx];
+                device.mem_peek(addr - range.start())
+            }
+            None => self.ram.mem_peek(addr),
+        }
+    }
+
+    fn save_sections(&self, state: &mut StateWriter) -> io::Result<()> {
+        self.ram.save_sections(state)?;
+        for (_, device) in &self.devices {
+            device.save_sections(state)?;
+        }
+        Ok(())
+    }
+
+    fn check_sections(&self, state: &mut StateReader) -> io::Result<()> {
+        self.ram.check_sections(state)?;
+        for (_, device) in &self.devices {
+            device.check_sections(state)?;
+        }
+        Ok(())
+    }
+
+    fn load_sections(&mut self, state: &mut StateReader) -> io::Result<()> {
+        self.ram.load_sections(state)?;
+        for (_, device) in &mut self.devices {
+            device.load_sections(state)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Counter {
+        reads: u8,
+    }
+
+    impl Mem for Counter {
+        fn mem_read(&mut self, _addr: u16) -> u8 {
+            self.reads += 1;
+            self.reads
+        }
+
+        fn mem_write(&mut self, _addr: u16, data: u8) {
+            self.reads = data;
+        }
+
+        fn mem_peek(&self, _addr: u16) -> u8 {
+            self.reads
+        }
+    }
+
+    #[test]
+    fn test_mapped_bus_routes_to_devices() {
+        let mut bus = MappedBus::new();
+        bus.map(0x00fe..=0x00fe, Counter { reads: 0 });
+        bus.map(0x8000..=0xffff, Rom::new(vec![0xea; 0x4000]));
+
+        assert_eq!(bus.mem_read(0xfe), 1);
+        assert_eq!(bus.mem_read(0xfe), 2);
+        assert_eq!(bus.mem_peek(0xfe), 2);
+
+        bus.mem_write(0x10, 0x42);
+        assert_eq!(bus.mem_read(0x10), 0x42);
+
+        bus.mem_write(0xc000, 0x42);
+        assert_eq!(bus.mem_read(0xc000), 0xea);
+    }
+}
@@ -0,0 +1,744 @@
+//! Cycle stepped execution.
+//!
+//! [`CPU::clock`] advances the CPU by a single clock cycle and performs the
+//! bus access the NMOS 6502 makes on that cycle, including the dummy reads of
+//! indexed addressing and the double write of read-modify-write instructions.
+//! Devices that watch the bus (PPU registers, mappers, acknowledge on read)
+//! see exactly what the real chip would show them.
+
+use crate::opcode::OpCode;
+use crate::{
+    AddressingMode, CpuError, CpuFlags, Handler, Interrupt, Mem, Operation, StepOutcome, CPU,
+};
+use crate::{IRQ_VECTOR, STACK};
+
+/// Instruction being executed by [`CPU::clock`], kept between two cycles.
+#[derive(Clone, Copy)]
+pub(crate) struct InFlight {
+    /// Interrupt serviced before the instruction, reported in the outcome
+    interrupt: Option<Interrupt>,
+    /// Interrupt whose sequence is still running
+    servicing: Option<Interrupt>,
+    /// Cycle of the current sequence, the opcode fetch is cycle 1
+    cycle: u8,
+    cycles_before: u64,
+    stack_before: u8,
+    pc_before: u16,
+    code: u8,
+    /// `None` when the instruction was executed at once on its first cycle
+    opcode: Option<&'static OpCode>,
+    /// Cycles left to idle after an instruction executed at once
+    remaining: u8,
+    /// Cycle on which the effective address became known
+    ready: u8,
+    pointer: u8,
+    base: u16,
+    addr: u16,
+    data: u8,
+    page_crossed: bool,
+}
+
+impl<B: Mem> CPU<B> {
+    /// Advances the CPU by one clock cycle.
+    ///
+    /// Returns the outcome of the instruction once its last cycle has run and
+    /// `None` while it is still in flight. An interrupt is serviced before the
+    /// instruction that follows it and reported together with that instruction,
+    /// like [`CPU::step`] does.
+    ///
+    /// The 65C02 is not modelled cycle by cycle: its instructions perform all
+    /// their bus accesses on the first cycle and idle for the remaining ones.
+    pub fn clock(&mut self) -> Result<Option<StepOutcome>, CpuError> {
+        if self.in_flight.is_none() && self.waiting {
+            return Ok(Some(self.wait_cycle()));
+        }
+        let mut state = match self.in_flight.take() {
+            Some(state) => state,
+            None => self.begin_instruction(),
+        };
+        if state.servicing.is_none() && state.cycle == 0 {
+            if let Err(error) = self.trace_instruction() {
+                self.in_flight = Some(state);
+                return Err(error);
+            }
+        }
+
+        self.tick(1);
+        state.cycle += 1;
+
+        let done = match state.servicing {
+            Some(interrupt) => {
+                self.interrupt_cycle(&mut state, interrupt);
+                false
+            }
+            None => self.instruction_cycle(&mut state)?,
+        };
+
+        if !done {
+            self.in_flight = Some(state);
+            return Ok(None);
+        }
+
+        let outcome = StepOutcome {
+            interrupt: state.interrupt,
+            opcode: state.code,
+            cycles: (self.cycles - state.cycles_before) as u8,
+            pc_before: state.pc_before,
+            pc_after: self.program_counter,
+        };
+        self.profile(&outcome, state.stack_before);
+        Ok(Some(outcome))
+    }
+
+    fn begin_instruction(&mut self) -> InFlight {
+        let interrupt = self.pending_interrupt();
+        if interrupt == Some(Interrupt::Nmi) {
+            self.nmi_pending = false;
+        }
+
+        InFlight {
+            interrupt,
+            servicing: interrupt,
+            cycle: 0,
+            cycles_before: self.cycles,
+            stack_before: self.stack_pointer,
+            pc_before: self.program_counter,
+            code: 0,
+            opcode: None,
+            remaining: 0,
+            ready: 0,
+            pointer: 0,
+            base: 0,
+            addr: 0,
+            data: 0,
+            page_crossed: false,
+        }
+    }
+
+    /// Hardware interrupt sequence, the first two cycles read the opcode that
+    /// gets discarded.
+    fn interrupt_cycle(&mut self, state: &mut InFlight, interrupt: Interrupt) {
+        match state.cycle {
+            1 | 2 => {
+                self.mem_read(self.program_counter);
+            }
+            _ => {
+                if self.push_and_vector_cycle(state, interrupt.vector(), false) {
+                    state.servicing = None;
+                    state.cycle = 0;
+                }
+            }
+        }
+    }
+
+    /// Cycles 3 to 7 shared by hardware interrupts and BRK: push the return
+    /// address and the status, then load the vector. Returns `true` on the last cycle.
+    fn push_and_vector_cycle(
+        &mut self,
+        state: &mut InFlight,
+        vector: u16,
+        break_flag: bool,
+    ) -> bool {
+        match state.cycle {
+            3 => self.stack_push((self.program_counter >> 8) as u8),
+            4 => self.stack_push(self.program_counter as u8),
+            5 => self.push_status_and_mask(break_flag),
+            6 => state.data = self.mem_read(vector),
+            _ => {
+                let hi = self.mem_read(vector.wrapping_add(1));
+                self.program_counter = (hi as u16) << 8 | state.data as u16;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Runs one cycle of the instruction, returns `true` on its last cycle.
+    fn instruction_cycle(&mut self, state: &mut InFlight) -> Result<bool, CpuError> {
+        if state.cycle == 1 {
+            state.pc_before = self.program_counter;
+            state.code = self.mem_read(self.program_counter);
+            self.program_counter = self.program_counter.wrapping_add(1);
+
+            state.opcode = self.variant.decode(state.code);
+            if self.variant.is_cmos() || state.opcode.is_none() {
+                state.opcode = None;
+                return self.execute_at_once(state);
+            }
+            return Ok(false);
+        }
+
+        let opcode = match state.opcode {
+            Some(opcode) => opcode,
+            None => {
+                state.remaining -= 1;
+                return Ok(state.remaining == 0);
+            }
+        };
+
+        // errors go through the policy like they do in `step`
+        self.operation_cycle(state, opcode).or_else(|error| {
+            self.handle_error(error, state.code, state.pc_before)?;
+            Ok(true)
+        })
+    }
+
+    fn operation_cycle(
+        &mut self,
+        state: &mut InFlight,
+        opcode: &'static OpCode,
+    ) -> Result<bool, CpuError> {
+        match self.operation(state.code) {
+            Operation::Read(op) => {
+                if opcode.mode == AddressingMode::Immediate {
+                    let data = self.mem_read(self.program_counter);
+                    self.program_counter = self.program_counter.wrapping_add(1);
+                    op(self, data);
+                    return Ok(true);
+                }
+                if !self.address_cycle(state, opcode.mode, true)? {
+                    return Ok(false);
+                }
+                let data = self.mem_read(state.addr);
+                op(self, data);
+                Ok(true)
+            }
+            Operation::Write(op) => {
+                if !self.address_cycle(state, opcode.mode, false)? {
+                    return Ok(false);
+                }
+                let data = op(self);
+                self.mem_write(state.addr, data);
+                Ok(true)
+            }
+            Operation::WriteHigh(op) => {
+                if !self.address_cycle(state, opcode.mode, false)? {
+                    return Ok(false);
+                }
+                let value = op(self);
+                self.store_and_high(&opcode.mode, state.addr, state.page_crossed, value);
+                Ok(true)
+            }
+            Operation::Modify(op) if opcode.mode == AddressingMode::Accumulator => {
+                self.mem_read(self.program_counter);
+                let data = self.register_a;
+                self.register_a = op(self, data);
+                Ok(true)
+            }
+            Operation::Modify(op) => {
+                if !self.address_cycle(state, opcode.mode, false)? {
+                    return Ok(false);
+                }
+                // the unmodified value is written back while the ALU works
+                match state.cycle - state.ready {
+                    1 => {
+                        state.data = self.mem_read(state.addr);
+                        Ok(false)
+                    }
+                    2 => {
+                        self.mem_write(state.addr, state.data);
+                        state.data = op(self, state.data);
+                        Ok(false)
+                    }
+                    _ => {
+                        self.mem_write(state.addr, state.data);
+                        Ok(true)
+                    }
+                }
+            }
+            Operation::Custom(handler) => self.custom_cycle(state, opcode, handler),
+        }
+    }
+
+    /// Executes the whole instruction on its first cycle, the remaining cycles idle.
+    fn execute_at_once(&mut self, state: &mut InFlight) -> Result<bool, CpuError> {
+        let cycles = self.cycles;
+        self.execute(state.code, state.pc_before)?;
+        // `execute` charges the opcode fetch, which has already been ticked
+        state.remaining = (self.cycles - cycles).saturating_sub(1) as u8;
+        self.cycles = cycles;
+        Ok(state.remaining == 0)
+    }
+
+    /// Runs the addressing mode cycles. Returns `true` once the effective
+    /// address is known and the operand access happens on the current cycle.
+    ///
+    /// Indexed modes first access the address with the uncorrected high byte.
+    /// Reads that did not cross a page use that access, any other instruction
+    /// makes it a dummy read and accesses the corrected address on the next cycle.
+    fn address_cycle(
+        &mut self,
+        state: &mut InFlight,
+        mode: AddressingMode,
+        read: bool,
+    ) -> Result<bool, CpuError> {
+        if state.ready != 0 {
+            return Ok(state.cycle > state.ready);
+        }
+
+        let ready = match (mode, state.cycle) {
+            (AddressingMode::ZeroPage, _) => {
+                state.addr = self.fetch_operand() as u16;
+                true
+            }
+
+            (AddressingMode::Absolute, 2)
+            | (AddressingMode::Absolute_X, 2)
+            | (AddressingMode::Absolute_Y, 2) => {
+                state.base = self.fetch_operand() as u16;
+                false
+            }
+            (AddressingMode::Absolute, _) => {
+                state.addr = (self.fetch_operand() as u16) << 8 | state.base;
+                true
+            }
+            (AddressingMode::Absolute_X, 3) | (AddressingMode::Absolute_Y, 3) => {
+                state.base |= (self.fetch_operand() as u16) << 8;
+                let index = if mode == AddressingMode::Absolute_X {
+                    self.register_x
+                } else {
+                    self.register_y
+                };
+                self.index_address(state, index, read)
+            }
+
+            (AddressingMode::ZeroPage_X, 2)
+            | (AddressingMode::ZeroPage_Y, 2)
+            | (AddressingMode::Indirect_X, 2) => {
+                state.pointer = self.fetch_operand();
+                false
+            }
+            (AddressingMode::ZeroPage_X, _) | (AddressingMode::ZeroPage_Y, _) => {
+                self.mem_read(state.pointer as u16);
+                let index = if mode == AddressingMode::ZeroPage_X {
+                    self.register_x
+                } else {
+                    self.register_y
+                };
+                state.addr = state.pointer.wrapping_add(index) as u16;
+                true
+            }
+
+            (AddressingMode::Indirect_X, 3) => {
+                self.mem_read(state.pointer as u16);
+                state.pointer = state.pointer.wrapping_add(self.register_x);
+                false
+            }
+            (AddressingMode::Indirect_X, 4) => {
+                state.base = self.mem_read(state.pointer as u16) as u16;
+                false
+            }
+            (AddressingMode::Indirect_X, _) => {
+                let hi = self.mem_read(state.pointer.wrapping_add(1) as u16);
+                state.addr = (hi as u16) << 8 | state.base;
+                true
+            }
+
+            (AddressingMode::Indirect_Y, 2) => {
+                state.pointer = self.fetch_operand();
+                false
+            }
+            (AddressingMode::Indirect_Y, 3) => {
+                state.base = self.mem_read(state.pointer as u16) as u16;
+                false
+            }
+            (AddressingMode::Indirect_Y, 4) => {
+                let hi = self.mem_read(state.pointer.wrapping_add(1) as u16);
+                state.base |= (hi as u16) << 8;
+                self.index_address(state, self.register_y, read)
+            }
+
+            // the fixup cycle of an indexed mode
+            (AddressingMode::Absolute_X, _)
+            | (AddressingMode::Absolute_Y, _)
+            | (AddressingMode::Indirect_Y, _) => {
+                self.mem_read(Self::uncorrected(state));
+                true
+            }
+
+            _ => {
+                return Err(CpuError::InvalidAddressingMode {
+                    mode,
+                    address: state.pc_before,
+                })
+            }
+        };
+
+        if ready {
+            state.ready = state.cycle;
+        }
+        Ok(false)
+    }
+
+    /// Adds the index to the base address. Returns `true` when no fixup cycle is needed.
+    fn index_address(&mut self, state: &mut InFlight, index: u8, read: bool) -> bool {
+        state.addr = state.base.wrapping_add(index as u16);
+        state.page_crossed = Self::page_crossed(state.base, state.addr);
+        read && !state.page_crossed
+    }
+
+    /// Address accessed before the high byte is fixed by the carry of the index
+    fn uncorrected(state: &InFlight) -> u16 {
+        (state.base & 0xff00) | (state.addr & 0x00ff)
+    }
+
+    fn fetch_operand(&mut self) -> u8 {
+        let data = self.mem_read(self.program_counter);
+        self.program_counter = self.program_counter.wrapping_add(1);
+        data
+    }
+
+    /// Instructions that do not access an operand through an addressing mode.
+    fn custom_cycle(
+        &mut self,
+        state: &mut InFlight,
+        opcode: &'static OpCode,
+        handler: Handler<B>,
+    ) -> Result<bool, CpuError> {
+        let done = match (state.code, state.cycle) {
+            /* BRK, the byte after the opcode is padding */
+            (0x00, 2) => {
+                self.fetch_operand();
+                false
+            }
+            (0x00, _) => self.push_and_vector_cycle(state, IRQ_VECTOR, true),
+
+            /* JSR */
+            (0x20, 2) => {
+                state.data = self.fetch_operand();
+                false
+            }
+            (0x20, 3) => {
+                self.mem_read(STACK + self.stack_pointer as u16);
+                false
+            }
+            (0x20, 4) => {
+                self.stack_push((self.program_counter >> 8) as u8);
+                false
+            }
+            (0x20, 5) => {
+                self.stack_push(self.program_counter as u8);
+                false
+            }
+            (0x20, _) => {
+                let hi = self.mem_read(self.program_counter);
+                self.program_counter = (hi as u16) << 8 | state.data as u16;
+                true
+            }
+
+            /* RTS */
+            (0x60, 2) => {
+                self.mem_read(self.program_counter);
+                false
+            }
+            (0x60, 3) => {
+                self.mem_read(STACK + self.stack_pointer as u16);
+                false
+            }
+            (0x60, 4) => {
+                state.data = self.stack_pop();
+                false
+            }
+            (0x60, 5) => {
+                let hi = self.stack_pop();
+                self.program_counter = (hi as u16) << 8 | state.data as u16;
+                false
+            }
+            (0x60, _) => {
+                self.fetch_operand();
+                true
+            }
+
+            /* RTI */
+            (0x40, 2) => {
+                self.mem_read(self.program_counter);
+                false
+            }
+            (0x40, 3) => {
+                self.mem_read(STACK + self.stack_pointer as u16);
+                false
+            }
+            (0x40, 4) => {
+                self.plp();
+                false
+            }
+            (0x40, 5) => {
+                state.data = self.stack_pop();
+                false
+            }
+            (0x40, _) => {
+                let hi = self.stack_pop();
+                self.program_counter = (hi as u16) << 8 | state.data as u16;
+                true
+            }
+
+            /* JMP Absolute */
+            (0x4c, 2) => {
+                state.data = self.fetch_operand();
+                false
+            }
+            (0x4c, _) => {
+                let hi = self.mem_read(self.program_counter);
+                self.program_counter = (hi as u16) << 8 | state.data as u16;
+                true
+            }
+
+            /* JMP Indirect */
+            (0x6c, 2) => {
+                state.base = self.fetch_operand() as u16;
+                false
+            }
+            (0x6c, 3) => {
+                state.base |= (self.fetch_operand() as u16) << 8;
+                false
+            }
+            (0x6c, 4) => {
+                state.data = self.mem_read(state.base);
+                false
+            }
+            (0x6c, _) => {
+                // the high byte is read from the same page on the NMOS chip
+                let hi_address = if self.variant.has_jmp_indirect_bug() {
+                    (state.base & 0xff00) | (state.base.wrapping_add(1) & 0x00ff)
+                } else {
+                    state.base.wrapping_add(1)
+                };
+                let hi = self.mem_read(hi_address);
+                self.program_counter = (hi as u16) << 8 | state.data as u16;
+                true
+            }
+
+            /* Branches */
+            (code, 2) if opcode.mode == AddressingMode::Relative => {
+                state.data = self.fetch_operand();
+                let flag = match code >> 6 {
+                    0 => CpuFlags::NEGATIV,
+                    1 => CpuFlags::OVERFLOW,
+                    2 => CpuFlags::CARRY,
+                    _ => CpuFlags::ZERO,
+                };
+                let taken = self.status.contains(flag) == (code & 0x20 != 0);
+                !taken
+            }
+            (_, 3) if opcode.mode == AddressingMode::Relative => {
+                self.mem_read(self.program_counter);
+                state.addr = self.program_counter.wrapping_add(state.data as i8 as u16);
+                if Self::page_crossed(self.program_counter, state.addr) {
+                    state.base = self.program_counter;
+                    false
+                } else {
+                    self.program_counter = state.addr;
+                    true
+                }
+            }
+            _ if opcode.mode == AddressingMode::Relative => {
+                self.mem_read(Self::uncorrected(state));
+                self.program_counter = state.addr;
+                true
+            }
+
+            /* PHA, PHP, PLA, PLP */
+            (0x48, 2) | (0x08, 2) | (0x68, 2) | (0x28, 2) => {
+                self.mem_read(self.program_counter);
+                false
+            }
+            (0x68, 3) | (0x28, 3) => {
+                self.mem_read(STACK + self.stack_pointer as u16);
+                false
+            }
+            (0x48, _) | (0x08, _) | (0x68, _) | (0x28, _) => {
+                handler(self, opcode)?;
+                true
+            }
+
+            /* Implied instructions read the byte after the opcode and discard it */
+            _ => {
+                self.mem_read(self.program_counter);
+                handler(self, opcode)?;
+                true
+            }
+        };
+        Ok(done)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Ram;
+
+    #[derive(Debug, PartialEq)]
+    enum Access {
+        Read(u16, u8),
+        Write(u16, u8),
+    }
+
+    /// Ram that logs every bus access
+    struct Recorder {
+        ram: Ram,
+        log: Vec<Access>,
+    }
+
+    impl Mem for Recorder {
+        fn mem_read(&mut self, addr: u16) -> u8 {
+            let data = self.ram.mem_read(addr);
+            self.log.push(Access::Read(addr, data));
+            data
+        }
+
+        fn mem_write(&mut self, addr: u16, data: u8) {
+            self.log.push(Access::Write(addr, data));
+            self.ram.mem_write(addr, data);
+        }
+
+        fn mem_peek(&self, addr: u16) -> u8 {
+            self.ram.mem_peek(addr)
+        }
+    }
+
+    fn recording_cpu(program: &[u8]) -> CPU<Recorder> {
+        let mut cpu = CPU::with_bus(Recorder {
+            ram: Ram::new(),
+            log: Vec::new(),
+        });
+        for (i, byte) in program.iter().enumerate() {
+            cpu.mem_write(0x0600 + i as u16, *byte);
+        }
+        cpu.mem_write_u16(0xfffc, 0x0600);
+        cpu.reset();
+        cpu.bus.log.clear();
+        cpu
+    }
+
+    fn clock_instruction<B: Mem>(cpu: &mut CPU<B>) -> StepOutcome {
+        loop {
+            if let Some(outcome) = cpu.clock().unwrap() {
+                return outcome;
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_modify_write_writes_twice() {
+        let mut cpu = recording_cpu(&[0xe6, 0x10]);
+        cpu.mem_write(0x10, 0x41);
+        cpu.bus.log.clear();
+
+        for _ in 0..4 {
+            assert_eq!(cpu.clock().unwrap(), None);
+        }
+        let outcome = cpu.clock().unwrap().unwrap();
+        assert_eq!(outcome.cycles, 5);
+        assert_eq!(
+            cpu.bus.log,
+            vec![
+                Access::Read(0x0600, 0xe6),
+                Access::Read(0x0601, 0x10),
+                Access::Read(0x0010, 0x41),
+                Access::Write(0x0010, 0x41),
+                Access::Write(0x0010, 0x42),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_indexed_dummy_reads() {
+        // LDA $02ff,X crossing into the next page
+        let mut cpu = recording_cpu(&[0xbd, 0xff, 0x02]);
+        cpu.register_x = 1;
+        assert_eq!(clock_instruction(&mut cpu).cycles, 5);
+        assert_eq!(
+            cpu.bus.log,
+            vec![
+                Access::Read(0x0600, 0xbd),
+                Access::Read(0x0601, 0xff),
+                Access::Read(0x0602, 0x02),
+                Access::Read(0x0200, 0x00),
+                Access::Read(0x0300, 0x00),
+            ]
+        );
+
+        // STA $0200,X always takes the fixup cycle
+        let mut cpu = recording_cpu(&[0x9d, 0x00, 0x02]);
+        cpu.register_a = 0x42;
+        cpu.register_x = 1;
+        assert_eq!(clock_instruction(&mut cpu).cycles, 5);
+        assert_eq!(
+            cpu.bus.log,
+            vec![
+                Access::Read(0x0600, 0x9d),
+                Access::Read(0x0601, 0x00),
+                Access::Read(0x0602, 0x02),
+                Access::Read(0x0201, 0x00),
+                Access::Write(0x0201, 0x42),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_step_finishes_instruction_in_flight() {
+        let mut cpu = recording_cpu(&[0xa9, 0x05, 0xaa]);
+        assert_eq!(cpu.clock().unwrap(), None);
+
+        let outcome = cpu.step().unwrap();
+        assert_eq!(outcome.opcode, 0xa9);
+        assert_eq!(outcome.cycles, 2);
+        assert_eq!(cpu.register_a, 5);
+
+        assert_eq!(cpu.step().unwrap().opcode, 0xaa);
+    }
+
+    #[test]
+    fn test_clock_matches_step() {
+        let program = [
+            0xa2, 0x05, // LDX #$05
+            0xa9, 0x10, // LDA #$10
+            0x85, 0x20, // STA $20
+            0xa9, 0x02, // LDA #$02
+            0x85, 0x21, // STA $21
+            0xa0, 0xf8, // LDY #$f8
+            0xb1, 0x20, // loop: LDA ($20),Y
+            0x91, 0x20, // STA ($20),Y
+            0xfe, 0xfe, 0x02, // INC $02fe,X
+            0x20, 0x1c, 0x06, // JSR sub
+            0xca, // DEX
+            0xd0, 0xf3, // BNE loop
+            0x6c, 0x30, 0x00, // JMP ($0030)
+            0x48, // sub: PHA
+            0x0a, // ASL A
+            0x7d, 0x00, 0x03, // ADC $0300,X
+            0x81, 0x1f, // STA ($1f,X)
+            0x68, // PLA
+            0x08, // PHP
+            0x28, // PLP
+            0x60, // RTS
+        ];
+        let mut stepped = CPU::new();
+        let mut clocked = CPU::new();
+        for cpu in [&mut stepped, &mut clocked].iter_mut() {
+            cpu.load(program.to_vec());
+            cpu.mem_write_u16(0x0030, 0x0600);
+            // NMI handler: RTI
+            cpu.mem_write(0x0640, 0x40);
+            cpu.mem_write_u16(0xfffa, 0x0640);
+            cpu.reset();
+        }
+
+        for i in 0..300 {
+            if i == 100 {
+                stepped.set_nmi(true);
+                clocked.set_nmi(true);
+            }
+            assert_eq!(stepped.step().unwrap(), clock_instruction(&mut clocked));
+            assert_eq!(stepped.register_a, clocked.register_a);
+            assert_eq!(stepped.register_x, clocked.register_x);
+            assert_eq!(stepped.register_y, clocked.register_y);
+            assert_eq!(stepped.stack_pointer, clocked.stack_pointer);
+            assert_eq!(stepped.status, clocked.status);
+            assert_eq!(stepped.cycles, clocked.cycles);
+        }
+        for addr in 0..0x0800 {
+            assert_eq!(stepped.mem_peek(addr), clocked.mem_peek(addr));
+        }
+    }
+}
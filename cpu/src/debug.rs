@@ -0,0 +1,564 @@
+//! Breakpoints, watchpoints and conditional stops.
+//!
+//! Breakpoints are checked by [`CPU::run_with_callback`], which stops and
+//! returns the [`BreakpointHit`] of the first one that fires. Execution and
+//! conditional breakpoints stop before the instruction executes, watchpoints
+//! right after the instruction that made the access. Running again after a
+//! stop executes the instruction the CPU stopped before instead of stopping
+//! on it again.
+//!
+//! ```
+//! use cpu::debug::{Access, Breakpoint};
+//! use cpu::CPU;
+//!
+//! let mut cpu = CPU::new();
+//! cpu.load(vec![0xa9, 0x40, 0xc9, 0x40, 0x85, 0x10, 0x00]);
+//! cpu.reset();
+//! let id = cpu.add_breakpoint(Breakpoint::Condition("A == $40 && Z".parse().unwrap()));
+//! cpu.add_breakpoint(Breakpoint::Watch { range: 0x10..=0x1f, access: Access::Write });
+//!
+//! let hit = cpu.run().unwrap().unwrap();
+//! assert_eq!((hit.id, hit.pc), (id, 0x0604));
+//! ```
+
+use crate::{CpuFlags, Mem, CPU};
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// Identifies a breakpoint added with [`CPU::add_breakpoint`].
+pub type BreakpointId = u32;
+
+/// Kind of memory access a watchpoint stops on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn covers(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Stops before the instruction at the address
+    Execute(u16),
+    /// Stops after an instruction read or wrote an address in the range,
+    /// including the dummy accesses of the cycle stepped core
+    Watch {
+        range: RangeInclusive<u16>,
+        access: Access,
+    },
+    /// Stops before any instruction the condition holds for
+    Condition(Condition),
+}
+
+/// Why [`CPU::run_with_callback`] stopped on a breakpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreakpointHit {
+    pub id: BreakpointId,
+    /// Address of the instruction the CPU stopped before, or of the one that
+    /// made the access for a watchpoint
+    pub pc: u16,
+    /// The access that fired a watchpoint
+    pub access: Option<MemoryAccess>,
+}
+
+/// A single bus access, `kind` is either `Read` or `Write`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub address: u16,
+    pub value: u8,
+    pub kind: Access,
+}
+
+/// Breakpoints of a CPU and the watchpoint hit of the running instruction.
+#[derive(Default)]
+pub(crate) struct Breakpoints {
+    next_id: BreakpointId,
+    list: Vec<(BreakpointId, Breakpoint)>,
+    /// Whether any watchpoint is set, so the bus accesses of a CPU without
+    /// watchpoints stay cheap
+    watching: bool,
+    watch_hit: Option<(BreakpointId, MemoryAccess)>,
+    /// Address the last run stopped before, the next run does not stop there again
+    pub(crate) stopped_at: Option<u16>,
+}
+
+impl Breakpoints {
+    pub(crate) fn access(&mut self, address: u16, value: u8, kind: Access) {
+        if !self.watching || self.watch_hit.is_some() {
+            return;
+        }
+        let hit = self.list.iter().find(|(_, breakpoint)| match breakpoint {
+            Breakpoint::Watch { range, access } => access.covers(kind) && range.contains(&address),
+            _ => false,
+        });
+        if let Some((id, _)) = hit {
+            let access = MemoryAccess {
+                address,
+                value,
+                kind,
+            };
+            self.watch_hit = Some((*id, access));
+        }
+    }
+
+    pub(crate) fn take_watch_hit(&mut self) -> Option<(BreakpointId, MemoryAccess)> {
+        self.watch_hit.take()
+    }
+
+    fn update_watching(&mut self) {
+        self.watching = self
+            .list
+            .iter()
+            .any(|(_, breakpoint)| matches!(breakpoint, Breakpoint::Watch { .. }));
+    }
+}
+
+impl<B: Mem> CPU<B> {
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> BreakpointId {
+        let breakpoints = &mut self.breakpoints;
+        let id = breakpoints.next_id;
+        breakpoints.next_id += 1;
+        breakpoints.list.push((id, breakpoint));
+        breakpoints.update_watching();
+        id
+    }
+
+    /// Returns the removed breakpoint, `None` if there was none with this id.
+    pub fn remove_breakpoint(&mut self, id: BreakpointId) -> Option<Breakpoint> {
+        let breakpoints = &mut self.breakpoints;
+        let index = breakpoints.list.iter().position(|(i, _)| *i == id)?;
+        let (_, breakpoint) = breakpoints.list.remove(index);
+        breakpoints.update_watching();
+        Some(breakpoint)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.list.clear();
+        self.breakpoints.update_watching();
+    }
+
+    /// Breakpoints in the order they were added
+    pub fn breakpoints(&self) -> impl Iterator<Item = (BreakpointId, &Breakpoint)> {
+        self.breakpoints
+            .list
+            .iter()
+            .map(|(id, breakpoint)| (*id, breakpoint))
+    }
+
+    /// The first execution or conditional breakpoint that fires before the
+    /// next instruction.
+    pub(crate) fn breakpoint_before(&self) -> Option<BreakpointHit> {
+        let pc = self.program_counter;
+        let (id, _) = self
+            .breakpoints
+            .list
+            .iter()
+            .find(|(_, breakpoint)| match breakpoint {
+                Breakpoint::Execute(address) => *address == pc,
+                Breakpoint::Condition(condition) => condition.eval(self),
+                Breakpoint::Watch { .. } => false,
+            })?;
+        Some(BreakpointHit {
+            id: *id,
+            pc,
+            access: None,
+        })
+    }
+}
+
+/// A condition on registers, flags and memory, like `A == $40 && Z`.
+///
+/// Operands are the registers `A X Y SP P PC`, the flags `N V D I Z C`
+/// (1 when set), numbers in decimal, `$hex` or `%binary`, and `[address]`
+/// for the byte in memory. They combine with `+ -`, the comparisons
+/// `== != < <= > >=`, `&&`, `||`, `!` and parentheses, any operand that is
+/// not zero is true. Names are case insensitive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Number(u16),
+    Register(Register),
+    Flag(CpuFlags),
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(Box<Expr>, Operator, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register {
+    A,
+    X,
+    Y,
+    Sp,
+    P,
+    Pc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Or,
+    And,
+    Add,
+    Sub,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Why a [`Condition`] could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConditionError {
+    pub message: String,
+}
+
+impl fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid condition: {}", self.message)
+    }
+}
+
+impl std::error::Error for ConditionError {}
+
+impl Condition {
+    pub fn eval<B: Mem>(&self, cpu: &CPU<B>) -> bool {
+        eval(&self.expr, cpu) != 0
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = ConditionError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            chars: source.chars().filter(|c| !c.is_whitespace()).collect(),
+            pos: 0,
+        };
+        let expr = parser.or().map_err(|message| ConditionError { message })?;
+        if let Some(c) = parser.peek() {
+            return Err(ConditionError {
+                message: format!("unexpected {:?}", c),
+            });
+        }
+        Ok(Condition {
+            source: source.trim().to_string(),
+            expr,
+        })
+    }
+}
+
+fn eval<B: Mem>(expr: &Expr, cpu: &CPU<B>) -> u16 {
+    match expr {
+        Expr::Number(value) => *value,
+        Expr::Register(register) => match register {
+            Register::A => cpu.register_a as u16,
+            Register::X => cpu.register_x as u16,
+            Register::Y => cpu.register_y as u16,
+            Register::Sp => cpu.stack_pointer as u16,
+            Register::P => cpu.status.bits() as u16,
+            Register::Pc => cpu.program_counter,
+        },
+        Expr::Flag(flag) => cpu.status.contains(*flag) as u16,
+        Expr::Memory(address) => cpu.mem_peek(eval(address, cpu)) as u16,
+        Expr::Not(expr) => (eval(expr, cpu) == 0) as u16,
+        Expr::Binary(left, operator, right) => {
+            let left = eval(left, cpu);
+            // && and || short circuit like they do in Rust
+            match operator {
+                Operator::Or if left != 0 => return 1,
+                Operator::And if left == 0 => return 0,
+                _ => {}
+            }
+            let right = eval(right, cpu);
+            let result = match operator {
+                Operator::Add => return left.wrapping_add(right),
+                Operator::Sub => return left.wrapping_sub(right),
+                Operator::Or | Operator::And => right != 0,
+                Operator::Eq => left == right,
+                Operator::Ne => left != right,
+                Operator::Lt => left < right,
+                Operator::Le => left <= right,
+                Operator::Gt => left > right,
+                Operator::Ge => left >= right,
+            };
+            result as u16
+        }
+    }
+}
+
+/// Recursive descent over a condition, lowest precedence first
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        let len = token.chars().count();
+        let matches = self.chars.len() >= self.pos + len
+            && self.chars[self.pos..self.pos + len]
+                .iter()
+                .copied()
+                .eq(token.chars());
+        if matches {
+            self.pos += len;
+        }
+        matches
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut left = self.and()?;
+        while self.eat("||") {
+            left = Expr::Binary(Box::new(left), Operator::Or, Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut left = self.comparison()?;
+        while self.eat("&&") {
+            left = Expr::Binary(Box::new(left), Operator::And, Box::new(self.comparison()?));
+        }
+        Ok(left)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let left = self.sum()?;
+        // two character operators first so `<=` is not read as `<`
+        let operators = [
+            ("==", Operator::Eq),
+            ("!=", Operator::Ne),
+            ("<=", Operator::Le),
+            (">=", Operator::Ge),
+            ("<", Operator::Lt),
+            (">", Operator::Gt),
+        ];
+        for (token, operator) in operators.iter() {
+            if self.eat(token) {
+                return Ok(Expr::Binary(
+                    Box::new(left),
+                    *operator,
+                    Box::new(self.sum()?),
+                ));
+            }
+        }
+        Ok(left)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        loop {
+            let operator = if self.eat("+") {
+                Operator::Add
+            } else if self.eat("-") {
+                Operator::Sub
+            } else {
+                return Ok(left);
+            };
+            left = Expr::Binary(Box::new(left), operator, Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        let c = self.peek().ok_or("missing operand")?;
+        if self.eat("(") {
+            let expr = self.or()?;
+            if !self.eat(")") {
+                return Err("missing )".to_string());
+            }
+            Ok(expr)
+        } else if self.eat("[") {
+            let address = self.or()?;
+            if !self.eat("]") {
+                return Err("missing ]".to_string());
+            }
+            Ok(Expr::Memory(Box::new(address)))
+        } else if self.eat("$") {
+            self.number(16)
+        } else if self.eat("%") {
+            self.number(2)
+        } else if c.is_ascii_digit() {
+            self.number(10)
+        } else if c.is_ascii_alphabetic() {
+            let start = self.pos;
+            while matches!(self.peek(), Some(c) if c.is_ascii_alphabetic()) {
+                self.pos += 1;
+            }
+            let name: String = self.chars[start..self.pos].iter().collect();
+            let expr = match name.to_ascii_uppercase().as_str() {
+                "A" => Expr::Register(Register::A),
+                "X" => Expr::Register(Register::X),
+                "Y" => Expr::Register(Register::Y),
+                "SP" => Expr::Register(Register::Sp),
+                "P" => Expr::Register(Register::P),
+                "PC" => Expr::Register(Register::Pc),
+                "N" => Expr::Flag(CpuFlags::NEGATIV),
+                "V" => Expr::Flag(CpuFlags::OVERFLOW),
+                "D" => Expr::Flag(CpuFlags::DECIMAL_MODE),
+                "I" => Expr::Flag(CpuFlags::INTERRUPT_DISABLE),
+                "Z" => Expr::Flag(CpuFlags::ZERO),
+                "C" => Expr::Flag(CpuFlags::CARRY),
+                _ => return Err(format!("unknown register or flag {}", name)),
+            };
+            Ok(expr)
+        } else {
+            Err(format!("unexpected {:?}", c))
+        }
+    }
+
+    fn number(&mut self, radix: u32) -> Result<Expr, String> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_digit(radix)) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        u16::from_str_radix(&digits, radix)
+            .map(Expr::Number)
+            .map_err(|_| format!("invalid number {:?}", digits))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn condition(source: &str) -> Condition {
+        source.parse().unwrap()
+    }
+
+    #[test]
+    fn test_conditions() {
+        let mut cpu = CPU::new();
+        cpu.register_a = 0x40;
+        cpu.register_x = 3;
+        cpu.status.insert(CpuFlags::ZERO);
+        cpu.mem_write(0x10, 7);
+
+        assert!(condition("A == $40 && Z").eval(&cpu));
+        assert!(!condition("a == $40 && !z").eval(&cpu));
+        assert!(condition("X >= 3 && X < %100 || C").eval(&cpu));
+        assert!(condition("[$10] == 7 && [$0d + X] != 0").eval(&cpu));
+        assert!(condition("(C || Z) && SP == $fd").eval(&cpu));
+        assert!(!condition("PC > 0").eval(&cpu));
+
+        let error = "A == Q".parse::<Condition>().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid condition: unknown register or flag Q"
+        );
+        assert!("A == ".parse::<Condition>().is_err());
+        assert!("(A".parse::<Condition>().is_err());
+        assert_eq!(condition(" X == 1 ").to_string(), "X == 1");
+    }
+
+    #[test]
+    fn test_execute_breakpoint_and_resume() {
+        let mut cpu = CPU::new();
+        // loop: INX, CPX #$03, BNE loop, BRK
+        cpu.load(vec![0xe8, 0xe0, 0x03, 0xd0, 0xfb, 0x00]);
+        cpu.reset();
+        cpu.stop_on_brk = true;
+        let id = cpu.add_breakpoint(Breakpoint::Execute(0x0600));
+
+        // stops on the first instruction and again on every iteration
+        for x in 0..3 {
+            let hit = cpu.run().unwrap().unwrap();
+            assert_eq!(
+                hit,
+                BreakpointHit {
+                    id,
+                    pc: 0x0600,
+                    access: None
+                }
+            );
+            assert_eq!(cpu.register_x, x);
+        }
+
+        assert_eq!(cpu.remove_breakpoint(id), Some(Breakpoint::Execute(0x0600)));
+        assert_eq!(cpu.remove_breakpoint(id), None);
+        assert_eq!(cpu.run().unwrap(), None);
+        assert_eq!(cpu.register_x, 3);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut cpu = CPU::new();
+        // LDA $20, STA $11, INC $12, BRK
+        cpu.load(vec![0xa5, 0x20, 0x85, 0x11, 0xe6, 0x12, 0x00]);
+        cpu.reset();
+        cpu.stop_on_brk = true;
+        cpu.mem_write(0x12, 0x41);
+        let writes = cpu.add_breakpoint(Breakpoint::Watch {
+            range: 0x10..=0x1f,
+            access: Access::Write,
+        });
+        let reads = cpu.add_breakpoint(Breakpoint::Watch {
+            range: 0x12..=0x12,
+            access: Access::Read,
+        });
+
+        let hit = cpu.run().unwrap().unwrap();
+        let access = MemoryAccess {
+            address: 0x11,
+            value: 0,
+            kind: Access::Write,
+        };
+        assert_eq!(
+            hit,
+            BreakpointHit {
+                id: writes,
+                pc: 0x0602,
+                access: Some(access)
+            }
+        );
+        assert_eq!(cpu.program_counter, 0x0604);
+
+        // INC reads before it writes
+        let hit = cpu.run().unwrap().unwrap();
+        let access = MemoryAccess {
+            address: 0x12,
+            value: 0x41,
+            kind: Access::Read,
+        };
+        assert_eq!(
+            hit,
+            BreakpointHit {
+                id: reads,
+                pc: 0x0604,
+                access: Some(access)
+            }
+        );
+
+        cpu.clear_breakpoints();
+        assert_eq!(cpu.breakpoints().count(), 0);
+        assert_eq!(cpu.run().unwrap(), None);
+        assert_eq!(cpu.mem_read(0x12), 0x42);
+    }
+}
@@ -0,0 +1,258 @@
+//! Disassembler.
+//!
+//! Decodes memory back into [`Instruction`]s through the opcode table of a
+//! [`CpuVariant`]. Memory is read with `mem_peek`, so any bus can be
+//! disassembled without side effects on its devices.
+
+use crate::bus::Rom;
+use crate::opcode::OpCode;
+use crate::{AddressingMode, CpuVariant, Mem};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// Names substituted for addresses in the formatted operands
+pub type Symbols = HashMap<u16, String>;
+
+/// One decoded instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,
+    pub opcode: &'static OpCode,
+    /// Operand bytes, the first one in the low byte
+    pub operand: u16,
+}
+
+impl Instruction {
+    /// The instruction as it is stored in memory
+    pub fn bytes(&self) -> Vec<u8> {
+        let [lo, hi] = self.operand.to_le_bytes();
+        let bytes = [self.opcode.code, lo, hi];
+        bytes[..self.opcode.len as usize].to_vec()
+    }
+
+    /// Address following the instruction
+    pub fn next(&self) -> u16 {
+        self.address.wrapping_add(self.opcode.len as u16)
+    }
+
+    /// Absolute address a branch goes to
+    pub fn branch_target(&self) -> Option<u16> {
+        let offset = match self.opcode.mode {
+            AddressingMode::Relative => self.operand as u8,
+            AddressingMode::ZeroPage_Relative => (self.operand >> 8) as u8,
+            _ => return None,
+        };
+        Some(self.next().wrapping_add(offset as i8 as u16))
+    }
+
+    /// Formats the instruction, replacing the addresses found in `symbols` by their name.
+    pub fn format(&self, symbols: &Symbols) -> String {
+        let name = |addr: u16, digits: usize| match symbols.get(&addr) {
+            Some(symbol) => symbol.clone(),
+            None => format!("${:0width$X}", addr, width = digits),
+        };
+        let zero_page = name(self.operand & 0x00ff, 2);
+        let absolute = name(self.operand, 4);
+
+        let operand = match self.opcode.mode {
+            AddressingMode::Implied => String::new(),
+            AddressingMode::Accumulator => "A".to_string(),
+            AddressingMode::Immediate => format!("#${:02X}", self.operand),
+            AddressingMode::ZeroPage => zero_page,
+            AddressingMode::ZeroPage_X => format!("{},X", zero_page),
+            AddressingMode::ZeroPage_Y => format!("{},Y", zero_page),
+            AddressingMode::Absolute => absolute,
+            AddressingMode::Absolute_X => format!("{},X", absolute),
+            AddressingMode::Absolute_Y => format!("{},Y", absolute),
+            AddressingMode::Indirect_X => format!("({},X)", zero_page),
+            AddressingMode::Indirect_Y => format!("({}),Y", zero_page),
+            AddressingMode::ZeroPage_Indirect => format!("({})", zero_page),
+            AddressingMode::Relative => name(self.branch_target().unwrap(), 4),
+            AddressingMode::Indirect => format!("({})", absolute),
+            AddressingMode::Absolute_Indexed_Indirect => format!("({},X)", absolute),
+            AddressingMode::ZeroPage_Relative => {
+                format!("{},{}", zero_page, name(self.branch_target().unwrap(), 4))
+            }
+        };
+
+        if operand.is_empty() {
+            self.opcode.mnemonic.to_string()
+        } else {
+            format!("{} {}", self.opcode.mnemonic, operand)
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format(&Symbols::new()))
+    }
+}
+
+/// Decodes the instruction at `address`.
+pub fn decode<M: Mem + ?Sized>(mem: &M, variant: CpuVariant, address: u16) -> Instruction {
+    let opcode = variant
+        .decode(mem.mem_peek(address))
+        .expect("the opcode tables cover every byte");
+    let lo = mem.mem_peek(address.wrapping_add(1));
+    let hi = mem.mem_peek(address.wrapping_add(2));
+    let operand = match opcode.len {
+        1 => 0,
+        2 => lo as u16,
+        _ => u16::from_le_bytes([lo, hi]),
+    };
+    Instruction {
+        address,
+        opcode,
+        operand,
+    }
+}
+
+/// Decodes the instructions that start in `range`, one after the other.
+pub fn disassemble<M: Mem + ?Sized>(
+    mem: &M,
+    variant: CpuVariant,
+    range: RangeInclusive<u16>,
+) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut address = *range.start() as u32;
+    while address <= *range.end() as u32 {
+        let instruction = decode(mem, variant, address as u16);
+        address += instruction.opcode.len as u32;
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+/// Decodes a program loaded at `origin`, up to its end or $FFFF.
+///
+/// # Panics
+///
+/// If the program does not fit in the 64KiB address space.
+pub fn disassemble_bytes(program: &[u8], origin: u16, variant: CpuVariant) -> Vec<Instruction> {
+    assert!(
+        program.len() <= 0x10000,
+        "a program of {} bytes does not fit in 64KiB",
+        program.len()
+    );
+    if program.is_empty() {
+        return Vec::new();
+    }
+    let mut memory = vec![0; 0x10000];
+    for (i, byte) in program.iter().enumerate() {
+        memory[origin.wrapping_add(i as u16) as usize] = *byte;
+    }
+    let end = (origin as usize + program.len() - 1).min(0xffff) as u16;
+    disassemble(&Rom::new(memory), variant, origin..=end)
+}
+
+/// Formats instructions one per line, with their address and bytes:
+///
+/// ```text
+/// 0600  20 06 06  JSR init
+/// ```
+pub fn listing(instructions: &[Instruction], symbols: &Symbols) -> String {
+    let mut listing = String::new();
+    for instruction in instructions {
+        if let Some(label) = symbols.get(&instruction.address) {
+            listing.push_str(&format!("{}:\n", label));
+        }
+        let bytes: Vec<String> = instruction
+            .bytes()
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        listing.push_str(&format!(
+            "{:04X}  {:8}  {}\n",
+            instruction.address,
+            bytes.join(" "),
+            instruction.format(symbols)
+        ));
+    }
+    listing
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_disassemble_modes() {
+        let program = [
+            0x20, 0x06, 0x06, // JSR $0606
+            0xa9, 0x02, // LDA #$02
+            0xb1, 0x10, // LDA ($10),Y
+            0x9d, 0x00, 0x02, // STA $0200,X
+            0x0a, // ASL A
+            0xd0, 0xf6, // BNE $0603
+            0x6c, 0xfc, 0xff, // JMP ($FFFC)
+        ];
+        let text: Vec<String> = disassemble_bytes(&program, 0x0600, CpuVariant::Nmos6502)
+            .iter()
+            .map(|instruction| instruction.to_string())
+            .collect();
+        assert_eq!(
+            text,
+            [
+                "JSR $0606",
+                "LDA #$02",
+                "LDA ($10),Y",
+                "STA $0200,X",
+                "ASL A",
+                "BNE $0603",
+                "JMP ($FFFC)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_listing_with_symbols() {
+        let program = [0x20, 0x06, 0x06, 0x60, 0xea, 0xea, 0xa5, 0xfe, 0xf0, 0xfc];
+        let mut symbols = Symbols::new();
+        symbols.insert(0x0606, "init".to_string());
+        symbols.insert(0x00fe, "random".to_string());
+
+        let instructions = disassemble_bytes(&program, 0x0600, CpuVariant::Nmos6502);
+        assert_eq!(
+            listing(&instructions, &symbols),
+            "0600  20 06 06  JSR init\n\
+             0603  60        RTS\n\
+             0604  EA        NOP\n\
+             0605  EA        NOP\n\
+             init:\n\
+             0606  A5 FE     LDA random\n\
+             0608  F0 FC     BEQ init\n"
+        );
+    }
+
+    #[test]
+    fn test_full_address_space() {
+        let image = vec![0xea; 0x10000];
+        let instructions = disassemble_bytes(&image, 0x0000, CpuVariant::Nmos6502);
+        assert_eq!(instructions.len(), 0x10000);
+        assert_eq!(instructions.last().unwrap().address, 0xffff);
+
+        // the part past $FFFF wraps around in memory but is not listed
+        let instructions = disassemble_bytes(&image, 0xfffe, CpuVariant::Nmos6502);
+        assert_eq!(instructions.len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit in 64KiB")]
+    fn test_rejects_images_over_64k() {
+        disassemble_bytes(&vec![0xea; 0x10001], 0x0000, CpuVariant::Nmos6502);
+    }
+
+    #[test]
+    fn test_65c02_modes() {
+        // BBS7 $12,$0603; JMP ($1234,X); LDA ($20)
+        let program = [0xff, 0x12, 0x00, 0x7c, 0x34, 0x12, 0xb2, 0x20];
+        let instructions = disassemble_bytes(&program, 0x0600, CpuVariant::Wdc65C02);
+        assert_eq!(instructions[0].to_string(), "BBS7 $12,$0603");
+        assert_eq!(instructions[0].branch_target(), Some(0x0603));
+        assert_eq!(instructions[1].to_string(), "JMP ($1234,X)");
+        assert_eq!(instructions[2].to_string(), "LDA ($20)");
+        assert_eq!(instructions[2].bytes(), vec![0xb2, 0x20]);
+    }
+}
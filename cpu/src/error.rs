@@ -0,0 +1,60 @@
+use crate::{AddressingMode, CPU};
+use std::fmt;
+
+/// Why the CPU could not execute an instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CpuError {
+    /// The opcode is not in the opcode table of the CPU variant
+    IllegalOpcode { opcode: u8, address: u16 },
+    /// A JAM (also known as KIL) opcode locked up the CPU
+    Jammed { opcode: u8, address: u16 },
+    /// The 65C02 STP instruction stopped the clock until the next reset
+    Stopped { address: u16 },
+    /// An instruction asked for an operand in a mode that has none
+    InvalidAddressingMode { mode: AddressingMode, address: u16 },
+    /// The trace line of the instruction could not be written
+    Trace { address: u16, message: String },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::IllegalOpcode { opcode, address } => {
+                write!(f, "illegal opcode ${:02x} at ${:04x}", opcode, address)
+            }
+            CpuError::Jammed { opcode, address } => {
+                write!(
+                    f,
+                    "CPU jammed by opcode ${:02x} at ${:04x}",
+                    opcode, address
+                )
+            }
+            CpuError::Stopped { address } => write!(f, "CPU stopped by STP at ${:04x}", address),
+            CpuError::InvalidAddressingMode { mode, address } => write!(
+                f,
+                "addressing mode {:?} has no operand, at ${:04x}",
+                mode, address
+            ),
+            CpuError::Trace { address, message } => {
+                write!(f, "could not trace ${:04x}: {}", address, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+/// Called on the instruction a CPU failed to execute, see [`ErrorPolicy::Trap`].
+pub type TrapHandler<B> = Box<dyn FnMut(&mut CPU<B>, &CpuError) -> Result<(), CpuError>>;
+
+/// What the CPU does when an instruction fails with a [`CpuError`].
+pub enum ErrorPolicy<B> {
+    /// `step` returns the error and the program counter stays on the faulting instruction
+    Halt,
+    /// The faulting instruction is skipped as if it was a NOP of the same length
+    Nop,
+    /// The handler is called with the program counter on the faulting instruction.
+    /// It may fix up the CPU state and move the program counter to resume, or
+    /// return an error to halt.
+    Trap(TrapHandler<B>),
+}
@@ -1,852 +1,2147 @@
-use bitflags::bitflags;
-use std::collections::HashMap;
-extern crate lazy_static;
-#[macro_use]
-pub mod opcode;
-
-bitflags! {
-    /// # Status Register (P) http://wiki.nesdev.com/w/index.php/Status_flags
-    ///
-    ///  7 6 5 4 3 2 1 0
-    ///  N V _ B D I Z C
-    ///  | |   | | | | +--- Carry Flag
-    ///  | |   | | | +----- Zero Flag
-    ///  | |   | | +------- Interrupt Disable
-    ///  | |   | +--------- Decimal Mode (not used on NES)
-    ///  | |   +----------- Break Command
-    ///  | +--------------- Overflow Flag
-    ///  +----------------- Negative Flag
-    ///
-    pub struct CpuFlags: u8 {
-        const CARRY             = 0b00000001;
-        const ZERO              = 0b00000010;
-        const INTERRUPT_DISABLE = 0b00000100;
-        const DECIMAL_MODE      = 0b00001000;
-        const BREAK             = 0b00010000;
-        const BREAK2            = 0b00100000;
-        const OVERFLOW          = 0b01000000;
-        const NEGATIV           = 0b10000000;
-    }
-}
-
-const STACK: u16 = 0x0100;
-const STACK_RESET: u8 = 0xfd;
-
-// Follows the standard of the classic 6502 CPU chip
-pub struct CPU {
-    pub register_a: u8,
-    pub register_x: u8,
-    pub status: CpuFlags,
-    pub stack_pointer: u8,
-    pub register_y: u8,
-    pub program_counter: u16,
-    memory: [u8; 0xFFFF],
-}
-
-pub trait Mem {
-    fn mem_read(&self, addr: u16) -> u8;
-
-    fn mem_write(&mut self, addr: u16, data: u8);
-
-    fn mem_read_u16(&self, pos: u16) -> u16 {
-        let lo = self.mem_read(pos) as u16;
-        let hi = self.mem_read(pos + 1) as u16;
-        (hi << 8) | (lo as u16)
-    }
-
-    fn mem_write_u16(&mut self, pos: u16, data: u16) {
-        let hi = (data >> 8) as u8;
-        let lo = (data & 0xff) as u8;
-        self.mem_write(pos, lo);
-        self.mem_write(pos + 1, hi);
-    }
-}
-
-impl Mem for CPU {
-    fn mem_read(&self, addr: u16) -> u8 {
-        self.memory[addr as usize]
-    }
-
-    fn mem_write(&mut self, addr: u16, data: u8) {
-        self.memory[addr as usize] = data;
-    }
-}
-
-#[derive(Debug)]
-#[allow(non_camel_case_types)]
-pub enum AddressingMode {
-    Immediate,
-    ZeroPage,
-    ZeroPage_X,
-    ZeroPage_Y,
-    Absolute,
-    Absolute_X,
-    Absolute_Y,
-    Indirect_X,
-    Indirect_Y,
-    NoneAddressing,
-}
-
-impl CPU {
-    pub fn new() -> Self {
-        CPU {
-            register_a: 0,
-            register_x: 0,
-            register_y: 0,
-            stack_pointer: STACK_RESET,
-            program_counter: 0,
-            status: CpuFlags::from_bits_truncate(0b100100),
-            memory: [0; 0xFFFF],
-        }
-    }
-
-    fn get_operand_address(&self, mode: &AddressingMode) -> u16 {
-        match mode {
-            AddressingMode::Immediate => self.program_counter,
-
-            AddressingMode::ZeroPage => self.mem_read(self.program_counter) as u16,
-
-            AddressingMode::Absolute => self.mem_read_u16(self.program_counter),
-
-            AddressingMode::ZeroPage_X => {
-                let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_x) as u16;
-                addr
-            }
-            AddressingMode::ZeroPage_Y => {
-                let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_y) as u16;
-                addr
-            }
-
-            AddressingMode::Absolute_X => {
-                let base = self.mem_read_u16(self.program_counter);
-                let addr = base.wrapping_add(self.register_x as u16);
-                addr
-            }
-            AddressingMode::Absolute_Y => {
-                let base = self.mem_read_u16(self.program_counter);
-                let addr = base.wrapping_add(self.register_y as u16);
-                addr
-            }
-
-            AddressingMode::Indirect_X => {
-                let base = self.mem_read(self.program_counter);
-
-                let ptr: u8 = (base as u8).wrapping_add(self.register_x);
-                let lo = self.mem_read(ptr as u16);
-                let hi = self.mem_read(ptr.wrapping_add(1) as u16);
-                (hi as u16) << 8 | (lo as u16)
-            }
-            AddressingMode::Indirect_Y => {
-                let base = self.mem_read(self.program_counter);
-
-                let lo = self.mem_read(base as u16);
-                let hi = self.mem_read((base as u8).wrapping_add(1) as u16);
-                let deref_base = (hi as u16) << 8 | (lo as u16);
-                let deref = deref_base.wrapping_add(self.register_y as u16);
-                deref
-            }
-
-            AddressingMode::NoneAddressing => {
-                panic!("mode {:?} is not supported", mode);
-            }
-        }
-    }
-
-    // Load data in register_a
-    fn ldy(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let data = self.mem_read(addr);
-        self.register_y = data;
-        self.update_zero_and_negative_flags(self.register_y);
-    }
-
-    fn ldx(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let data = self.mem_read(addr);
-        self.register_x = data;
-        self.update_zero_and_negative_flags(self.register_x);
-    }
-
-    fn lda(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(&mode);
-        let value = self.mem_read(addr);
-        self.set_register_a(value);
-    }
-
-    fn sta(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        self.mem_write(addr, self.register_a);
-    }
-
-    fn set_register_a(&mut self, value: u8) {
-        self.register_a = value;
-        self.update_zero_and_negative_flags(self.register_a);
-    }
-
-    fn and(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let data = self.mem_read(addr);
-        self.set_register_a(data & self.register_a);
-    }
-
-    fn eor(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let data = self.mem_read(addr);
-        self.set_register_a(data ^ self.register_a);
-    }
-
-    fn ora(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let data = self.mem_read(addr);
-        self.set_register_a(data | self.register_a);
-    }
-
-    fn tax(&mut self) {
-        self.register_x = self.register_a;
-        self.update_zero_and_negative_flags(self.register_x);
-    }
-
-    fn update_zero_and_negative_flags(&mut self, result: u8) {
-        if result == 0 {
-            self.status.insert(CpuFlags::ZERO);
-        } else {
-            self.status.remove(CpuFlags::ZERO);
-        }
-
-        if result & 0b1000_0000 != 0 {
-            self.status.insert(CpuFlags::NEGATIV);
-        } else {
-            self.status.remove(CpuFlags::NEGATIV);
-        }
-    }
-
-    fn inx(&mut self) {
-        self.register_x = self.register_x.wrapping_add(1);
-        self.update_zero_and_negative_flags(self.register_x);
-    }
-
-    fn iny(&mut self) {
-        self.register_y = self.register_y.wrapping_add(1);
-        self.update_zero_and_negative_flags(self.register_y);
-    }
-
-    pub fn load_and_run(&mut self, program: Vec<u8>) {
-        self.load(program);
-        self.reset();
-        self.run()
-    }
-
-    pub fn load(&mut self, program: Vec<u8>) {
-        self.memory[0x0600..(0x0600 + program.len())].copy_from_slice(&program[..]);
-        self.mem_write_u16(0xFFFC, 0x0600);
-    }
-
-    pub fn reset(&mut self) {
-        self.register_a = 0;
-        self.register_x = 0;
-        self.register_y = 0;
-        self.stack_pointer = STACK_RESET;
-        self.status = CpuFlags::from_bits_truncate(0b100100);
-        self.program_counter = self.mem_read_u16(0xFFFC);
-    }
-
-    fn set_carry_flag(&mut self) {
-        self.status.insert(CpuFlags::CARRY)
-    }
-
-    fn clear_carry_flag(&mut self) {
-        self.status.remove(CpuFlags::CARRY)
-    }
-
-    /// note: ignoring decimal mode
-    /// http://www.righto.com/2012/12/the-6502-overflow-flag-explained.html
-    fn add_to_register_a(&mut self, data: u8) {
-        let mut sum = self.register_a as u16 + data as u16;
-
-        // Applly carry to sum
-        if self.status.contains(CpuFlags::CARRY) {
-            sum += 1
-        }
-
-        let carry = sum > 0xff; // Determine if we need the carry flag or not
-
-        if carry {
-            self.status.insert(CpuFlags::CARRY);
-        } else {
-            self.status.remove(CpuFlags::CARRY);
-        }
-
-        let result = sum as u8;
-
-        if (data ^ result) & (result ^ self.register_a) & 0x80 != 0 {
-            self.status.insert(CpuFlags::OVERFLOW);
-        } else {
-            self.status.remove(CpuFlags::OVERFLOW)
-        }
-
-        self.set_register_a(result);
-    }
-
-    fn sbc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(&mode);
-        let data = self.mem_read(addr);
-        self.add_to_register_a(((data as i8).wrapping_neg().wrapping_sub(1)) as u8);
-    }
-
-    fn adc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
-        self.add_to_register_a(value);
-    }
-
-    fn stack_pop(&mut self) -> u8 {
-        self.stack_pointer = self.stack_pointer.wrapping_add(1);
-        self.mem_read((STACK as u16) + self.stack_pointer as u16)
-    }
-
-    fn stack_push(&mut self, data: u8) {
-        self.mem_write((STACK as u16) + self.stack_pointer as u16, data);
-        self.stack_pointer = self.stack_pointer.wrapping_sub(1)
-    }
-
-    fn stack_push_u16(&mut self, data: u16) {
-        let hi = (data >> 8) as u8;
-        let lo = (data & 0xff) as u8;
-        self.stack_push(hi);
-        self.stack_push(lo);
-    }
-
-    fn stack_pop_u16(&mut self) -> u16 {
-        let lo = self.stack_pop() as u16;
-        let hi = self.stack_pop() as u16;
-
-        hi << 8 | lo
-    }
-
-    fn asl_accumulator(&mut self) {
-        let mut data = self.register_a;
-        if data >> 7 == 1 {
-            self.set_carry_flag();
-        } else {
-            self.clear_carry_flag();
-        }
-        data = data << 1;
-        self.set_register_a(data)
-    }
-
-    fn asl(&mut self, mode: &AddressingMode) -> u8 {
-        let addr = self.get_operand_address(mode);
-        let mut data = self.mem_read(addr);
-        if data >> 7 == 1 {
-            self.set_carry_flag();
-        } else {
-            self.clear_carry_flag();
-        }
-        data = data << 1;
-        self.mem_write(addr, data);
-        self.update_zero_and_negative_flags(data);
-        data
-    }
-
-    fn lsr_accumulator(&mut self) {
-        let mut data = self.register_a;
-        if data & 1 == 1 {
-            self.set_carry_flag();
-        } else {
-            self.clear_carry_flag();
-        }
-        data = data >> 1;
-        self.set_register_a(data)
-    }
-
-    fn lsr(&mut self, mode: &AddressingMode) -> u8 {
-        let addr = self.get_operand_address(mode);
-        let mut data = self.mem_read(addr);
-        if data & 1 == 1 {
-            self.set_carry_flag();
-        } else {
-            self.clear_carry_flag();
-        }
-        data = data >> 1;
-        self.mem_write(addr, data);
-        self.update_zero_and_negative_flags(data);
-        data
-    }
-
-    fn rol(&mut self, mode: &AddressingMode) -> u8 {
-        let addr = self.get_operand_address(mode);
-        let mut data = self.mem_read(addr);
-        let old_carry = self.status.contains(CpuFlags::CARRY);
-
-        if data >> 7 == 1 {
-            self.set_carry_flag();
-        } else {
-            self.clear_carry_flag();
-        }
-        data = data << 1;
-        if old_carry {
-            data = data | 1;
-        }
-        self.mem_write(addr, data);
-        self.update_zero_and_negative_flags(data);
-        data
-    }
-
-    fn rol_accumulator(&mut self) {
-        let mut data = self.register_a;
-        let old_carry = self.status.contains(CpuFlags::CARRY);
-
-        if data >> 7 == 1 {
-            self.set_carry_flag();
-        } else {
-            self.clear_carry_flag();
-        }
-        data = data << 1;
-        if old_carry {
-            data = data | 1;
-        }
-        self.set_register_a(data);
-    }
-
-    fn ror(&mut self, mode: &AddressingMode) -> u8 {
-        let addr = self.get_operand_address(mode);
-        let mut data = self.mem_read(addr);
-        let old_carry = self.status.contains(CpuFlags::CARRY);
-
-        if data & 1 == 1 {
-            self.set_carry_flag();
-        } else {
-            self.clear_carry_flag();
-        }
-        data = data >> 1;
-        if old_carry {
-            data = data | 0b10000000;
-        }
-        self.mem_write(addr, data);
-        self.update_zero_and_negative_flags(data);
-        data
-    }
-
-    fn ror_accumulator(&mut self) {
-        let mut data = self.register_a;
-        let old_carry = self.status.contains(CpuFlags::CARRY);
-
-        if data & 1 == 1 {
-            self.set_carry_flag();
-        } else {
-            self.clear_carry_flag();
-        }
-        data = data >> 1;
-        if old_carry {
-            data = data | 0b10000000;
-        }
-        self.set_register_a(data);
-    }
-
-    fn inc(&mut self, mode: &AddressingMode) -> u8 {
-        let addr = self.get_operand_address(mode);
-        let mut data = self.mem_read(addr);
-        data = data.wrapping_add(1);
-        self.mem_write(addr, data);
-        self.update_zero_and_negative_flags(data);
-        data
-    }
-
-    fn dey(&mut self) {
-        self.register_y = self.register_y.wrapping_sub(1);
-        self.update_zero_and_negative_flags(self.register_y);
-    }
-
-    fn dex(&mut self) {
-        self.register_x = self.register_x.wrapping_sub(1);
-        self.update_zero_and_negative_flags(self.register_x);
-    }
-
-    fn dec(&mut self, mode: &AddressingMode) -> u8 {
-        let addr = self.get_operand_address(mode);
-        let mut data = self.mem_read(addr);
-        data = data.wrapping_sub(1);
-        self.mem_write(addr, data);
-        self.update_zero_and_negative_flags(data);
-        data
-    }
-
-    fn pla(&mut self) {
-        let data = self.stack_pop();
-        self.set_register_a(data);
-    }
-
-    fn plp(&mut self) {
-        self.status.bits = self.stack_pop();
-        self.status.remove(CpuFlags::BREAK);
-        self.status.insert(CpuFlags::BREAK2);
-    }
-
-    fn php(&mut self) {
-        //http://wiki.nesdev.com/w/index.php/CPU_status_flag_behavior
-        let mut flags = self.status.clone();
-        flags.insert(CpuFlags::BREAK);
-        flags.insert(CpuFlags::BREAK2);
-        self.stack_push(flags.bits());
-    }
-
-    fn bit(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let data = self.mem_read(addr);
-        let and = self.register_a & data;
-        if and == 0 {
-            self.status.insert(CpuFlags::ZERO);
-        } else {
-            self.status.remove(CpuFlags::ZERO);
-        }
-
-        self.status.set(CpuFlags::NEGATIV, data & 0b10000000 > 0);
-        self.status.set(CpuFlags::OVERFLOW, data & 0b01000000 > 0);
-    }
-
-    fn compare(&mut self, mode: &AddressingMode, compare_with: u8) {
-        let addr = self.get_operand_address(mode);
-        let data = self.mem_read(addr);
-        if data <= compare_with {
-            self.status.insert(CpuFlags::CARRY);
-        } else {
-            self.status.remove(CpuFlags::CARRY);
-        }
-
-        self.update_zero_and_negative_flags(compare_with.wrapping_sub(data));
-    }
-
-    fn branch(&mut self, condition: bool) {
-        if condition {
-            let jump: i8 = self.mem_read(self.program_counter) as i8;
-            let jump_addr = self
-                .program_counter
-                .wrapping_add(1)
-                .wrapping_add(jump as u16);
-
-            self.program_counter = jump_addr;
-        }
-    }
-
-    pub fn run(&mut self) {
-        self.run_with_callback(|_| {});
-    }
-
-    pub fn run_with_callback<F>(&mut self, mut callback: F)
-    where
-        F: FnMut(&mut CPU),
-    {
-        let ref opcodes: HashMap<u8, &'static opcode::OpCode> = *opcode::OPCODES_MAP;
-        let code = self.mem_read(self.program_counter);
-        self.program_counter += 1;
-        let program_counter_state = self.program_counter;
-
-        let opcode = opcodes
-            .get(&code)
-            .expect(&format!("OpCode {:x} is not recognized", code));
-        match code {
-            0xa9 | 0xa5 | 0xb5 | 0xad | 0xbd | 0xb9 | 0xa1 | 0xb1 => {
-                self.lda(&opcode.mode);
-            }
-
-            0xAA => self.tax(),
-            0xe8 => self.inx(),
-            0x00 => return,
-
-            /* CLD */ 0xd8 => self.status.remove(CpuFlags::DECIMAL_MODE),
-
-            /* CLI */ 0x58 => self.status.remove(CpuFlags::INTERRUPT_DISABLE),
-
-            /* CLV */ 0xb8 => self.status.remove(CpuFlags::OVERFLOW),
-
-            /* CLC */ 0x18 => self.clear_carry_flag(),
-
-            /* SEC */ 0x38 => self.set_carry_flag(),
-
-            /* SEI */ 0x78 => self.status.insert(CpuFlags::INTERRUPT_DISABLE),
-
-            /* SED */ 0xf8 => self.status.insert(CpuFlags::DECIMAL_MODE),
-
-            /* PHA */ 0x48 => self.stack_push(self.register_a),
-
-            /* PLA */
-            0x68 => {
-                self.pla();
-            }
-
-            /* PHP */
-            0x08 => {
-                self.php();
-            }
-
-            /* PLP */
-            0x28 => {
-                self.plp();
-            }
-
-            /* ADC */
-            0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => {
-                self.adc(&opcode.mode);
-            }
-
-            /* SBC */
-            0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 => {
-                self.sbc(&opcode.mode);
-            }
-
-            /* AND */
-            0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => {
-                self.and(&opcode.mode);
-            }
-
-            /* EOR */
-            0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => {
-                self.eor(&opcode.mode);
-            }
-
-            /* ORA */
-            0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => {
-                self.ora(&opcode.mode);
-            }
-
-            /* LSR */ 0x4a => self.lsr_accumulator(),
-
-            /* LSR */
-            0x46 | 0x56 | 0x4e | 0x5e => {
-                self.lsr(&opcode.mode);
-            }
-
-            /*ASL*/ 0x0a => self.asl_accumulator(),
-
-            /* ASL */
-            0x06 | 0x16 | 0x0e | 0x1e => {
-                self.asl(&opcode.mode);
-            }
-
-            /*ROL*/ 0x2a => self.rol_accumulator(),
-
-            /* ROL */
-            0x26 | 0x36 | 0x2e | 0x3e => {
-                self.rol(&opcode.mode);
-            }
-
-            /* ROR */ 0x6a => self.ror_accumulator(),
-
-            /* ROR */
-            0x66 | 0x76 | 0x6e | 0x7e => {
-                self.ror(&opcode.mode);
-            }
-
-            /* INC */
-            0xe6 | 0xf6 | 0xee | 0xfe => {
-                self.inc(&opcode.mode);
-            }
-
-            /* INY */
-            0xc8 => self.iny(),
-
-            /* DEC */
-            0xc6 | 0xd6 | 0xce | 0xde => {
-                self.dec(&opcode.mode);
-            }
-
-            /* DEX */
-            0xca => {
-                self.dex();
-            }
-
-            /* DEY */
-            0x88 => {
-                self.dey();
-            }
-
-            /* CMP */
-            0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => {
-                self.compare(&opcode.mode, self.register_a);
-            }
-
-            /* CPY */
-            0xc0 | 0xc4 | 0xcc => {
-                self.compare(&opcode.mode, self.register_y);
-            }
-
-            /* CPX */
-            0xe0 | 0xe4 | 0xec => self.compare(&opcode.mode, self.register_x),
-
-            /* JMP Absolute */
-            0x4c => {
-                let mem_address = self.mem_read_u16(self.program_counter);
-                self.program_counter = mem_address;
-            }
-
-            /* JMP Indirect */
-            0x6c => {
-                let mem_address = self.mem_read_u16(self.program_counter);
-                // let indirect_ref = self.mem_read_u16(mem_address);
-                //6502 bug mode with with page boundary:
-                //  if address $3000 contains $40, $30FF contains $80, and $3100 contains $50,
-                // the result of JMP ($30FF) will be a transfer of control to $4080 rather than $5080 as you intended
-                // i.e. the 6502 took the low byte of the address from $30FF and the high byte from $3000
-
-                let indirect_ref = if mem_address & 0x00FF == 0x00FF {
-                    let lo = self.mem_read(mem_address);
-                    let hi = self.mem_read(mem_address & 0xFF00);
-                    (hi as u16) << 8 | (lo as u16)
-                } else {
-                    self.mem_read_u16(mem_address)
-                };
-
-                self.program_counter = indirect_ref;
-            }
-
-            /* JSR */
-            0x20 => {
-                self.stack_push_u16(self.program_counter + 2 - 1);
-                let target_address = self.mem_read_u16(self.program_counter);
-                self.program_counter = target_address
-            }
-
-            /* RTS */
-            0x60 => {
-                self.program_counter = self.stack_pop_u16() + 1;
-            }
-
-            /* RTI */
-            0x40 => {
-                self.status.bits = self.stack_pop();
-                self.status.remove(CpuFlags::BREAK);
-                self.status.insert(CpuFlags::BREAK2);
-
-                self.program_counter = self.stack_pop_u16();
-            }
-
-            /* BNE */
-            0xd0 => {
-                self.branch(!self.status.contains(CpuFlags::ZERO));
-            }
-
-            /* BVS */
-            0x70 => {
-                self.branch(self.status.contains(CpuFlags::OVERFLOW));
-            }
-
-            /* BVC */
-            0x50 => {
-                self.branch(!self.status.contains(CpuFlags::OVERFLOW));
-            }
-
-            /* BPL */
-            0x10 => {
-                self.branch(!self.status.contains(CpuFlags::NEGATIV));
-            }
-
-            /* BMI */
-            0x30 => {
-                self.branch(self.status.contains(CpuFlags::NEGATIV));
-            }
-
-            /* BEQ */
-            0xf0 => {
-                self.branch(self.status.contains(CpuFlags::ZERO));
-            }
-
-            /* BCS */
-            0xb0 => {
-                self.branch(self.status.contains(CpuFlags::CARRY));
-            }
-
-            /* BCC */
-            0x90 => {
-                self.branch(!self.status.contains(CpuFlags::CARRY));
-            }
-
-            /* BIT */
-            0x24 | 0x2c => {
-                self.bit(&opcode.mode);
-            }
-
-            /* STA */
-            0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => {
-                self.sta(&opcode.mode);
-            }
-
-            /* STX */
-            0x86 | 0x96 | 0x8e => {
-                let addr = self.get_operand_address(&opcode.mode);
-                self.mem_write(addr, self.register_x);
-            }
-
-            /* STY */
-            0x84 | 0x94 | 0x8c => {
-                let addr = self.get_operand_address(&opcode.mode);
-                self.mem_write(addr, self.register_y);
-            }
-
-            /* LDX */
-            0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => {
-                self.ldx(&opcode.mode);
-            }
-
-            /* LDY */
-            0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => {
-                self.ldy(&opcode.mode);
-            }
-
-            /* NOP */
-            0xea => {
-                //do nothing
-            }
-
-            /* TAY */
-            0xa8 => {
-                self.register_y = self.register_a;
-                self.update_zero_and_negative_flags(self.register_y);
-            }
-
-            /* TSX */
-            0xba => {
-                self.register_x = self.stack_pointer;
-                self.update_zero_and_negative_flags(self.register_x);
-            }
-
-            /* TXA */
-            0x8a => {
-                self.register_a = self.register_x;
-                self.update_zero_and_negative_flags(self.register_a);
-            }
-
-            /* TXS */
-            0x9a => {
-                self.stack_pointer = self.register_x;
-            }
-
-            /* TYA */
-            0x98 => {
-                self.register_a = self.register_y;
-                self.update_zero_and_negative_flags(self.register_a);
-            }
-
-            _ => todo!(),
-        }
-
-        if program_counter_state == self.program_counter {
-            self.program_counter += (opcode.len - 1) as u16;
-        }
-
-        callback(self);
-    }
-}
-
-#[cfg(test)]
-mod test {
-    use super::*;
-
-    #[test]
-    fn test_0xa9_lda_immediate_load_data() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9, 0x05, 0x02]);
-        assert_eq!(cpu.register_a, 0);
-    }
-}
+use bitflags::bitflags;
+use std::fmt;
+use std::io::{self, Write};
+pub mod asm;
+pub mod bus;
+mod cycle;
+pub mod debug;
+pub mod disasm;
+mod error;
+pub mod opcode;
+pub mod profile;
+pub mod rewind;
+pub mod state;
+pub mod trace;
+pub mod variant;
+
+pub use bus::Ram;
+pub use debug::BreakpointHit;
+pub use error::{CpuError, ErrorPolicy, TrapHandler};
+pub use variant::CpuVariant;
+
+bitflags! {
+    /// # Status Register (P) http://wiki.nesdev.com/w/index.php/Status_flags
+    ///
+    ///  7 6 5 4 3 2 1 0
+    ///  N V _ B D I Z C
+    ///  | |   | | | | +--- Carry Flag
+    ///  | |   | | | +----- Zero Flag
+    ///  | |   | | +------- Interrupt Disable
+    ///  | |   | +--------- Decimal Mode (not used on NES)
+    ///  | |   +----------- Break Command
+    ///  | +--------------- Overflow Flag
+    ///  +----------------- Negative Flag
+    ///
+    pub struct CpuFlags: u8 {
+        const CARRY             = 0b00000001;
+        const ZERO              = 0b00000010;
+        const INTERRUPT_DISABLE = 0b00000100;
+        const DECIMAL_MODE      = 0b00001000;
+        const BREAK             = 0b00010000;
+        const BREAK2            = 0b00100000;
+        const OVERFLOW          = 0b01000000;
+        const NEGATIV           = 0b10000000;
+    }
+}
+
+/// Formats the flags as `NV-BDIZC`, upper case when set and lower case when clear
+impl fmt::Display for CpuFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, name) in "NV-BDIZC".chars().enumerate() {
+            if self.bits() & (0x80 >> i) != 0 {
+                write!(f, "{}", name)?;
+            } else {
+                write!(f, "{}", name.to_ascii_lowercase())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where [`CPU::load`] puts programs, and where the assembler and the tools
+/// load code that does not say otherwise
+pub const DEFAULT_ORIGIN: u16 = 0x0600;
+
+const STACK: u16 = 0x0100;
+const STACK_RESET: u8 = 0xfd;
+
+/// Constant OR-ed into A by the unstable XAA and LXA opcodes.
+/// It varies between chips and even with temperature, 0xEE is the most common value.
+const UNSTABLE_MAGIC: u8 = 0xEE;
+
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+/// Hardware interrupts the CPU can service between two instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    Nmi,
+    Irq,
+}
+
+impl Interrupt {
+    fn vector(self) -> u16 {
+        match self {
+            Interrupt::Nmi => NMI_VECTOR,
+            Interrupt::Irq => IRQ_VECTOR,
+        }
+    }
+}
+
+// Follows the standard of the classic 6502 CPU chip
+pub struct CPU<B = Ram> {
+    pub register_a: u8,
+    pub register_x: u8,
+    pub status: CpuFlags,
+    pub stack_pointer: u8,
+    pub register_y: u8,
+    pub program_counter: u16,
+    /// Total number of clock cycles executed since power on
+    pub cycles: u64,
+    pub bus: B,
+    /// Makes the run loop stop when it reaches a BRK instead of executing it,
+    /// for programs that use BRK to mark their end
+    pub stop_on_brk: bool,
+    /// How instructions that can not be executed are handled
+    pub error_policy: ErrorPolicy<B>,
+    variant: CpuVariant,
+    /// Set by WAI, cleared once an interrupt line is asserted
+    waiting: bool,
+    nmi_line: bool,
+    nmi_pending: bool,
+    irq_line: bool,
+    /// Instruction being executed by `clock`, between two cycles
+    in_flight: Option<cycle::InFlight>,
+    tracer: Option<Box<dyn io::Write>>,
+    profiler: Option<profile::Profiler>,
+    breakpoints: debug::Breakpoints,
+}
+
+pub trait Mem {
+    fn mem_read(&mut self, addr: u16) -> u8;
+
+    fn mem_write(&mut self, addr: u16, data: u8);
+
+    /// Reads a byte without triggering any side effect a device may have on
+    /// read (clearing a latch, advancing a counter...). Used by frontends and
+    /// debugging tools to inspect memory.
+    fn mem_peek(&self, addr: u16) -> u8;
+
+    /// Appends the device's own sections to a save state. Devices without
+    /// state of their own, like ROM, keep this default.
+    fn save_sections(&self, _state: &mut state::StateWriter) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Checks the sections `load_sections` is going to restore without
+    /// touching the device: every section of a state is checked before any
+    /// is restored, so a state that can not be loaded leaves the machine as
+    /// it was. Devices overriding `load_sections` override this one too.
+    fn check_sections(&self, _state: &mut state::StateReader) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Restores the sections written by `save_sections`, once
+    /// `check_sections` accepted them.
+    fn load_sections(&mut self, _state: &mut state::StateReader) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
+        let lo = self.mem_read(pos) as u16;
+        let hi = self.mem_read(pos.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    fn mem_write_u16(&mut self, pos: u16, data: u16) {
+        let hi = (data >> 8) as u8;
+        let lo = (data & 0xff) as u8;
+        self.mem_write(pos, lo);
+        self.mem_write(pos.wrapping_add(1), hi);
+    }
+}
+
+impl<B: Mem> Mem for CPU<B> {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let data = self.bus.mem_read(addr);
+        self.breakpoints.access(addr, data, debug::Access::Read);
+        data
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.bus.mem_write(addr, data);
+        self.breakpoints.access(addr, data, debug::Access::Write);
+    }
+
+    fn mem_peek(&self, addr: u16) -> u8 {
+        self.bus.mem_peek(addr)
+    }
+}
+
+/// Executes one decoded instruction, the program counter points past the opcode byte
+type Handler<B> = fn(&mut CPU<B>, &opcode::OpCode) -> Result<(), CpuError>;
+
+/// What an instruction does with its operand. The instruction level core
+/// performs the whole access at once, the cycle stepped core spreads it over
+/// the bus cycles of the addressing mode.
+enum Operation<B> {
+    /// Reads the operand, like LDA
+    Read(fn(&mut CPU<B>, u8)),
+    /// Stores a value at the operand address, like STA
+    Write(fn(&mut CPU<B>) -> u8),
+    /// Reads the operand and writes back the result, like INC
+    Modify(fn(&mut CPU<B>, u8) -> u8),
+    /// The unstable SHX, SHY, AHX and TAS stores, see `store_and_high`
+    WriteHigh(fn(&mut CPU<B>) -> u8),
+    /// Implied, stack, jump and branch instructions
+    Custom(Handler<B>),
+}
+
+// derived Clone and Copy would require B to be Copy
+impl<B> Clone for Operation<B> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<B> Copy for Operation<B> {}
+
+/// What happened during a single call to [`CPU::step`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepOutcome {
+    /// Interrupt serviced right before the instruction was executed
+    pub interrupt: Option<Interrupt>,
+    pub opcode: u8,
+    pub cycles: u8,
+    pub pc_before: u16,
+    pub pc_after: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum AddressingMode {
+    /// No operand
+    Implied,
+    /// Operates on the accumulator, `ASL A`
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPage_X,
+    ZeroPage_Y,
+    Absolute,
+    Absolute_X,
+    Absolute_Y,
+    Indirect_X,
+    Indirect_Y,
+    /// `($nn)`, 65C02 only
+    ZeroPage_Indirect,
+    /// Signed offset from the next instruction, used by branches
+    Relative,
+    /// `($nnnn)`, only used by JMP
+    Indirect,
+    /// `($nnnn,X)`, JMP on the 65C02
+    Absolute_Indexed_Indirect,
+    /// `$nn,$rr`: zero page operand followed by a relative offset, BBR and BBS on the 65C02
+    ZeroPage_Relative,
+}
+
+impl AddressingMode {
+    /// Number of operand bytes following the opcode
+    pub fn operand_len(self) -> u8 {
+        match self {
+            AddressingMode::Implied | AddressingMode::Accumulator => 0,
+            AddressingMode::Immediate
+            | AddressingMode::ZeroPage
+            | AddressingMode::ZeroPage_X
+            | AddressingMode::ZeroPage_Y
+            | AddressingMode::Indirect_X
+            | AddressingMode::Indirect_Y
+            | AddressingMode::ZeroPage_Indirect
+            | AddressingMode::Relative => 1,
+            AddressingMode::Absolute
+            | AddressingMode::Absolute_X
+            | AddressingMode::Absolute_Y
+            | AddressingMode::Indirect
+            | AddressingMode::Absolute_Indexed_Indirect
+            | AddressingMode::ZeroPage_Relative => 2,
+        }
+    }
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CPU {
+    pub fn new() -> Self {
+        CPU::with_bus(Ram::new())
+    }
+
+    pub fn with_variant(variant: CpuVariant) -> Self {
+        CPU::with_bus_and_variant(Ram::new(), variant)
+    }
+}
+
+impl<B: Mem> CPU<B> {
+    pub fn with_bus(bus: B) -> Self {
+        CPU::with_bus_and_variant(bus, CpuVariant::default())
+    }
+
+    pub fn with_bus_and_variant(bus: B, variant: CpuVariant) -> Self {
+        CPU {
+            register_a: 0,
+            register_x: 0,
+            register_y: 0,
+            stack_pointer: STACK_RESET,
+            program_counter: 0,
+            status: CpuFlags::from_bits_truncate(0b100100),
+            cycles: 0,
+            bus,
+            stop_on_brk: false,
+            error_policy: ErrorPolicy::Halt,
+            variant,
+            waiting: false,
+            nmi_line: false,
+            nmi_pending: false,
+            irq_line: false,
+            in_flight: None,
+            tracer: None,
+            profiler: None,
+            breakpoints: debug::Breakpoints::default(),
+        }
+    }
+
+    pub fn variant(&self) -> CpuVariant {
+        self.variant
+    }
+
+    fn page_crossed(addr1: u16, addr2: u16) -> bool {
+        addr1 & 0xFF00 != addr2 & 0xFF00
+    }
+
+    /// Returns the effective address of the operand and whether computing it
+    /// crossed a page boundary (which costs read instructions an extra cycle).
+    fn get_operand_address(&mut self, mode: &AddressingMode) -> Result<(u16, bool), CpuError> {
+        let address = match mode {
+            AddressingMode::Immediate => (self.program_counter, false),
+
+            AddressingMode::ZeroPage | AddressingMode::ZeroPage_Relative => {
+                (self.mem_read(self.program_counter) as u16, false)
+            }
+
+            AddressingMode::Absolute => (self.mem_read_u16(self.program_counter), false),
+
+            AddressingMode::ZeroPage_X => {
+                let pos = self.mem_read(self.program_counter);
+                (pos.wrapping_add(self.register_x) as u16, false)
+            }
+            AddressingMode::ZeroPage_Y => {
+                let pos = self.mem_read(self.program_counter);
+                (pos.wrapping_add(self.register_y) as u16, false)
+            }
+
+            AddressingMode::Absolute_X => {
+                let base = self.mem_read_u16(self.program_counter);
+                let addr = base.wrapping_add(self.register_x as u16);
+                (addr, Self::page_crossed(base, addr))
+            }
+            AddressingMode::Absolute_Y => {
+                let base = self.mem_read_u16(self.program_counter);
+                let addr = base.wrapping_add(self.register_y as u16);
+                (addr, Self::page_crossed(base, addr))
+            }
+
+            AddressingMode::Indirect_X => {
+                let base = self.mem_read(self.program_counter);
+
+                let ptr: u8 = base.wrapping_add(self.register_x);
+                let lo = self.mem_read(ptr as u16);
+                let hi = self.mem_read(ptr.wrapping_add(1) as u16);
+                ((hi as u16) << 8 | (lo as u16), false)
+            }
+            AddressingMode::Indirect_Y => {
+                let base = self.mem_read(self.program_counter);
+
+                let lo = self.mem_read(base as u16);
+                let hi = self.mem_read(base.wrapping_add(1) as u16);
+                let deref_base = (hi as u16) << 8 | (lo as u16);
+                let deref = deref_base.wrapping_add(self.register_y as u16);
+                (deref, Self::page_crossed(deref_base, deref))
+            }
+
+            AddressingMode::ZeroPage_Indirect => {
+                let base = self.mem_read(self.program_counter);
+
+                let lo = self.mem_read(base as u16);
+                let hi = self.mem_read(base.wrapping_add(1) as u16);
+                ((hi as u16) << 8 | (lo as u16), false)
+            }
+
+            AddressingMode::Relative => {
+                let offset = self.mem_read(self.program_counter) as i8;
+                let next = self.program_counter.wrapping_add(1);
+                let target = next.wrapping_add(offset as u16);
+                (target, Self::page_crossed(next, target))
+            }
+
+            AddressingMode::Indirect => {
+                let ptr = self.mem_read_u16(self.program_counter);
+                // 6502 bug mode with with page boundary:
+                //  if address $3000 contains $40, $30FF contains $80, and $3100 contains $50,
+                // the result of JMP ($30FF) will be a transfer of control to $4080 rather than $5080 as you intended
+                // i.e. the 6502 took the low byte of the address from $30FF and the high byte from $3000
+                if ptr & 0x00FF == 0x00FF && self.variant.has_jmp_indirect_bug() {
+                    let lo = self.mem_read(ptr);
+                    let hi = self.mem_read(ptr & 0xFF00);
+                    ((hi as u16) << 8 | (lo as u16), false)
+                } else {
+                    (self.mem_read_u16(ptr), false)
+                }
+            }
+
+            AddressingMode::Absolute_Indexed_Indirect => {
+                let base = self.mem_read_u16(self.program_counter);
+                let ptr = base.wrapping_add(self.register_x as u16);
+                (self.mem_read_u16(ptr), false)
+            }
+
+            AddressingMode::Implied | AddressingMode::Accumulator => {
+                return Err(CpuError::InvalidAddressingMode {
+                    mode: *mode,
+                    address: self.program_counter.wrapping_sub(1),
+                });
+            }
+        };
+        Ok(address)
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.cycles += cycles as u64;
+    }
+
+    /// Resolves the operand of a read instruction, charging the extra cycle
+    /// taken when indexing crosses a page boundary.
+    fn get_read_operand_address(&mut self, mode: &AddressingMode) -> Result<u16, CpuError> {
+        let (addr, page_cross) = self.get_operand_address(mode)?;
+        if page_cross {
+            self.tick(1);
+        }
+        Ok(addr)
+    }
+
+    // Load data in register_a
+    fn ldy(&mut self, data: u8) {
+        self.register_y = data;
+        self.update_zero_and_negative_flags(self.register_y);
+    }
+
+    fn ldx(&mut self, data: u8) {
+        self.register_x = data;
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+
+    fn lda(&mut self, value: u8) {
+        self.set_register_a(value);
+    }
+
+    fn set_register_a(&mut self, value: u8) {
+        self.register_a = value;
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+
+    fn and(&mut self, data: u8) {
+        self.set_register_a(data & self.register_a);
+    }
+
+    fn eor(&mut self, data: u8) {
+        self.set_register_a(data ^ self.register_a);
+    }
+
+    fn ora(&mut self, data: u8) {
+        self.set_register_a(data | self.register_a);
+    }
+
+    fn tax(&mut self) {
+        self.register_x = self.register_a;
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+
+    fn update_zero_and_negative_flags(&mut self, result: u8) {
+        if result == 0 {
+            self.status.insert(CpuFlags::ZERO);
+        } else {
+            self.status.remove(CpuFlags::ZERO);
+        }
+
+        if result & 0b1000_0000 != 0 {
+            self.status.insert(CpuFlags::NEGATIV);
+        } else {
+            self.status.remove(CpuFlags::NEGATIV);
+        }
+    }
+
+    fn inx(&mut self) {
+        self.register_x = self.register_x.wrapping_add(1);
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+
+    fn iny(&mut self) {
+        self.register_y = self.register_y.wrapping_add(1);
+        self.update_zero_and_negative_flags(self.register_y);
+    }
+
+    pub fn load_and_run(&mut self, program: Vec<u8>) -> Result<Option<BreakpointHit>, CpuError> {
+        self.load(program);
+        self.reset();
+        self.run()
+    }
+
+    pub fn load(&mut self, program: Vec<u8>) {
+        for (i, byte) in program.iter().enumerate() {
+            self.mem_write(DEFAULT_ORIGIN + i as u16, *byte);
+        }
+        self.mem_write_u16(0xFFFC, DEFAULT_ORIGIN);
+    }
+
+    pub fn reset(&mut self) {
+        self.register_a = 0;
+        self.register_x = 0;
+        self.register_y = 0;
+        self.stack_pointer = STACK_RESET;
+        self.status = CpuFlags::from_bits_truncate(0b100100);
+        self.nmi_pending = false;
+        self.waiting = false;
+        self.in_flight = None;
+        // the reset sequence takes as long as any other interrupt
+        self.tick(7);
+        self.program_counter = self.mem_read_u16(RESET_VECTOR);
+    }
+
+    /// Drives the NMI line. NMI is edge triggered: it is latched when the
+    /// line goes from released to asserted and serviced before the next instruction.
+    pub fn set_nmi(&mut self, asserted: bool) {
+        if asserted && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = asserted;
+        self.waiting &= !asserted;
+    }
+
+    /// Drives the IRQ line. IRQ is level triggered: it is serviced before every
+    /// instruction for as long as the line is asserted and interrupts are not disabled.
+    ///
+    /// Both lines wake a CPU waiting on WAI, an IRQ masked by interrupt disable
+    /// resumes at the instruction after the WAI without being serviced.
+    pub fn set_irq(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+        self.waiting &= !asserted;
+    }
+
+    fn pending_interrupt(&self) -> Option<Interrupt> {
+        if self.nmi_pending {
+            Some(Interrupt::Nmi)
+        } else if self.irq_line && !self.status.contains(CpuFlags::INTERRUPT_DISABLE) {
+            Some(Interrupt::Irq)
+        } else {
+            None
+        }
+    }
+
+    fn interrupt(&mut self, interrupt: Interrupt) {
+        if interrupt == Interrupt::Nmi {
+            self.nmi_pending = false;
+        }
+
+        // hardware interrupts push the status with B cleared
+        self.push_interrupt_frame(self.program_counter, false);
+        self.tick(7);
+        self.program_counter = self.mem_read_u16(interrupt.vector());
+    }
+
+    fn push_interrupt_frame(&mut self, return_address: u16, break_flag: bool) {
+        self.stack_push_u16(return_address);
+        self.push_status_and_mask(break_flag);
+    }
+
+    /// Last push of an interrupt sequence, further IRQs are masked afterwards.
+    fn push_status_and_mask(&mut self, break_flag: bool) {
+        let mut flags = self.status;
+        flags.set(CpuFlags::BREAK, break_flag);
+        flags.insert(CpuFlags::BREAK2);
+        self.stack_push(flags.bits());
+        self.status.insert(CpuFlags::INTERRUPT_DISABLE);
+        if self.variant.clears_decimal_on_interrupt() {
+            self.status.remove(CpuFlags::DECIMAL_MODE);
+        }
+    }
+
+    /// Software interrupt: the byte following BRK is padding, so the return
+    /// address pushed is the BRK address + 2.
+    fn brk(&mut self) {
+        self.push_interrupt_frame(self.program_counter.wrapping_add(1), true);
+        self.program_counter = self.mem_read_u16(IRQ_VECTOR);
+    }
+
+    fn set_carry_flag(&mut self) {
+        self.status.insert(CpuFlags::CARRY)
+    }
+
+    fn clear_carry_flag(&mut self) {
+        self.status.remove(CpuFlags::CARRY)
+    }
+
+    /// Binary mode addition
+    /// http://www.righto.com/2012/12/the-6502-overflow-flag-explained.html
+    fn add_to_register_a(&mut self, data: u8) {
+        let mut sum = self.register_a as u16 + data as u16;
+
+        // Applly carry to sum
+        if self.status.contains(CpuFlags::CARRY) {
+            sum += 1
+        }
+
+        let carry = sum > 0xff; // Determine if we need the carry flag or not
+
+        if carry {
+            self.status.insert(CpuFlags::CARRY);
+        } else {
+            self.status.remove(CpuFlags::CARRY);
+        }
+
+        let result = sum as u8;
+
+        if (data ^ result) & (result ^ self.register_a) & 0x80 != 0 {
+            self.status.insert(CpuFlags::OVERFLOW);
+        } else {
+            self.status.remove(CpuFlags::OVERFLOW)
+        }
+
+        self.set_register_a(result);
+    }
+
+    /// Decimal mode addition, NMOS flavour: Z is taken from the binary sum while
+    /// N and V come from the intermediate result, before the high nibble is adjusted.
+    /// http://www.6502.org/tutorials/decimal_mode.html
+    fn add_decimal_to_register_a(&mut self, data: u8) {
+        let a = self.register_a;
+        let carry = self.status.contains(CpuFlags::CARRY) as u8;
+
+        let mut lo = (a & 0x0f) + (data & 0x0f) + carry;
+        if lo > 0x09 {
+            lo += 0x06;
+        }
+        let mut hi = (a >> 4) + (data >> 4) + (lo > 0x0f) as u8;
+
+        self.status.set(
+            CpuFlags::ZERO,
+            a.wrapping_add(data).wrapping_add(carry) == 0,
+        );
+        self.status.set(CpuFlags::NEGATIV, hi & 0x08 != 0);
+        self.status.set(
+            CpuFlags::OVERFLOW,
+            !(a ^ data) & (a ^ (hi << 4)) & 0x80 != 0,
+        );
+
+        if hi > 0x09 {
+            hi += 0x06;
+        }
+        self.status.set(CpuFlags::CARRY, hi > 0x0f);
+
+        self.register_a = (hi << 4) | (lo & 0x0f);
+    }
+
+    /// Decimal mode subtraction, NMOS flavour: all the flags are the ones the
+    /// binary subtraction would produce, only the result is BCD adjusted.
+    fn sub_decimal_from_register_a(&mut self, data: u8) {
+        let a = self.register_a;
+        let borrow = !self.status.contains(CpuFlags::CARRY) as u8;
+
+        let diff = (a as u16)
+            .wrapping_sub(data as u16)
+            .wrapping_sub(borrow as u16);
+
+        let mut lo = (a & 0x0f).wrapping_sub(data & 0x0f).wrapping_sub(borrow);
+        let lo_borrow = (lo as i8) < 0;
+        if lo_borrow {
+            lo = lo.wrapping_sub(0x06);
+        }
+        let mut hi = (a >> 4)
+            .wrapping_sub(data >> 4)
+            .wrapping_sub(lo_borrow as u8);
+        if (hi as i8) < 0 {
+            hi = hi.wrapping_sub(0x06);
+        }
+
+        self.update_zero_and_negative_flags(diff as u8);
+        self.status.set(
+            CpuFlags::OVERFLOW,
+            (a ^ data) & (a ^ diff as u8) & 0x80 != 0,
+        );
+        self.status.set(CpuFlags::CARRY, diff & 0xff00 == 0);
+
+        self.register_a = (hi << 4) | (lo & 0x0f);
+    }
+
+    fn decimal_mode_active(&self) -> bool {
+        self.variant.has_decimal_mode() && self.status.contains(CpuFlags::DECIMAL_MODE)
+    }
+
+    fn sbc(&mut self, data: u8) {
+        self.sub_from_register_a(data);
+    }
+
+    fn sub_from_register_a(&mut self, data: u8) {
+        if self.decimal_mode_active() {
+            self.sub_decimal_from_register_a(data);
+            self.fix_decimal_flags();
+        } else {
+            self.add_to_register_a(((data as i8).wrapping_neg().wrapping_sub(1)) as u8);
+        }
+    }
+
+    fn adc(&mut self, value: u8) {
+        self.add_with_carry(value);
+    }
+
+    fn add_with_carry(&mut self, value: u8) {
+        if self.decimal_mode_active() {
+            self.add_decimal_to_register_a(value);
+            self.fix_decimal_flags();
+        } else {
+            self.add_to_register_a(value);
+        }
+    }
+
+    /// The 65C02 spends an extra cycle in decimal mode to set N and Z from the BCD result
+    fn fix_decimal_flags(&mut self) {
+        if self.variant.is_cmos() {
+            self.update_zero_and_negative_flags(self.register_a);
+            self.tick(1);
+        }
+    }
+
+    fn stack_pop(&mut self) -> u8 {
+        self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        self.mem_read(STACK + self.stack_pointer as u16)
+    }
+
+    fn stack_push(&mut self, data: u8) {
+        self.mem_write(STACK + self.stack_pointer as u16, data);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1)
+    }
+
+    fn stack_push_u16(&mut self, data: u16) {
+        let hi = (data >> 8) as u8;
+        let lo = (data & 0xff) as u8;
+        self.stack_push(hi);
+        self.stack_push(lo);
+    }
+
+    fn stack_pop_u16(&mut self) -> u16 {
+        let lo = self.stack_pop() as u16;
+        let hi = self.stack_pop() as u16;
+
+        hi << 8 | lo
+    }
+
+    fn asl(&mut self, mut data: u8) -> u8 {
+        if data >> 7 == 1 {
+            self.set_carry_flag();
+        } else {
+            self.clear_carry_flag();
+        }
+        data <<= 1;
+        self.update_zero_and_negative_flags(data);
+        data
+    }
+
+    fn lsr(&mut self, mut data: u8) -> u8 {
+        if data & 1 == 1 {
+            self.set_carry_flag();
+        } else {
+            self.clear_carry_flag();
+        }
+        data >>= 1;
+        self.update_zero_and_negative_flags(data);
+        data
+    }
+
+    fn rol(&mut self, mut data: u8) -> u8 {
+        let old_carry = self.status.contains(CpuFlags::CARRY);
+
+        if data >> 7 == 1 {
+            self.set_carry_flag();
+        } else {
+            self.clear_carry_flag();
+        }
+        data <<= 1;
+        if old_carry {
+            data |= 1;
+        }
+        self.update_zero_and_negative_flags(data);
+        data
+    }
+
+    fn ror(&mut self, mut data: u8) -> u8 {
+        let old_carry = self.status.contains(CpuFlags::CARRY);
+
+        if data & 1 == 1 {
+            self.set_carry_flag();
+        } else {
+            self.clear_carry_flag();
+        }
+        data >>= 1;
+        if old_carry {
+            data |= 0b10000000;
+        }
+        self.update_zero_and_negative_flags(data);
+        data
+    }
+
+    fn inc(&mut self, mut data: u8) -> u8 {
+        data = data.wrapping_add(1);
+        self.update_zero_and_negative_flags(data);
+        data
+    }
+
+    fn dey(&mut self) {
+        self.register_y = self.register_y.wrapping_sub(1);
+        self.update_zero_and_negative_flags(self.register_y);
+    }
+
+    fn dex(&mut self) {
+        self.register_x = self.register_x.wrapping_sub(1);
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+
+    fn dec(&mut self, mut data: u8) -> u8 {
+        data = data.wrapping_sub(1);
+        self.update_zero_and_negative_flags(data);
+        data
+    }
+
+    fn pla(&mut self) {
+        let data = self.stack_pop();
+        self.set_register_a(data);
+    }
+
+    fn plp(&mut self) {
+        self.status.bits = self.stack_pop();
+        self.status.remove(CpuFlags::BREAK);
+        self.status.insert(CpuFlags::BREAK2);
+    }
+
+    fn php(&mut self) {
+        //http://wiki.nesdev.com/w/index.php/CPU_status_flag_behavior
+        let mut flags = self.status;
+        flags.insert(CpuFlags::BREAK);
+        flags.insert(CpuFlags::BREAK2);
+        self.stack_push(flags.bits());
+    }
+
+    fn bit(&mut self, data: u8) {
+        let and = self.register_a & data;
+        if and == 0 {
+            self.status.insert(CpuFlags::ZERO);
+        } else {
+            self.status.remove(CpuFlags::ZERO);
+        }
+
+        self.status.set(CpuFlags::NEGATIV, data & 0b10000000 > 0);
+        self.status.set(CpuFlags::OVERFLOW, data & 0b01000000 > 0);
+    }
+
+    fn compare_value(&mut self, data: u8, compare_with: u8) {
+        if data <= compare_with {
+            self.status.insert(CpuFlags::CARRY);
+        } else {
+            self.status.remove(CpuFlags::CARRY);
+        }
+
+        self.update_zero_and_negative_flags(compare_with.wrapping_sub(data));
+    }
+
+    /* Undocumented instructions http://www.oxyron.de/html/opcodes02.html */
+
+    fn lax(&mut self, data: u8) {
+        self.set_register_a(data);
+        self.register_x = self.register_a;
+    }
+
+    fn dcp(&mut self, data: u8) -> u8 {
+        let data = self.dec(data);
+        self.compare_value(data, self.register_a);
+        data
+    }
+
+    fn isb(&mut self, data: u8) -> u8 {
+        let data = self.inc(data);
+        self.sub_from_register_a(data);
+        data
+    }
+
+    fn slo(&mut self, data: u8) -> u8 {
+        let data = self.asl(data);
+        self.set_register_a(data | self.register_a);
+        data
+    }
+
+    fn rla(&mut self, data: u8) -> u8 {
+        let data = self.rol(data);
+        self.set_register_a(data & self.register_a);
+        data
+    }
+
+    fn sre(&mut self, data: u8) -> u8 {
+        let data = self.lsr(data);
+        self.set_register_a(data ^ self.register_a);
+        data
+    }
+
+    fn rra(&mut self, data: u8) -> u8 {
+        let data = self.ror(data);
+        self.add_with_carry(data);
+        data
+    }
+
+    fn anc(&mut self, data: u8) {
+        self.and(data);
+        self.status
+            .set(CpuFlags::CARRY, self.status.contains(CpuFlags::NEGATIV));
+    }
+
+    fn alr(&mut self, data: u8) {
+        self.and(data);
+        self.register_a = self.lsr(self.register_a);
+    }
+
+    fn arr(&mut self, data: u8) {
+        let data = data & self.register_a;
+        let carry = self.status.contains(CpuFlags::CARRY) as u8;
+        let mut result = (data >> 1) | (carry << 7);
+        self.update_zero_and_negative_flags(result);
+
+        if self.decimal_mode_active() {
+            self.status
+                .set(CpuFlags::OVERFLOW, (data ^ result) & 0x40 != 0);
+            if (data & 0x0f) + (data & 0x01) > 0x05 {
+                result = (result & 0xf0) | (result.wrapping_add(0x06) & 0x0f);
+            }
+            let high_fixup = (data & 0xf0) as u16 + (data & 0x10) as u16 > 0x50;
+            if high_fixup {
+                result = result.wrapping_add(0x60);
+            }
+            self.status.set(CpuFlags::CARRY, high_fixup);
+        } else {
+            self.status.set(CpuFlags::CARRY, result & 0x40 != 0);
+            self.status
+                .set(CpuFlags::OVERFLOW, ((result >> 6) ^ (result >> 5)) & 1 != 0);
+        }
+        self.register_a = result;
+    }
+
+    fn axs(&mut self, data: u8) {
+        let and = self.register_a & self.register_x;
+        self.status.set(CpuFlags::CARRY, data <= and);
+        self.register_x = and.wrapping_sub(data);
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+
+    fn xaa(&mut self, data: u8) {
+        self.set_register_a((self.register_a | UNSTABLE_MAGIC) & self.register_x & data);
+    }
+
+    fn lxa(&mut self, data: u8) {
+        self.set_register_a((self.register_a | UNSTABLE_MAGIC) & data);
+        self.register_x = self.register_a;
+    }
+
+    fn las(&mut self, data: u8) {
+        let data = data & self.stack_pointer;
+        self.set_register_a(data);
+        self.register_x = data;
+        self.stack_pointer = data;
+    }
+
+    /// SHX, SHY, AHX and TAS store `value & (high byte of the base address + 1)`.
+    /// When indexing crosses a page the stored value also replaces the high
+    /// byte of the effective address.
+    fn store_and_high(&mut self, mode: &AddressingMode, addr: u16, page_cross: bool, value: u8) {
+        let index = match mode {
+            AddressingMode::Absolute_X => self.register_x,
+            _ => self.register_y,
+        };
+        let base = addr.wrapping_sub(index as u16);
+        let data = value & ((base >> 8) as u8).wrapping_add(1);
+        let addr = if page_cross {
+            (data as u16) << 8 | (addr & 0x00ff)
+        } else {
+            addr
+        };
+        self.mem_write(addr, data);
+    }
+
+    fn jmp(&mut self, opcode: &opcode::OpCode) -> Result<(), CpuError> {
+        let (addr, _) = self.get_operand_address(&opcode.mode)?;
+        self.program_counter = addr;
+        Ok(())
+    }
+
+    /// Taking a branch costs an extra cycle, landing on another page one more.
+    fn branch(&mut self, mode: &AddressingMode, condition: bool) -> Result<(), CpuError> {
+        if condition {
+            let (target, page_cross) = self.get_operand_address(mode)?;
+            self.tick(1 + page_cross as u8);
+            self.program_counter = target;
+        }
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> Result<Option<BreakpointHit>, CpuError> {
+        self.run_with_callback(|_| true)
+    }
+
+    /// Runs until `callback` returns `false` or, when `stop_on_brk` is set,
+    /// until the next instruction is a BRK. The callback is invoked after every instruction.
+    /// Stops early with the error of the first instruction that fails under the error policy.
+    ///
+    /// Also stops on the first breakpoint that fires and returns it, see [`debug`].
+    pub fn run_with_callback<F>(
+        &mut self,
+        mut callback: F,
+    ) -> Result<Option<BreakpointHit>, CpuError>
+    where
+        F: FnMut(&mut Self) -> bool,
+    {
+        // the instruction a previous run stopped before is executed, not stopped on again
+        let mut resume_at = self.breakpoints.stopped_at.take();
+        loop {
+            let interrupt = self.pending_interrupt();
+            if self.stop_on_brk
+                && interrupt.is_none()
+                && self.mem_peek(self.program_counter) == 0x00
+            {
+                return Ok(None);
+            }
+            // a CPU waiting on WAI has not reached the next instruction yet
+            if interrupt.is_none() && !self.waiting && resume_at != Some(self.program_counter) {
+                if let Some(hit) = self.breakpoint_before() {
+                    self.breakpoints.stopped_at = Some(hit.pc);
+                    return Ok(Some(hit));
+                }
+            }
+            resume_at = None;
+
+            // accesses made outside of run, by a frontend, do not count
+            self.breakpoints.take_watch_hit();
+            let outcome = self.step()?;
+            if let Some((id, access)) = self.breakpoints.take_watch_hit() {
+                return Ok(Some(BreakpointHit {
+                    id,
+                    pc: outcome.pc_before,
+                    access: Some(access),
+                }));
+            }
+
+            if !callback(self) {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Operation of every NMOS opcode, indexed by the opcode
+    const NMOS_OPERATIONS: [Operation<B>; 256] = Self::operation_table(false);
+
+    /// Operation of every 65C02 opcode, indexed by the opcode
+    const CMOS_OPERATIONS: [Operation<B>; 256] = Self::operation_table(true);
+
+    const fn operation_table(cmos: bool) -> [Operation<B>; 256] {
+        let mut table = [Self::nmos_operation(0); 256];
+        let mut code = 0;
+        while code < 256 {
+            table[code] = match Self::cmos_operation(code as u8) {
+                Some(operation) if cmos => operation,
+                _ => Self::nmos_operation(code as u8),
+            };
+            code += 1;
+        }
+        table
+    }
+
+    const fn nmos_operation(code: u8) -> Operation<B> {
+        use Operation::*;
+
+        match code {
+            0xa9 | 0xa5 | 0xb5 | 0xad | 0xbd | 0xb9 | 0xa1 | 0xb1 => Read(Self::lda),
+
+            0xAA => Custom(|cpu, _| {
+                cpu.tax();
+                Ok(())
+            }),
+            0xe8 => Custom(|cpu, _| {
+                cpu.inx();
+                Ok(())
+            }),
+
+            /* BRK */
+            0x00 => Custom(|cpu, _| {
+                cpu.brk();
+                Ok(())
+            }),
+
+            /* CLD */
+            0xd8 => Custom(|cpu, _| {
+                cpu.status.remove(CpuFlags::DECIMAL_MODE);
+                Ok(())
+            }),
+
+            /* CLI */
+            0x58 => Custom(|cpu, _| {
+                cpu.status.remove(CpuFlags::INTERRUPT_DISABLE);
+                Ok(())
+            }),
+
+            /* CLV */
+            0xb8 => Custom(|cpu, _| {
+                cpu.status.remove(CpuFlags::OVERFLOW);
+                Ok(())
+            }),
+
+            /* CLC */
+            0x18 => Custom(|cpu, _| {
+                cpu.clear_carry_flag();
+                Ok(())
+            }),
+
+            /* SEC */
+            0x38 => Custom(|cpu, _| {
+                cpu.set_carry_flag();
+                Ok(())
+            }),
+
+            /* SEI */
+            0x78 => Custom(|cpu, _| {
+                cpu.status.insert(CpuFlags::INTERRUPT_DISABLE);
+                Ok(())
+            }),
+
+            /* SED */
+            0xf8 => Custom(|cpu, _| {
+                cpu.status.insert(CpuFlags::DECIMAL_MODE);
+                Ok(())
+            }),
+
+            /* PHA */
+            0x48 => Custom(|cpu, _| {
+                cpu.stack_push(cpu.register_a);
+                Ok(())
+            }),
+
+            /* PLA */
+            0x68 => Custom(|cpu, _| {
+                cpu.pla();
+                Ok(())
+            }),
+
+            /* PHP */
+            0x08 => Custom(|cpu, _| {
+                cpu.php();
+                Ok(())
+            }),
+
+            /* PLP */
+            0x28 => Custom(|cpu, _| {
+                cpu.plp();
+                Ok(())
+            }),
+
+            /* ADC */
+            0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => Read(Self::adc),
+
+            /* SBC */
+            0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 => Read(Self::sbc),
+
+            /* AND */
+            0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => Read(Self::and),
+
+            /* EOR */
+            0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => Read(Self::eor),
+
+            /* ORA */
+            0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => Read(Self::ora),
+
+            /* LSR */
+            0x4a | 0x46 | 0x56 | 0x4e | 0x5e => Modify(Self::lsr),
+
+            /* ASL */
+            0x0a | 0x06 | 0x16 | 0x0e | 0x1e => Modify(Self::asl),
+
+            /* ROL */
+            0x2a | 0x26 | 0x36 | 0x2e | 0x3e => Modify(Self::rol),
+
+            /* ROR */
+            0x6a | 0x66 | 0x76 | 0x6e | 0x7e => Modify(Self::ror),
+
+            /* INC */
+            0xe6 | 0xf6 | 0xee | 0xfe => Modify(Self::inc),
+
+            /* INY */
+            0xc8 => Custom(|cpu, _| {
+                cpu.iny();
+                Ok(())
+            }),
+
+            /* DEC */
+            0xc6 | 0xd6 | 0xce | 0xde => Modify(Self::dec),
+
+            /* DEX */
+            0xca => Custom(|cpu, _| {
+                cpu.dex();
+                Ok(())
+            }),
+
+            /* DEY */
+            0x88 => Custom(|cpu, _| {
+                cpu.dey();
+                Ok(())
+            }),
+
+            /* CMP */
+            0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => {
+                Read(|cpu, data| cpu.compare_value(data, cpu.register_a))
+            }
+
+            /* CPY */
+            0xc0 | 0xc4 | 0xcc => Read(|cpu, data| cpu.compare_value(data, cpu.register_y)),
+
+            /* CPX */
+            0xe0 | 0xe4 | 0xec => Read(|cpu, data| cpu.compare_value(data, cpu.register_x)),
+
+            /* JMP */
+            0x4c | 0x6c => Custom(Self::jmp),
+
+            /* JSR */
+            0x20 => Custom(|cpu, _| {
+                cpu.stack_push_u16(cpu.program_counter.wrapping_add(1));
+                let target_address = cpu.mem_read_u16(cpu.program_counter);
+                cpu.program_counter = target_address;
+                Ok(())
+            }),
+
+            /* RTS */
+            0x60 => Custom(|cpu, _| {
+                cpu.program_counter = cpu.stack_pop_u16().wrapping_add(1);
+                Ok(())
+            }),
+
+            /* RTI */
+            0x40 => Custom(|cpu, _| {
+                cpu.status.bits = cpu.stack_pop();
+                cpu.status.remove(CpuFlags::BREAK);
+                cpu.status.insert(CpuFlags::BREAK2);
+
+                cpu.program_counter = cpu.stack_pop_u16();
+                Ok(())
+            }),
+
+            /* BNE */
+            0xd0 => Custom(|cpu, op| cpu.branch(&op.mode, !cpu.status.contains(CpuFlags::ZERO))),
+
+            /* BVS */
+            0x70 => Custom(|cpu, op| cpu.branch(&op.mode, cpu.status.contains(CpuFlags::OVERFLOW))),
+
+            /* BVC */
+            0x50 => {
+                Custom(|cpu, op| cpu.branch(&op.mode, !cpu.status.contains(CpuFlags::OVERFLOW)))
+            }
+
+            /* BPL */
+            0x10 => Custom(|cpu, op| cpu.branch(&op.mode, !cpu.status.contains(CpuFlags::NEGATIV))),
+
+            /* BMI */
+            0x30 => Custom(|cpu, op| cpu.branch(&op.mode, cpu.status.contains(CpuFlags::NEGATIV))),
+
+            /* BEQ */
+            0xf0 => Custom(|cpu, op| cpu.branch(&op.mode, cpu.status.contains(CpuFlags::ZERO))),
+
+            /* BCS */
+            0xb0 => Custom(|cpu, op| cpu.branch(&op.mode, cpu.status.contains(CpuFlags::CARRY))),
+
+            /* BCC */
+            0x90 => Custom(|cpu, op| cpu.branch(&op.mode, !cpu.status.contains(CpuFlags::CARRY))),
+
+            /* BIT */
+            0x24 | 0x2c => Read(Self::bit),
+
+            /* STA */
+            0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => Write(|cpu| cpu.register_a),
+
+            /* STX */
+            0x86 | 0x96 | 0x8e => Write(|cpu| cpu.register_x),
+
+            /* STY */
+            0x84 | 0x94 | 0x8c => Write(|cpu| cpu.register_y),
+
+            /* LDX */
+            0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => Read(Self::ldx),
+
+            /* LDY */
+            0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => Read(Self::ldy),
+
+            /* NOP */
+            0xea => Custom(|_, _| {
+                //do nothing
+                Ok(())
+            }),
+
+            /* TAY */
+            0xa8 => Custom(|cpu, _| {
+                cpu.register_y = cpu.register_a;
+                cpu.update_zero_and_negative_flags(cpu.register_y);
+                Ok(())
+            }),
+
+            /* TSX */
+            0xba => Custom(|cpu, _| {
+                cpu.register_x = cpu.stack_pointer;
+                cpu.update_zero_and_negative_flags(cpu.register_x);
+                Ok(())
+            }),
+
+            /* TXA */
+            0x8a => Custom(|cpu, _| {
+                cpu.register_a = cpu.register_x;
+                cpu.update_zero_and_negative_flags(cpu.register_a);
+                Ok(())
+            }),
+
+            /* TXS */
+            0x9a => Custom(|cpu, _| {
+                cpu.stack_pointer = cpu.register_x;
+                Ok(())
+            }),
+
+            /* TYA */
+            0x98 => Custom(|cpu, _| {
+                cpu.register_a = cpu.register_y;
+                cpu.update_zero_and_negative_flags(cpu.register_a);
+                Ok(())
+            }),
+
+            /* LAX */
+            0xa7 | 0xb7 | 0xaf | 0xbf | 0xa3 | 0xb3 => Read(Self::lax),
+
+            /* SAX */
+            0x87 | 0x97 | 0x8f | 0x83 => Write(|cpu| cpu.register_a & cpu.register_x),
+
+            /* DCP */
+            0xc7 | 0xd7 | 0xcf | 0xdf | 0xdb | 0xc3 | 0xd3 => Modify(Self::dcp),
+
+            /* ISB */
+            0xe7 | 0xf7 | 0xef | 0xff | 0xfb | 0xe3 | 0xf3 => Modify(Self::isb),
+
+            /* SLO */
+            0x07 | 0x17 | 0x0f | 0x1f | 0x1b | 0x03 | 0x13 => Modify(Self::slo),
+
+            /* RLA */
+            0x27 | 0x37 | 0x2f | 0x3f | 0x3b | 0x23 | 0x33 => Modify(Self::rla),
+
+            /* SRE */
+            0x47 | 0x57 | 0x4f | 0x5f | 0x5b | 0x43 | 0x53 => Modify(Self::sre),
+
+            /* RRA */
+            0x67 | 0x77 | 0x6f | 0x7f | 0x7b | 0x63 | 0x73 => Modify(Self::rra),
+
+            /* ANC */ 0x0b | 0x2b => Read(Self::anc),
+
+            /* ALR */ 0x4b => Read(Self::alr),
+
+            /* ARR */ 0x6b => Read(Self::arr),
+
+            /* AXS */ 0xcb => Read(Self::axs),
+
+            /* SBC */ 0xeb => Read(Self::sbc),
+
+            /* XAA */ 0x8b => Read(Self::xaa),
+
+            /* LXA */ 0xab => Read(Self::lxa),
+
+            /* LAS */ 0xbb => Read(Self::las),
+
+            /* TAS */
+            0x9b => WriteHigh(|cpu| {
+                cpu.stack_pointer = cpu.register_a & cpu.register_x;
+                cpu.stack_pointer
+            }),
+
+            /* SHY */ 0x9c => WriteHigh(|cpu| cpu.register_y),
+
+            /* SHX */ 0x9e => WriteHigh(|cpu| cpu.register_x),
+
+            /* AHX */
+            0x9f | 0x93 => WriteHigh(|cpu| cpu.register_a & cpu.register_x),
+
+            /* NOP */
+            0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa => Custom(|_, _| Ok(())),
+
+            /* NOP with an operand, which is still read from the bus */
+            0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 | 0x04 | 0x44 | 0x64 | 0x14 | 0x34 | 0x54 | 0x74
+            | 0xd4 | 0xf4 | 0x0c | 0x1c | 0x3c | 0x5c | 0x7c | 0xdc | 0xfc => Read(|_, _| {}),
+
+            /* JAM */
+            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2 | 0xf2 => {
+                Custom(|cpu, op| {
+                    Err(CpuError::Jammed {
+                        opcode: op.code,
+                        address: cpu.program_counter.wrapping_sub(1),
+                    })
+                })
+            }
+        }
+    }
+
+    /// Operations of the instructions that are new or behave differently on the 65C02.
+    /// Returns None for the ones shared with the NMOS 6502.
+    const fn cmos_operation(code: u8) -> Option<Operation<B>> {
+        use Operation::*;
+
+        let operation = match code {
+            /* BRA */
+            0x80 => Custom(|cpu, op| cpu.branch(&op.mode, true)),
+
+            /* PHX */
+            0xda => Custom(|cpu, _| {
+                cpu.stack_push(cpu.register_x);
+                Ok(())
+            }),
+
+            /* PHY */
+            0x5a => Custom(|cpu, _| {
+                cpu.stack_push(cpu.register_y);
+                Ok(())
+            }),
+
+            /* PLX */
+            0xfa => Custom(|cpu, _| {
+                cpu.register_x = cpu.stack_pop();
+                cpu.update_zero_and_negative_flags(cpu.register_x);
+                Ok(())
+            }),
+
+            /* PLY */
+            0x7a => Custom(|cpu, _| {
+                cpu.register_y = cpu.stack_pop();
+                cpu.update_zero_and_negative_flags(cpu.register_y);
+                Ok(())
+            }),
+
+            /* STZ */
+            0x64 | 0x74 | 0x9c | 0x9e => Write(|_| 0),
+
+            /* TSB */
+            0x04 | 0x0c => Modify(|cpu, data| {
+                cpu.status.set(CpuFlags::ZERO, data & cpu.register_a == 0);
+                data | cpu.register_a
+            }),
+
+            /* TRB */
+            0x14 | 0x1c => Modify(|cpu, data| {
+                cpu.status.set(CpuFlags::ZERO, data & cpu.register_a == 0);
+                data & !cpu.register_a
+            }),
+
+            /* INC A */
+            0x1a => Modify(Self::inc),
+
+            /* DEC A */
+            0x3a => Modify(Self::dec),
+
+            /* BIT immediate only affects Z */
+            0x89 => Read(|cpu, data| cpu.status.set(CpuFlags::ZERO, data & cpu.register_a == 0)),
+
+            /* BIT */
+            0x34 | 0x3c => Read(Self::bit),
+
+            /* ORA */ 0x12 => Read(Self::ora),
+
+            /* AND */ 0x32 => Read(Self::and),
+
+            /* EOR */ 0x52 => Read(Self::eor),
+
+            /* ADC */ 0x72 => Read(Self::adc),
+
+            /* STA */ 0x92 => Write(|cpu| cpu.register_a),
+
+            /* LDA */ 0xb2 => Read(Self::lda),
+
+            /* CMP */ 0xd2 => Read(|cpu, data| cpu.compare_value(data, cpu.register_a)),
+
+            /* SBC */ 0xf2 => Read(Self::sbc),
+
+            /* JMP */
+            0x7c => Custom(Self::jmp),
+
+            /* RMB */
+            0x07 | 0x17 | 0x27 | 0x37 | 0x47 | 0x57 | 0x67 | 0x77 => Custom(|cpu, op| {
+                let (addr, _) = cpu.get_operand_address(&op.mode)?;
+                let data = cpu.mem_read(addr);
+                cpu.mem_write(addr, data & !(1 << (op.code >> 4)));
+                Ok(())
+            }),
+
+            /* SMB */
+            0x87 | 0x97 | 0xa7 | 0xb7 | 0xc7 | 0xd7 | 0xe7 | 0xf7 => Custom(|cpu, op| {
+                let (addr, _) = cpu.get_operand_address(&op.mode)?;
+                let data = cpu.mem_read(addr);
+                cpu.mem_write(addr, data | (1 << ((op.code >> 4) - 8)));
+                Ok(())
+            }),
+
+            /* BBR, BBS */
+            0x0f | 0x1f | 0x2f | 0x3f | 0x4f | 0x5f | 0x6f | 0x7f | 0x8f | 0x9f | 0xaf | 0xbf
+            | 0xcf | 0xdf | 0xef | 0xff => Custom(|cpu, op| {
+                let (addr, _) = cpu.get_operand_address(&op.mode)?;
+                let data = cpu.mem_read(addr);
+                let bit_set = data & (1 << ((op.code >> 4) & 0x07)) != 0;
+                let branch_if_set = op.code & 0x80 != 0;
+
+                if bit_set == branch_if_set {
+                    // the offset follows the zero page operand
+                    cpu.program_counter = cpu.program_counter.wrapping_add(1);
+                    cpu.branch(&AddressingMode::Relative, true)?;
+                }
+                Ok(())
+            }),
+
+            /* WAI */
+            0xcb => Custom(|cpu, _| {
+                // the CPU idles until an interrupt line is asserted, see `wait_cycle`
+                cpu.waiting = !(cpu.nmi_pending || cpu.irq_line);
+                Ok(())
+            }),
+
+            /* STP */
+            0xdb => Custom(|cpu, _| {
+                Err(CpuError::Stopped {
+                    address: cpu.program_counter.wrapping_sub(1),
+                })
+            }),
+
+            /* Reserved opcodes, NOPs that still read their operand */
+            0x02 | 0x22 | 0x42 | 0x62 | 0x82 | 0xc2 | 0xe2 | 0x44 | 0x54 | 0xd4 | 0xf4 | 0x5c
+            | 0xdc | 0xfc => Read(|_, _| {}),
+
+            /* Reserved opcodes in the x3 and xB columns */
+            _ if code & 0x07 == 0x03 => Custom(|_, _| Ok(())),
+
+            _ => return None,
+        };
+        Some(operation)
+    }
+
+    /// Decodes and executes exactly one instruction.
+    ///
+    /// An instruction the CPU can not execute is handled according to
+    /// `error_policy`: by default the error is returned and the program
+    /// counter is left on the faulting instruction.
+    pub fn step(&mut self) -> Result<StepOutcome, CpuError> {
+        // an instruction started by `clock` runs to completion first
+        while self.in_flight.is_some() {
+            if let Some(outcome) = self.clock()? {
+                return Ok(outcome);
+            }
+        }
+        if self.waiting {
+            return Ok(self.wait_cycle());
+        }
+
+        let cycles_before = self.cycles;
+        let stack_before = self.stack_pointer;
+        let interrupt = self.pending_interrupt();
+        if let Some(interrupt) = interrupt {
+            self.interrupt(interrupt);
+        }
+
+        self.trace_instruction()?;
+        let pc_before = self.program_counter;
+        let code = self.mem_read(self.program_counter);
+        self.program_counter = self.program_counter.wrapping_add(1);
+        self.execute(code, pc_before)?;
+
+        let outcome = StepOutcome {
+            interrupt,
+            opcode: code,
+            cycles: (self.cycles - cycles_before) as u8,
+            pc_before,
+            pc_after: self.program_counter,
+        };
+        self.profile(&outcome, stack_before);
+        Ok(outcome)
+    }
+
+    /// Idles for one cycle while the CPU waits on WAI. The program counter is
+    /// already past the WAI, which the outcome reports as its opcode.
+    fn wait_cycle(&mut self) -> StepOutcome {
+        self.tick(1);
+        StepOutcome {
+            interrupt: None,
+            opcode: 0xcb,
+            cycles: 1,
+            pc_before: self.program_counter,
+            pc_after: self.program_counter,
+        }
+    }
+
+    /// Writes a line in the nestest.log layout to `tracer` before every
+    /// instruction, see [`trace::trace`]. `None` stops tracing.
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn io::Write>>) {
+        self.tracer = tracer;
+    }
+
+    /// Starts recording where the cycles go, see [`profile::Profiler`].
+    /// `None` stops profiling.
+    pub fn set_profiler(&mut self, profiler: Option<profile::Profiler>) {
+        self.profiler = profiler;
+    }
+
+    pub fn profiler(&self) -> Option<&profile::Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Stops profiling and returns the profile
+    pub fn take_profiler(&mut self) -> Option<profile::Profiler> {
+        self.profiler.take()
+    }
+
+    fn profile(&mut self, outcome: &StepOutcome, stack_before: u8) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(outcome, stack_before, self.stack_pointer);
+        }
+    }
+
+    fn trace_instruction(&mut self) -> Result<(), CpuError> {
+        let mut tracer = match self.tracer.take() {
+            Some(tracer) => tracer,
+            None => return Ok(()),
+        };
+        let result = writeln!(tracer, "{}", trace::trace(self));
+        self.tracer = Some(tracer);
+        result.map_err(|e| CpuError::Trace {
+            address: self.program_counter,
+            message: e.to_string(),
+        })
+    }
+
+    /// Executes the instruction whose opcode was just fetched and moves the
+    /// program counter past it.
+    fn execute(&mut self, code: u8, pc_before: u16) -> Result<(), CpuError> {
+        let program_counter_state = self.program_counter;
+
+        match self.decode_and_execute(code, pc_before) {
+            Ok(len) => {
+                if program_counter_state == self.program_counter {
+                    self.program_counter = self.program_counter.wrapping_add((len - 1) as u16);
+                }
+                Ok(())
+            }
+            Err(error) => self.handle_error(error, code, pc_before),
+        }
+    }
+
+    /// Executes `code` and returns the length of the instruction.
+    fn decode_and_execute(&mut self, code: u8, pc_before: u16) -> Result<u8, CpuError> {
+        let opcode = match self.variant.decode(code) {
+            Some(opcode) => opcode,
+            None => {
+                return Err(CpuError::IllegalOpcode {
+                    opcode: code,
+                    address: pc_before,
+                })
+            }
+        };
+        self.tick(opcode.cycles);
+
+        match self.operation(code) {
+            Operation::Read(op) => {
+                let addr = self.get_read_operand_address(&opcode.mode)?;
+                let data = self.mem_read(addr);
+                op(self, data);
+            }
+            Operation::Write(op) => {
+                let (addr, _) = self.get_operand_address(&opcode.mode)?;
+                let data = op(self);
+                self.mem_write(addr, data);
+            }
+            Operation::Modify(op) if opcode.mode == AddressingMode::Accumulator => {
+                let data = self.register_a;
+                self.register_a = op(self, data);
+            }
+            Operation::Modify(op) => {
+                let (addr, _) = self.get_operand_address(&opcode.mode)?;
+                let data = self.mem_read(addr);
+                let result = op(self, data);
+                self.mem_write(addr, result);
+            }
+            Operation::WriteHigh(op) => {
+                let (addr, page_cross) = self.get_operand_address(&opcode.mode)?;
+                let value = op(self);
+                self.store_and_high(&opcode.mode, addr, page_cross, value);
+            }
+            Operation::Custom(handler) => handler(self, opcode)?,
+        }
+
+        Ok(opcode.len)
+    }
+
+    fn operation(&self, code: u8) -> Operation<B> {
+        if self.variant.is_cmos() {
+            Self::CMOS_OPERATIONS[code as usize]
+        } else {
+            Self::NMOS_OPERATIONS[code as usize]
+        }
+    }
+
+    /// Applies `error_policy` to an instruction that failed to execute.
+    fn handle_error(&mut self, error: CpuError, code: u8, pc_before: u16) -> Result<(), CpuError> {
+        self.program_counter = pc_before;
+
+        // the handler gets the CPU itself, so the policy is taken out while it runs
+        let mut policy = std::mem::replace(&mut self.error_policy, ErrorPolicy::Halt);
+        let result = match &mut policy {
+            ErrorPolicy::Halt => Err(error),
+            ErrorPolicy::Nop => {
+                let len = self.variant.decode(code).map_or(1, |opcode| opcode.len);
+                self.program_counter = pc_before.wrapping_add(len as u16);
+                Ok(())
+            }
+            ErrorPolicy::Trap(handler) => handler(self, &error),
+        };
+        self.error_policy = policy;
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_0xa9_lda_immediate_load_data() {
+        let mut cpu = CPU::new();
+        cpu.stop_on_brk = true;
+        cpu.load_and_run(vec![0xa9, 0x05, 0x00]).unwrap();
+        assert_eq!(cpu.register_a, 5);
+        assert!(!cpu.status.contains(CpuFlags::ZERO));
+        assert!(!cpu.status.contains(CpuFlags::NEGATIV));
+    }
+
+    #[test]
+    fn test_step_executes_one_instruction() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xa9, 0x05, 0xaa, 0x00]);
+        cpu.reset();
+
+        let outcome = cpu.step().unwrap();
+        assert_eq!(outcome.opcode, 0xa9);
+        assert_eq!(outcome.cycles, 2);
+        assert_eq!(outcome.pc_before, 0x0600);
+        assert_eq!(outcome.pc_after, 0x0602);
+        assert_eq!(cpu.register_a, 5);
+        assert_eq!(cpu.register_x, 0);
+    }
+
+    #[test]
+    fn test_run_with_callback_stops_when_asked() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xe8, 0xe8, 0xe8, 0x00]);
+        cpu.reset();
+
+        let mut executed = 0;
+        cpu.run_with_callback(|_| {
+            executed += 1;
+            executed < 2
+        })
+        .unwrap();
+        assert_eq!(cpu.register_x, 2);
+    }
+
+    #[test]
+    fn test_cycles_page_cross_penalty() {
+        let mut cpu = CPU::new();
+        // LDA $10FF,X with X = 1 crosses into page $11
+        cpu.load(vec![0xa2, 0x01, 0xbd, 0xff, 0x10, 0x00]);
+        cpu.reset();
+
+        assert_eq!(cpu.step().unwrap().cycles, 2);
+        assert_eq!(cpu.step().unwrap().cycles, 5);
+    }
+
+    #[test]
+    fn test_cycles_branch_penalty() {
+        let mut cpu = CPU::new();
+        // BNE not taken, then BEQ taken within the page
+        cpu.load(vec![0xa9, 0x00, 0xd0, 0x02, 0xf0, 0x00, 0x00]);
+        cpu.reset();
+
+        let start = cpu.cycles;
+        cpu.step().unwrap();
+        assert_eq!(cpu.step().unwrap().cycles, 2);
+        assert_eq!(cpu.step().unwrap().cycles, 3);
+        assert_eq!(cpu.cycles - start, 7);
+    }
+
+    #[test]
+    fn test_nmi_is_serviced_before_next_instruction() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xe8, 0xe8, 0x00]);
+        cpu.reset();
+        cpu.mem_write_u16(NMI_VECTOR, 0x0700);
+        cpu.mem_write(0x0700, 0xc8);
+        cpu.status.insert(CpuFlags::CARRY);
+
+        cpu.step().unwrap();
+        cpu.set_nmi(true);
+        let outcome = cpu.step().unwrap();
+
+        assert_eq!(outcome.interrupt, Some(Interrupt::Nmi));
+        assert_eq!(outcome.pc_before, 0x0700);
+        assert_eq!(outcome.cycles, 7 + 2);
+        assert_eq!(cpu.register_y, 1);
+        assert!(cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
+        assert_eq!(cpu.stack_pointer, STACK_RESET.wrapping_sub(3));
+        assert_eq!(cpu.mem_read_u16(0x01fc), 0x0601);
+        assert_eq!(cpu.mem_read(0x01fb), 0b0010_0101);
+
+        // still asserted, but edge triggered
+        assert_eq!(cpu.step().unwrap().interrupt, None);
+    }
+
+    #[test]
+    fn test_irq_is_masked_by_interrupt_disable() {
+        let mut cpu = CPU::new();
+        // SEI, NOP, CLI, NOP
+        cpu.load(vec![0x78, 0xea, 0x58, 0xea, 0x00]);
+        cpu.reset();
+        cpu.mem_write_u16(IRQ_VECTOR, 0x0700);
+        cpu.mem_write(0x0700, 0xea);
+
+        cpu.status.remove(CpuFlags::INTERRUPT_DISABLE);
+        cpu.step().unwrap();
+        cpu.set_irq(true);
+        assert_eq!(cpu.step().unwrap().interrupt, None);
+        assert_eq!(cpu.step().unwrap().interrupt, None);
+
+        let outcome = cpu.step().unwrap();
+        assert_eq!(outcome.interrupt, Some(Interrupt::Irq));
+        assert_eq!(cpu.mem_read_u16(0x01fc), 0x0603);
+    }
+
+    #[test]
+    fn test_brk_vectors_through_irq_vector() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x00, 0xff, 0xe8]);
+        cpu.reset();
+        cpu.mem_write_u16(IRQ_VECTOR, 0x0700);
+        // RTI
+        cpu.mem_write(0x0700, 0x40);
+
+        let outcome = cpu.step().unwrap();
+        assert_eq!(outcome.cycles, 7);
+        assert_eq!(cpu.program_counter, 0x0700);
+        assert!(cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
+        assert_eq!(cpu.mem_read_u16(0x01fc), 0x0602);
+        assert_eq!(cpu.mem_read(0x01fb), 0b0011_0100);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0602);
+        assert!(!cpu.status.contains(CpuFlags::BREAK));
+    }
+
+    #[test]
+    fn test_stop_on_brk() {
+        let mut cpu = CPU::new();
+        cpu.stop_on_brk = true;
+        cpu.load_and_run(vec![0xe8, 0x00, 0xe8]).unwrap();
+        assert_eq!(cpu.register_x, 1);
+        assert_eq!(cpu.program_counter, 0x0601);
+    }
+
+    #[test]
+    fn test_adc_decimal_mode() {
+        let mut cpu = CPU::new();
+        cpu.stop_on_brk = true;
+        // SED, SEC, LDA #$58, ADC #$46
+        cpu.load_and_run(vec![0xf8, 0x38, 0xa9, 0x58, 0x69, 0x46, 0x00])
+            .unwrap();
+        assert_eq!(cpu.register_a, 0x05);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+    }
+
+    #[test]
+    fn test_sbc_decimal_mode() {
+        let mut cpu = CPU::new();
+        cpu.stop_on_brk = true;
+        // SED, CLC, LDA #$40, SBC #$13
+        cpu.load_and_run(vec![0xf8, 0x18, 0xa9, 0x40, 0xe9, 0x13, 0x00])
+            .unwrap();
+        assert_eq!(cpu.register_a, 0x26);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+    }
+
+    #[test]
+    fn test_decimal_mode_disabled() {
+        let mut cpu = CPU::with_variant(CpuVariant::Ricoh2A03);
+        cpu.stop_on_brk = true;
+        // SED, CLC, LDA #$09, ADC #$01
+        cpu.load_and_run(vec![0xf8, 0x18, 0xa9, 0x09, 0x69, 0x01, 0x00])
+            .unwrap();
+        assert_eq!(cpu.register_a, 0x0a);
+    }
+
+    #[test]
+    fn test_opcode_table_covers_every_byte() {
+        let mut seen = [false; 256];
+        for op in opcode::CPU_OPS_CODES.iter() {
+            assert!(!seen[op.code as usize], "duplicate opcode {:02x}", op.code);
+            seen[op.code as usize] = true;
+        }
+
+        for variant in [CpuVariant::Nmos6502, CpuVariant::Wdc65C02].iter() {
+            assert_eq!(variant.opcodes().count(), 256);
+            for code in 0..=255u8 {
+                let op = variant.decode(code).unwrap();
+                assert_eq!(op.code, code);
+                assert_eq!(op.len, 1 + op.mode.operand_len(), "opcode {:02x}", code);
+            }
+        }
+    }
+
+    #[test]
+    fn test_lax_and_sax() {
+        let mut cpu = CPU::new();
+        cpu.stop_on_brk = true;
+        cpu.mem_write(0x10, 0x8f);
+        // LAX $10, LDA #$f0, SAX $11
+        cpu.load_and_run(vec![0xa7, 0x10, 0xa9, 0xf0, 0x87, 0x11, 0x00])
+            .unwrap();
+        assert_eq!(cpu.register_x, 0x8f);
+        assert_eq!(cpu.mem_read(0x11), 0x80);
+    }
+
+    #[test]
+    fn test_dcp_and_isb() {
+        let mut cpu = CPU::new();
+        cpu.stop_on_brk = true;
+        cpu.mem_write(0x10, 0x06);
+        cpu.mem_write(0x11, 0x01);
+        // LDA #$05, DCP $10, SEC, ISB $11
+        cpu.load_and_run(vec![0xa9, 0x05, 0xc7, 0x10, 0x38, 0xe7, 0x11, 0x00])
+            .unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0x05);
+        assert_eq!(cpu.mem_read(0x11), 0x02);
+        assert_eq!(cpu.register_a, 0x03);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+    }
+
+    #[test]
+    fn test_slo_and_multi_byte_nop() {
+        let mut cpu = CPU::new();
+        cpu.stop_on_brk = true;
+        cpu.mem_write(0x10, 0x81);
+        // LDA #$10, SLO $10, NOP $1234,X, NOP #$ff
+        cpu.load_and_run(vec![
+            0xa9, 0x10, 0x07, 0x10, 0x1c, 0x34, 0x12, 0x80, 0xff, 0x00,
+        ])
+        .unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0x02);
+        assert_eq!(cpu.register_a, 0x12);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+        assert_eq!(cpu.program_counter, 0x0609);
+    }
+
+    #[test]
+    fn test_65c02_instructions() {
+        let mut cpu = CPU::with_variant(CpuVariant::Wdc65C02);
+        cpu.stop_on_brk = true;
+        cpu.mem_write(0x20, 0x55);
+        cpu.mem_write_u16(0x10, 0x0020);
+        // LDA ($10), STZ $20, LDX #$07, PHX, PLY, BRA +1, INC A (skipped), DEC A
+        cpu.load_and_run(vec![
+            0xb2, 0x10, 0x64, 0x20, 0xa2, 0x07, 0xda, 0x7a, 0x80, 0x01, 0x1a, 0x3a, 0x00,
+        ])
+        .unwrap();
+        assert_eq!(cpu.register_a, 0x54);
+        assert_eq!(cpu.register_y, 0x07);
+        assert_eq!(cpu.mem_read(0x20), 0x00);
+    }
+
+    #[test]
+    fn test_wai_services_irq_and_resumes_after_it() {
+        let mut cpu = CPU::with_variant(CpuVariant::Wdc65C02);
+        // CLI, WAI, INX
+        cpu.load(vec![0x58, 0xcb, 0xe8, 0x00]);
+        cpu.reset();
+        cpu.mem_write_u16(IRQ_VECTOR, 0x0700);
+        // RTI
+        cpu.mem_write(0x0700, 0x40);
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0602);
+        for _ in 0..3 {
+            let outcome = cpu.step().unwrap();
+            assert_eq!(outcome.cycles, 1);
+            assert_eq!(outcome.pc_after, 0x0602);
+        }
+
+        cpu.set_irq(true);
+        let outcome = cpu.step().unwrap();
+        assert_eq!(outcome.interrupt, Some(Interrupt::Irq));
+        assert_eq!(cpu.mem_read_u16(0x01fc), 0x0602);
+        cpu.set_irq(false);
+
+        let outcome = cpu.step().unwrap();
+        assert_eq!(outcome.opcode, 0xe8);
+        assert_eq!(cpu.register_x, 1);
+        assert_eq!(cpu.program_counter, 0x0603);
+    }
+
+    #[test]
+    fn test_wai_with_interrupts_disabled_resumes_without_servicing() {
+        let mut cpu = CPU::with_variant(CpuVariant::Wdc65C02);
+        // SEI, WAI, INX
+        cpu.load(vec![0x78, 0xcb, 0xe8, 0x00]);
+        cpu.reset();
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.clock().unwrap().unwrap().cycles, 1);
+        assert_eq!(cpu.program_counter, 0x0602);
+
+        cpu.set_irq(true);
+        let outcome = cpu.step().unwrap();
+        assert_eq!(outcome.interrupt, None);
+        assert_eq!(outcome.opcode, 0xe8);
+        assert_eq!(cpu.register_x, 1);
+        assert_eq!(cpu.stack_pointer, STACK_RESET);
+    }
+
+    #[test]
+    fn test_jsr_rts_wrap_around_address_space() {
+        let mut cpu = CPU::new();
+        // JSR $0600 at $FFFE, its operand wraps to $0000
+        cpu.mem_write(0xfffe, 0x20);
+        cpu.mem_write(0xffff, 0x00);
+        cpu.mem_write(0x0000, 0x06);
+        cpu.mem_write(0x0600, 0x60);
+        cpu.program_counter = 0xfffe;
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0600);
+        assert_eq!(cpu.mem_read_u16(0x01fc), 0x0000);
+
+        // RTS returns past the operand, to $0001
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0001);
+
+        // a return address of $FFFF wraps to $0000
+        cpu.program_counter = 0x0600;
+        cpu.stack_pointer = 0xfb;
+        cpu.mem_write_u16(0x01fc, 0xffff);
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0000);
+    }
+
+    #[test]
+    fn test_flags_display() {
+        let flags = CpuFlags::from_bits_truncate(0b1010_0101);
+        assert_eq!(flags.to_string(), "Nv-bdIzC");
+    }
+
+    #[test]
+    fn test_parse_variant() {
+        assert_eq!("6502".parse(), Ok(CpuVariant::Nmos6502));
+        assert_eq!("2A03".parse(), Ok(CpuVariant::Ricoh2A03));
+        assert_eq!("65c02".parse(), Ok(CpuVariant::Wdc65C02));
+        let error = "z80".parse::<CpuVariant>().unwrap_err();
+        assert_eq!(error.to_string(), "unknown variant z80");
+    }
+
+    #[test]
+    fn test_jmp_indirect_page_wrap() {
+        let program = vec![0x6c, 0xff, 0x30];
+        let mut nmos = CPU::new();
+        let mut cmos = CPU::with_variant(CpuVariant::Wdc65C02);
+
+        for cpu in [&mut nmos, &mut cmos].iter_mut() {
+            cpu.load(program.clone());
+            cpu.reset();
+            cpu.mem_write(0x3000, 0x40);
+            cpu.mem_write(0x30ff, 0x80);
+            cpu.mem_write(0x3100, 0x50);
+            cpu.step().unwrap();
+        }
+
+        assert_eq!(nmos.program_counter, 0x4080);
+        assert_eq!(cmos.program_counter, 0x5080);
+    }
+
+    #[test]
+    fn test_65c02_decimal_flags() {
+        let mut cpu = CPU::with_variant(CpuVariant::Wdc65C02);
+        cpu.stop_on_brk = true;
+        // SED, CLC, LDA #$99, ADC #$01
+        cpu.load_and_run(vec![0xf8, 0x18, 0xa9, 0x99, 0x69, 0x01, 0x00])
+            .unwrap();
+        assert_eq!(cpu.register_a, 0x00);
+        assert!(cpu.status.contains(CpuFlags::ZERO));
+        assert!(!cpu.status.contains(CpuFlags::NEGATIV));
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+    }
+
+    #[test]
+    fn test_jam_halts_with_error() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xe8, 0x02, 0xe8]);
+        cpu.reset();
+
+        let error = cpu.run().unwrap_err();
+        assert_eq!(
+            error,
+            CpuError::Jammed {
+                opcode: 0x02,
+                address: 0x0601
+            }
+        );
+        assert_eq!(cpu.program_counter, 0x0601);
+        assert_eq!(cpu.register_x, 1);
+    }
+
+    #[test]
+    fn test_error_policy_nop_skips_instruction() {
+        let mut cpu = CPU::new();
+        cpu.stop_on_brk = true;
+        cpu.error_policy = ErrorPolicy::Nop;
+        cpu.load_and_run(vec![0xe8, 0x02, 0xe8, 0x00]).unwrap();
+        assert_eq!(cpu.register_x, 2);
+    }
+
+    #[test]
+    fn test_error_policy_trap_calls_handler() {
+        let mut cpu = CPU::with_variant(CpuVariant::Wdc65C02);
+        cpu.stop_on_brk = true;
+        // STP is used as a system call that loads A with $42
+        cpu.error_policy = ErrorPolicy::Trap(Box::new(|cpu, error| match error {
+            CpuError::Stopped { address } => {
+                cpu.register_a = 0x42;
+                cpu.program_counter = address + 1;
+                Ok(())
+            }
+            _ => Err(error.clone()),
+        }));
+        cpu.load_and_run(vec![0xdb, 0xaa, 0x00]).unwrap();
+        assert_eq!(cpu.register_x, 0x42);
+    }
+}
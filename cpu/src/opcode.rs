@@ -1,233 +1,511 @@
-use crate::AddressingMode;
-use std::collections::HashMap;
-
-use lazy_static::lazy_static;
-
-#[derive(Debug)]
-pub struct OpCode {
-    pub code: u8,
-    pub mnemonic: &'static str,
-    pub len: u8,
-    pub cycles: u8,
-    pub mode: AddressingMode,
-}
-
-impl OpCode {
-    fn new(code: u8, mnemonic: &'static str, len: u8, cycles: u8, mode: AddressingMode) -> Self {
-        OpCode {
-            code: code,
-            mnemonic: mnemonic,
-            len: len,
-            cycles: cycles,
-            mode: mode,
-        }
-    }
-}
-
-lazy_static! {
-    pub static ref CPU_OPS_CODES: Vec<OpCode> = vec![
-        OpCode::new(0x00, "BRK", 1, 7, AddressingMode::NoneAddressing),
-        OpCode::new(0xea, "NOP", 1, 2, AddressingMode::NoneAddressing),
-
-        /* Arithmetic */
-        OpCode::new(0x69, "ADC", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x65, "ADC", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x75, "ADC", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x6d, "ADC", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x7d, "ADC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0x79, "ADC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0x61, "ADC", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x71, "ADC", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0xe9, "SBC", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xe5, "SBC", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xf5, "SBC", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0xed, "SBC", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xfd, "SBC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0xf9, "SBC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0xe1, "SBC", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0xf1, "SBC", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0x29, "AND", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x25, "AND", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x35, "AND", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x2d, "AND", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x3d, "AND", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0x39, "AND", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0x21, "AND", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x31, "AND", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0x49, "EOR", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x45, "EOR", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x55, "EOR", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x4d, "EOR", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x5d, "EOR", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0x59, "EOR", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0x41, "EOR", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x51, "EOR", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0x09, "ORA", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x05, "ORA", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x15, "ORA", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x0d, "ORA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x1d, "ORA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0x19, "ORA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0x01, "ORA", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x11, "ORA", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        /* Shifts */
-        OpCode::new(0x0a, "ASL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x06, "ASL", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x16, "ASL", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0x0e, "ASL", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x1e, "ASL", 3, 7, AddressingMode::Absolute_X),
-
-        OpCode::new(0x4a, "LSR", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x46, "LSR", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x56, "LSR", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0x4e, "LSR", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x5e, "LSR", 3, 7, AddressingMode::Absolute_X),
-
-        OpCode::new(0x2a, "ROL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x26, "ROL", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x36, "ROL", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0x2e, "ROL", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x3e, "ROL", 3, 7, AddressingMode::Absolute_X),
-
-        OpCode::new(0x6a, "ROR", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x66, "ROR", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x76, "ROR", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0x6e, "ROR", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x7e, "ROR", 3, 7, AddressingMode::Absolute_X),
-
-        OpCode::new(0xe6, "INC", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0xf6, "INC", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0xee, "INC", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0xfe, "INC", 3, 7, AddressingMode::Absolute_X),
-
-        OpCode::new(0xe8, "INX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xc8, "INY", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xc6, "DEC", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0xd6, "DEC", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0xce, "DEC", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0xde, "DEC", 3, 7, AddressingMode::Absolute_X),
-
-        OpCode::new(0xca, "DEX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x88, "DEY", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xc9, "CMP", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xc5, "CMP", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xd5, "CMP", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0xcd, "CMP", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xdd, "CMP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0xd9, "CMP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0xc1, "CMP", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0xd1, "CMP", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0xc0, "CPY", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xc4, "CPY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xcc, "CPY", 3, 4, AddressingMode::Absolute),
-
-        OpCode::new(0xe0, "CPX", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xe4, "CPX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xec, "CPX", 3, 4, AddressingMode::Absolute),
-
-
-        /* Branching */
-
-        OpCode::new(0x4c, "JMP", 3, 3, AddressingMode::NoneAddressing), //AddressingMode that acts as Immidiate
-        OpCode::new(0x6c, "JMP", 3, 5, AddressingMode::NoneAddressing), //AddressingMode:Indirect with 6502 bug
-
-        OpCode::new(0x20, "JSR", 3, 6, AddressingMode::NoneAddressing),
-        OpCode::new(0x60, "RTS", 1, 6, AddressingMode::NoneAddressing),
-
-        OpCode::new(0x40, "RTI", 1, 6, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xd0, "BNE", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x70, "BVS", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x50, "BVC", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x30, "BMI", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0xf0, "BEQ", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0xb0, "BCS", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x90, "BCC", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x10, "BPL", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-
-        OpCode::new(0x24, "BIT", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x2c, "BIT", 3, 4, AddressingMode::Absolute),
-
-
-        /* Stores, Loads */
-        OpCode::new(0xa9, "LDA", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xa5, "LDA", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xb5, "LDA", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0xad, "LDA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xbd, "LDA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0xb9, "LDA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0xa1, "LDA", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0xb1, "LDA", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0xa2, "LDX", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xa6, "LDX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xb6, "LDX", 2, 4, AddressingMode::ZeroPage_Y),
-        OpCode::new(0xae, "LDX", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xbe, "LDX", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-
-        OpCode::new(0xa0, "LDY", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xa4, "LDY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xb4, "LDY", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0xac, "LDY", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xbc, "LDY", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-
-
-        OpCode::new(0x85, "STA", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x95, "STA", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x8d, "STA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x9d, "STA", 3, 5, AddressingMode::Absolute_X),
-        OpCode::new(0x99, "STA", 3, 5, AddressingMode::Absolute_Y),
-        OpCode::new(0x81, "STA", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x91, "STA", 2, 6, AddressingMode::Indirect_Y),
-
-        OpCode::new(0x86, "STX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x96, "STX", 2, 4, AddressingMode::ZeroPage_Y),
-        OpCode::new(0x8e, "STX", 3, 4, AddressingMode::Absolute),
-
-        OpCode::new(0x84, "STY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x94, "STY", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x8c, "STY", 3, 4, AddressingMode::Absolute),
-
-
-        /* Flags clear */
-
-        OpCode::new(0xD8, "CLD", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x58, "CLI", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xb8, "CLV", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x18, "CLC", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x38, "SEC", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x78, "SEI", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xf8, "SED", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xaa, "TAX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xa8, "TAY", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xba, "TSX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x8a, "TXA", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x9a, "TXS", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x98, "TYA", 1, 2, AddressingMode::NoneAddressing),
-
-        /* Stack */
-        OpCode::new(0x48, "PHA", 1, 3, AddressingMode::NoneAddressing),
-        OpCode::new(0x68, "PLA", 1, 4, AddressingMode::NoneAddressing),
-        OpCode::new(0x08, "PHP", 1, 3, AddressingMode::NoneAddressing),
-        OpCode::new(0x28, "PLP", 1, 4, AddressingMode::NoneAddressing),
-
-    ];
-
-
-    pub static ref OPCODES_MAP: HashMap<u8, &'static OpCode> = {
-        let mut map = HashMap::new();
-        for cpuop in &*CPU_OPS_CODES {
-            map.insert(cpuop.code, cpuop);
-        }
-        map
-    };
-}
\ No newline at end of file
+use crate::AddressingMode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpCode {
+    pub code: u8,
+    pub mnemonic: &'static str,
+    pub len: u8,
+    pub cycles: u8,
+    pub mode: AddressingMode,
+    /// Not part of the documented NMOS instruction set
+    pub undocumented: bool,
+}
+
+impl OpCode {
+    const fn new(
+        code: u8,
+        mnemonic: &'static str,
+        len: u8,
+        cycles: u8,
+        mode: AddressingMode,
+    ) -> Self {
+        OpCode {
+            code,
+            mnemonic,
+            len,
+            cycles,
+            mode,
+            undocumented: false,
+        }
+    }
+
+    const fn undocumented(
+        code: u8,
+        mnemonic: &'static str,
+        len: u8,
+        cycles: u8,
+        mode: AddressingMode,
+    ) -> Self {
+        OpCode {
+            undocumented: true,
+            ..OpCode::new(code, mnemonic, len, cycles, mode)
+        }
+    }
+}
+
+#[rustfmt::skip]
+pub const CPU_OPS_CODES: &[OpCode] = &[
+    OpCode::new(0x00, "BRK", 1, 7, AddressingMode::Implied),
+    OpCode::new(0xea, "NOP", 1, 2, AddressingMode::Implied),
+
+    /* Arithmetic */
+    OpCode::new(0x69, "ADC", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x65, "ADC", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x75, "ADC", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x6d, "ADC", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x7d, "ADC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+    OpCode::new(0x79, "ADC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
+    OpCode::new(0x61, "ADC", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0x71, "ADC", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
+
+    OpCode::new(0xe9, "SBC", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xe5, "SBC", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xf5, "SBC", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0xed, "SBC", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xfd, "SBC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+    OpCode::new(0xf9, "SBC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
+    OpCode::new(0xe1, "SBC", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0xf1, "SBC", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
+
+    OpCode::new(0x29, "AND", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x25, "AND", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x35, "AND", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x2d, "AND", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x3d, "AND", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+    OpCode::new(0x39, "AND", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
+    OpCode::new(0x21, "AND", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0x31, "AND", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
+
+    OpCode::new(0x49, "EOR", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x45, "EOR", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x55, "EOR", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x4d, "EOR", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x5d, "EOR", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+    OpCode::new(0x59, "EOR", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
+    OpCode::new(0x41, "EOR", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0x51, "EOR", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
+
+    OpCode::new(0x09, "ORA", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x05, "ORA", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x15, "ORA", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x0d, "ORA", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x1d, "ORA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+    OpCode::new(0x19, "ORA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
+    OpCode::new(0x01, "ORA", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0x11, "ORA", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
+
+    /* Shifts */
+    OpCode::new(0x0a, "ASL", 1, 2, AddressingMode::Accumulator),
+    OpCode::new(0x06, "ASL", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x16, "ASL", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::new(0x0e, "ASL", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x1e, "ASL", 3, 7, AddressingMode::Absolute_X),
+
+    OpCode::new(0x4a, "LSR", 1, 2, AddressingMode::Accumulator),
+    OpCode::new(0x46, "LSR", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x56, "LSR", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::new(0x4e, "LSR", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x5e, "LSR", 3, 7, AddressingMode::Absolute_X),
+
+    OpCode::new(0x2a, "ROL", 1, 2, AddressingMode::Accumulator),
+    OpCode::new(0x26, "ROL", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x36, "ROL", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::new(0x2e, "ROL", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x3e, "ROL", 3, 7, AddressingMode::Absolute_X),
+
+    OpCode::new(0x6a, "ROR", 1, 2, AddressingMode::Accumulator),
+    OpCode::new(0x66, "ROR", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x76, "ROR", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::new(0x6e, "ROR", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x7e, "ROR", 3, 7, AddressingMode::Absolute_X),
+
+    OpCode::new(0xe6, "INC", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xf6, "INC", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::new(0xee, "INC", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0xfe, "INC", 3, 7, AddressingMode::Absolute_X),
+
+    OpCode::new(0xe8, "INX", 1, 2, AddressingMode::Implied),
+    OpCode::new(0xc8, "INY", 1, 2, AddressingMode::Implied),
+
+    OpCode::new(0xc6, "DEC", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xd6, "DEC", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::new(0xce, "DEC", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0xde, "DEC", 3, 7, AddressingMode::Absolute_X),
+
+    OpCode::new(0xca, "DEX", 1, 2, AddressingMode::Implied),
+    OpCode::new(0x88, "DEY", 1, 2, AddressingMode::Implied),
+
+    OpCode::new(0xc9, "CMP", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xc5, "CMP", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xd5, "CMP", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0xcd, "CMP", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xdd, "CMP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+    OpCode::new(0xd9, "CMP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
+    OpCode::new(0xc1, "CMP", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0xd1, "CMP", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
+
+    OpCode::new(0xc0, "CPY", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xc4, "CPY", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xcc, "CPY", 3, 4, AddressingMode::Absolute),
+
+    OpCode::new(0xe0, "CPX", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xe4, "CPX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xec, "CPX", 3, 4, AddressingMode::Absolute),
+
+
+    /* Branching */
+
+    OpCode::new(0x4c, "JMP", 3, 3, AddressingMode::Absolute),
+    OpCode::new(0x6c, "JMP", 3, 5, AddressingMode::Indirect),
+
+    OpCode::new(0x20, "JSR", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x60, "RTS", 1, 6, AddressingMode::Implied),
+
+    OpCode::new(0x40, "RTI", 1, 6, AddressingMode::Implied),
+
+    OpCode::new(0xd0, "BNE", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::Relative),
+    OpCode::new(0x70, "BVS", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::Relative),
+    OpCode::new(0x50, "BVC", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::Relative),
+    OpCode::new(0x30, "BMI", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::Relative),
+    OpCode::new(0xf0, "BEQ", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::Relative),
+    OpCode::new(0xb0, "BCS", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::Relative),
+    OpCode::new(0x90, "BCC", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::Relative),
+    OpCode::new(0x10, "BPL", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::Relative),
+
+    OpCode::new(0x24, "BIT", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x2c, "BIT", 3, 4, AddressingMode::Absolute),
+
+
+    /* Stores, Loads */
+    OpCode::new(0xa9, "LDA", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xa5, "LDA", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xb5, "LDA", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0xad, "LDA", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xbd, "LDA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+    OpCode::new(0xb9, "LDA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
+    OpCode::new(0xa1, "LDA", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0xb1, "LDA", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
+
+    OpCode::new(0xa2, "LDX", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xa6, "LDX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xb6, "LDX", 2, 4, AddressingMode::ZeroPage_Y),
+    OpCode::new(0xae, "LDX", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xbe, "LDX", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
+
+    OpCode::new(0xa0, "LDY", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xa4, "LDY", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xb4, "LDY", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0xac, "LDY", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xbc, "LDY", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+
+
+    OpCode::new(0x85, "STA", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x95, "STA", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x8d, "STA", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x9d, "STA", 3, 5, AddressingMode::Absolute_X),
+    OpCode::new(0x99, "STA", 3, 5, AddressingMode::Absolute_Y),
+    OpCode::new(0x81, "STA", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0x91, "STA", 2, 6, AddressingMode::Indirect_Y),
+
+    OpCode::new(0x86, "STX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x96, "STX", 2, 4, AddressingMode::ZeroPage_Y),
+    OpCode::new(0x8e, "STX", 3, 4, AddressingMode::Absolute),
+
+    OpCode::new(0x84, "STY", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x94, "STY", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x8c, "STY", 3, 4, AddressingMode::Absolute),
+
+
+    /* Flags clear */
+
+    OpCode::new(0xD8, "CLD", 1, 2, AddressingMode::Implied),
+    OpCode::new(0x58, "CLI", 1, 2, AddressingMode::Implied),
+    OpCode::new(0xb8, "CLV", 1, 2, AddressingMode::Implied),
+    OpCode::new(0x18, "CLC", 1, 2, AddressingMode::Implied),
+    OpCode::new(0x38, "SEC", 1, 2, AddressingMode::Implied),
+    OpCode::new(0x78, "SEI", 1, 2, AddressingMode::Implied),
+    OpCode::new(0xf8, "SED", 1, 2, AddressingMode::Implied),
+
+    OpCode::new(0xaa, "TAX", 1, 2, AddressingMode::Implied),
+    OpCode::new(0xa8, "TAY", 1, 2, AddressingMode::Implied),
+    OpCode::new(0xba, "TSX", 1, 2, AddressingMode::Implied),
+    OpCode::new(0x8a, "TXA", 1, 2, AddressingMode::Implied),
+    OpCode::new(0x9a, "TXS", 1, 2, AddressingMode::Implied),
+    OpCode::new(0x98, "TYA", 1, 2, AddressingMode::Implied),
+
+    /* Stack */
+    OpCode::new(0x48, "PHA", 1, 3, AddressingMode::Implied),
+    OpCode::new(0x68, "PLA", 1, 4, AddressingMode::Implied),
+    OpCode::new(0x08, "PHP", 1, 3, AddressingMode::Implied),
+    OpCode::new(0x28, "PLP", 1, 4, AddressingMode::Implied),
+
+    /* Undocumented */
+    OpCode::undocumented(0xa7, "LAX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::undocumented(0xb7, "LAX", 2, 4, AddressingMode::ZeroPage_Y),
+    OpCode::undocumented(0xaf, "LAX", 3, 4, AddressingMode::Absolute),
+    OpCode::undocumented(0xbf, "LAX", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
+    OpCode::undocumented(0xa3, "LAX", 2, 6, AddressingMode::Indirect_X),
+    OpCode::undocumented(0xb3, "LAX", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
+
+    OpCode::undocumented(0x87, "SAX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::undocumented(0x97, "SAX", 2, 4, AddressingMode::ZeroPage_Y),
+    OpCode::undocumented(0x8f, "SAX", 3, 4, AddressingMode::Absolute),
+    OpCode::undocumented(0x83, "SAX", 2, 6, AddressingMode::Indirect_X),
+
+    OpCode::undocumented(0xc7, "DCP", 2, 5, AddressingMode::ZeroPage),
+    OpCode::undocumented(0xd7, "DCP", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::undocumented(0xcf, "DCP", 3, 6, AddressingMode::Absolute),
+    OpCode::undocumented(0xdf, "DCP", 3, 7, AddressingMode::Absolute_X),
+    OpCode::undocumented(0xdb, "DCP", 3, 7, AddressingMode::Absolute_Y),
+    OpCode::undocumented(0xc3, "DCP", 2, 8, AddressingMode::Indirect_X),
+    OpCode::undocumented(0xd3, "DCP", 2, 8, AddressingMode::Indirect_Y),
+
+    OpCode::undocumented(0xe7, "ISB", 2, 5, AddressingMode::ZeroPage),
+    OpCode::undocumented(0xf7, "ISB", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::undocumented(0xef, "ISB", 3, 6, AddressingMode::Absolute),
+    OpCode::undocumented(0xff, "ISB", 3, 7, AddressingMode::Absolute_X),
+    OpCode::undocumented(0xfb, "ISB", 3, 7, AddressingMode::Absolute_Y),
+    OpCode::undocumented(0xe3, "ISB", 2, 8, AddressingMode::Indirect_X),
+    OpCode::undocumented(0xf3, "ISB", 2, 8, AddressingMode::Indirect_Y),
+
+    OpCode::undocumented(0x07, "SLO", 2, 5, AddressingMode::ZeroPage),
+    OpCode::undocumented(0x17, "SLO", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::undocumented(0x0f, "SLO", 3, 6, AddressingMode::Absolute),
+    OpCode::undocumented(0x1f, "SLO", 3, 7, AddressingMode::Absolute_X),
+    OpCode::undocumented(0x1b, "SLO", 3, 7, AddressingMode::Absolute_Y),
+    OpCode::undocumented(0x03, "SLO", 2, 8, AddressingMode::Indirect_X),
+    OpCode::undocumented(0x13, "SLO", 2, 8, AddressingMode::Indirect_Y),
+
+    OpCode::undocumented(0x27, "RLA", 2, 5, AddressingMode::ZeroPage),
+    OpCode::undocumented(0x37, "RLA", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::undocumented(0x2f, "RLA", 3, 6, AddressingMode::Absolute),
+    OpCode::undocumented(0x3f, "RLA", 3, 7, AddressingMode::Absolute_X),
+    OpCode::undocumented(0x3b, "RLA", 3, 7, AddressingMode::Absolute_Y),
+    OpCode::undocumented(0x23, "RLA", 2, 8, AddressingMode::Indirect_X),
+    OpCode::undocumented(0x33, "RLA", 2, 8, AddressingMode::Indirect_Y),
+
+    OpCode::undocumented(0x47, "SRE", 2, 5, AddressingMode::ZeroPage),
+    OpCode::undocumented(0x57, "SRE", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::undocumented(0x4f, "SRE", 3, 6, AddressingMode::Absolute),
+    OpCode::undocumented(0x5f, "SRE", 3, 7, AddressingMode::Absolute_X),
+    OpCode::undocumented(0x5b, "SRE", 3, 7, AddressingMode::Absolute_Y),
+    OpCode::undocumented(0x43, "SRE", 2, 8, AddressingMode::Indirect_X),
+    OpCode::undocumented(0x53, "SRE", 2, 8, AddressingMode::Indirect_Y),
+
+    OpCode::undocumented(0x67, "RRA", 2, 5, AddressingMode::ZeroPage),
+    OpCode::undocumented(0x77, "RRA", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::undocumented(0x6f, "RRA", 3, 6, AddressingMode::Absolute),
+    OpCode::undocumented(0x7f, "RRA", 3, 7, AddressingMode::Absolute_X),
+    OpCode::undocumented(0x7b, "RRA", 3, 7, AddressingMode::Absolute_Y),
+    OpCode::undocumented(0x63, "RRA", 2, 8, AddressingMode::Indirect_X),
+    OpCode::undocumented(0x73, "RRA", 2, 8, AddressingMode::Indirect_Y),
+
+    OpCode::undocumented(0x0b, "ANC", 2, 2, AddressingMode::Immediate),
+    OpCode::undocumented(0x2b, "ANC", 2, 2, AddressingMode::Immediate),
+    OpCode::undocumented(0x4b, "ALR", 2, 2, AddressingMode::Immediate),
+    OpCode::undocumented(0x6b, "ARR", 2, 2, AddressingMode::Immediate),
+    OpCode::undocumented(0xcb, "AXS", 2, 2, AddressingMode::Immediate),
+    OpCode::undocumented(0xeb, "SBC", 2, 2, AddressingMode::Immediate),
+
+    /* Undocumented and unstable, the magic constant is chip dependent */
+    OpCode::undocumented(0x8b, "XAA", 2, 2, AddressingMode::Immediate),
+    OpCode::undocumented(0xab, "LXA", 2, 2, AddressingMode::Immediate),
+    OpCode::undocumented(0xbb, "LAS", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
+    OpCode::undocumented(0x9b, "TAS", 3, 5, AddressingMode::Absolute_Y),
+    OpCode::undocumented(0x9c, "SHY", 3, 5, AddressingMode::Absolute_X),
+    OpCode::undocumented(0x9e, "SHX", 3, 5, AddressingMode::Absolute_Y),
+    OpCode::undocumented(0x9f, "AHX", 3, 5, AddressingMode::Absolute_Y),
+    OpCode::undocumented(0x93, "AHX", 2, 6, AddressingMode::Indirect_Y),
+
+    OpCode::undocumented(0x1a, "NOP", 1, 2, AddressingMode::Implied),
+    OpCode::undocumented(0x3a, "NOP", 1, 2, AddressingMode::Implied),
+    OpCode::undocumented(0x5a, "NOP", 1, 2, AddressingMode::Implied),
+    OpCode::undocumented(0x7a, "NOP", 1, 2, AddressingMode::Implied),
+    OpCode::undocumented(0xda, "NOP", 1, 2, AddressingMode::Implied),
+    OpCode::undocumented(0xfa, "NOP", 1, 2, AddressingMode::Implied),
+    OpCode::undocumented(0x80, "NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::undocumented(0x82, "NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::undocumented(0x89, "NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::undocumented(0xc2, "NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::undocumented(0xe2, "NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::undocumented(0x04, "NOP", 2, 3, AddressingMode::ZeroPage),
+    OpCode::undocumented(0x44, "NOP", 2, 3, AddressingMode::ZeroPage),
+    OpCode::undocumented(0x64, "NOP", 2, 3, AddressingMode::ZeroPage),
+    OpCode::undocumented(0x14, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::undocumented(0x34, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::undocumented(0x54, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::undocumented(0x74, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::undocumented(0xd4, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::undocumented(0xf4, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::undocumented(0x0c, "NOP", 3, 4, AddressingMode::Absolute),
+    OpCode::undocumented(0x1c, "NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+    OpCode::undocumented(0x3c, "NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+    OpCode::undocumented(0x5c, "NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+    OpCode::undocumented(0x7c, "NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+    OpCode::undocumented(0xdc, "NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+    OpCode::undocumented(0xfc, "NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+
+    /* Locks the CPU up until the next reset */
+    OpCode::undocumented(0x02, "JAM", 1, 2, AddressingMode::Implied),
+    OpCode::undocumented(0x12, "JAM", 1, 2, AddressingMode::Implied),
+    OpCode::undocumented(0x22, "JAM", 1, 2, AddressingMode::Implied),
+    OpCode::undocumented(0x32, "JAM", 1, 2, AddressingMode::Implied),
+    OpCode::undocumented(0x42, "JAM", 1, 2, AddressingMode::Implied),
+    OpCode::undocumented(0x52, "JAM", 1, 2, AddressingMode::Implied),
+    OpCode::undocumented(0x62, "JAM", 1, 2, AddressingMode::Implied),
+    OpCode::undocumented(0x72, "JAM", 1, 2, AddressingMode::Implied),
+    OpCode::undocumented(0x92, "JAM", 1, 2, AddressingMode::Implied),
+    OpCode::undocumented(0xb2, "JAM", 1, 2, AddressingMode::Implied),
+    OpCode::undocumented(0xd2, "JAM", 1, 2, AddressingMode::Implied),
+    OpCode::undocumented(0xf2, "JAM", 1, 2, AddressingMode::Implied),
+];
+
+/// The instructions the WDC 65C02 adds on top of the documented NMOS ones,
+/// and NOPs of various lengths in place of the NMOS undocumented opcodes.
+#[rustfmt::skip]
+const CMOS_EXTRA_OPS_CODES: &[OpCode] = &[
+    OpCode::new(0x80, "BRA", 2, 2 /*(+1 as the branch always succeeds +1 if to a new page)*/, AddressingMode::Relative),
+
+    OpCode::new(0xda, "PHX", 1, 3, AddressingMode::Implied),
+    OpCode::new(0x5a, "PHY", 1, 3, AddressingMode::Implied),
+    OpCode::new(0xfa, "PLX", 1, 4, AddressingMode::Implied),
+    OpCode::new(0x7a, "PLY", 1, 4, AddressingMode::Implied),
+
+    OpCode::new(0x64, "STZ", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x74, "STZ", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x9c, "STZ", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x9e, "STZ", 3, 5, AddressingMode::Absolute_X),
+
+    OpCode::new(0x04, "TSB", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x0c, "TSB", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x14, "TRB", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x1c, "TRB", 3, 6, AddressingMode::Absolute),
+
+    OpCode::new(0x1a, "INC", 1, 2, AddressingMode::Accumulator),
+    OpCode::new(0x3a, "DEC", 1, 2, AddressingMode::Accumulator),
+
+    OpCode::new(0x89, "BIT", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x34, "BIT", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x3c, "BIT", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+
+    OpCode::new(0x12, "ORA", 2, 5, AddressingMode::ZeroPage_Indirect),
+    OpCode::new(0x32, "AND", 2, 5, AddressingMode::ZeroPage_Indirect),
+    OpCode::new(0x52, "EOR", 2, 5, AddressingMode::ZeroPage_Indirect),
+    OpCode::new(0x72, "ADC", 2, 5, AddressingMode::ZeroPage_Indirect),
+    OpCode::new(0x92, "STA", 2, 5, AddressingMode::ZeroPage_Indirect),
+    OpCode::new(0xb2, "LDA", 2, 5, AddressingMode::ZeroPage_Indirect),
+    OpCode::new(0xd2, "CMP", 2, 5, AddressingMode::ZeroPage_Indirect),
+    OpCode::new(0xf2, "SBC", 2, 5, AddressingMode::ZeroPage_Indirect),
+
+    OpCode::new(0x7c, "JMP", 3, 6, AddressingMode::Absolute_Indexed_Indirect),
+
+    /* WDC bit manipulation, the bit number is in the high nibble of the opcode */
+    OpCode::new(0x07, "RMB0", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x17, "RMB1", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x27, "RMB2", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x37, "RMB3", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x47, "RMB4", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x57, "RMB5", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x67, "RMB6", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x77, "RMB7", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x87, "SMB0", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x97, "SMB1", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xa7, "SMB2", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xb7, "SMB3", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xc7, "SMB4", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xd7, "SMB5", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xe7, "SMB6", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xf7, "SMB7", 2, 5, AddressingMode::ZeroPage),
+
+    OpCode::new(0x0f, "BBR0", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+    OpCode::new(0x1f, "BBR1", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+    OpCode::new(0x2f, "BBR2", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+    OpCode::new(0x3f, "BBR3", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+    OpCode::new(0x4f, "BBR4", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+    OpCode::new(0x5f, "BBR5", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+    OpCode::new(0x6f, "BBR6", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+    OpCode::new(0x7f, "BBR7", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+    OpCode::new(0x8f, "BBS0", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+    OpCode::new(0x9f, "BBS1", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+    OpCode::new(0xaf, "BBS2", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+    OpCode::new(0xbf, "BBS3", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+    OpCode::new(0xcf, "BBS4", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+    OpCode::new(0xdf, "BBS5", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+    OpCode::new(0xef, "BBS6", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+    OpCode::new(0xff, "BBS7", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+
+    OpCode::new(0xcb, "WAI", 1, 3, AddressingMode::Implied),
+    OpCode::new(0xdb, "STP", 1, 3, AddressingMode::Implied),
+
+    /* Reserved, these all behave as NOPs */
+    OpCode::undocumented(0x02, "NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::undocumented(0x22, "NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::undocumented(0x42, "NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::undocumented(0x62, "NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::undocumented(0x82, "NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::undocumented(0xc2, "NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::undocumented(0xe2, "NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::undocumented(0x44, "NOP", 2, 3, AddressingMode::ZeroPage),
+    OpCode::undocumented(0x54, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::undocumented(0xd4, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::undocumented(0xf4, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::undocumented(0x5c, "NOP", 3, 8, AddressingMode::Absolute),
+    OpCode::undocumented(0xdc, "NOP", 3, 4, AddressingMode::Absolute),
+    OpCode::undocumented(0xfc, "NOP", 3, 4, AddressingMode::Absolute),
+];
+
+/// NMOS 6502 opcodes indexed by their code, as used by the decoder
+pub static OPCODE_TABLE: [Option<OpCode>; 256] = decode_table();
+
+/// WDC 65C02 opcodes indexed by their code, as used by the decoder
+pub static CMOS_OPCODE_TABLE: [Option<OpCode>; 256] = cmos_decode_table();
+
+const fn decode_table() -> [Option<OpCode>; 256] {
+    let mut table = [None; 256];
+    let mut i = 0;
+    while i < CPU_OPS_CODES.len() {
+        table[CPU_OPS_CODES[i].code as usize] = Some(CPU_OPS_CODES[i]);
+        i += 1;
+    }
+    table
+}
+
+const fn cmos_decode_table() -> [Option<OpCode>; 256] {
+    let mut table = [None; 256];
+    let mut i = 0;
+    while i < CPU_OPS_CODES.len() {
+        let op = CPU_OPS_CODES[i];
+        if !op.undocumented {
+            table[op.code as usize] = Some(op);
+        }
+        i += 1;
+    }
+
+    // JMP ($xxFF) no longer wraps within the page, at the cost of a cycle
+    if let Some(op) = &mut table[0x6c] {
+        op.cycles = 6;
+    }
+
+    let mut i = 0;
+    while i < CMOS_EXTRA_OPS_CODES.len() {
+        table[CMOS_EXTRA_OPS_CODES[i].code as usize] = Some(CMOS_EXTRA_OPS_CODES[i]);
+        i += 1;
+    }
+
+    // the x3 and xB columns are single cycle, single byte NOPs
+    let mut code = 0;
+    while code < 256 {
+        if code & 0x07 == 0x03 && code != 0xcb && code != 0xdb {
+            table[code] = Some(OpCode::undocumented(
+                code as u8,
+                "NOP",
+                1,
+                1,
+                AddressingMode::Implied,
+            ));
+        }
+        code += 1;
+    }
+
+    table
+}
@@ -0,0 +1,504 @@
+//! Execution profiler.
+//!
+//! A [`Profiler`] attached with [`CPU::set_profiler`] counts every instruction
+//! executed and the cycles it took, per address. It also follows subroutine
+//! calls: JSR, BRK and interrupts open a frame that the matching RTS or RTI
+//! closes, which gives each routine its inclusive cycles (callees counted)
+//! and exclusive cycles (only its own instructions), and each caller to
+//! callee edge its number of calls.
+//!
+//! The results come out as a text report sorted by cycles, or as collapsed
+//! stacks, the input of `flamegraph.pl` and most flame graph viewers.
+//!
+//! ```
+//! use cpu::profile::Profiler;
+//! use cpu::CPU;
+//!
+//! let mut cpu = CPU::new();
+//! // JSR $0604, BRK, LDX #0, INX, BNE $0606, RTS
+//! cpu.load(vec![0x20, 0x04, 0x06, 0x00, 0xa2, 0x00, 0xe8, 0xd0, 0xfd, 0x60]);
+//! cpu.reset();
+//! cpu.stop_on_brk = true;
+//! cpu.set_profiler(Some(Profiler::new()));
+//! cpu.run().unwrap();
+//!
+//! let profiler = cpu.take_profiler().unwrap();
+//! assert_eq!(profiler.address(0x0606).count, 256);
+//! println!("{}", profiler.report(10));
+//! assert_eq!(
+//!     profiler.collapsed_stacks(),
+//!     "$0600 6\n$0600;$0604 1287\n"
+//! );
+//! ```
+//!
+//! [`CPU::set_profiler`]: crate::CPU::set_profiler
+
+use crate::disasm::Symbols;
+use crate::StepOutcome;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const BRK: u8 = 0x00;
+const RTI: u8 = 0x40;
+
+/// What was executed at one address
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AddressProfile {
+    /// Instructions executed
+    pub count: u64,
+    pub cycles: u64,
+}
+
+/// Time spent in a routine, named by its entry address
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RoutineProfile {
+    pub calls: u64,
+    /// Cycles between entering and leaving the routine. Recursive calls are
+    /// only counted once, by the outermost one.
+    pub inclusive: u64,
+    /// Cycles of the routine's own instructions
+    pub exclusive: u64,
+}
+
+/// Calls from one routine to another
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CallProfile {
+    pub calls: u64,
+    /// Inclusive cycles of the callee when called from this caller
+    pub cycles: u64,
+}
+
+pub struct Profiler {
+    addresses: Vec<AddressProfile>,
+    totals: Totals,
+    /// Routines being executed, the outermost first
+    frames: Vec<Frame>,
+    instructions: u64,
+    cycles: u64,
+    symbols: Symbols,
+}
+
+/// Figures of the routines that returned
+#[derive(Clone, Default)]
+struct Totals {
+    routines: HashMap<u16, RoutineProfile>,
+    calls: HashMap<(u16, u16), CallProfile>,
+    /// Exclusive cycles per chain of routines, the outermost first
+    stacks: HashMap<Vec<u16>, u64>,
+}
+
+#[derive(Clone)]
+struct Frame {
+    routine: u16,
+    /// Stack pointer once the routine returns, `None` for the routine the
+    /// profile started in, which never does
+    stack_pointer: Option<u8>,
+    /// Profiled cycles when the routine was entered
+    entered: u64,
+    exclusive: u64,
+}
+
+impl Totals {
+    fn enter(&mut self, frames: &mut Vec<Frame>, frame: Frame) {
+        self.routines.entry(frame.routine).or_default().calls += 1;
+        if let Some(caller) = frames.last() {
+            self.calls
+                .entry((caller.routine, frame.routine))
+                .or_default()
+                .calls += 1;
+        }
+        frames.push(frame);
+    }
+
+    /// Closes the innermost frame at `cycles`
+    fn leave(&mut self, frames: &mut Vec<Frame>, cycles: u64) {
+        let frame = match frames.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let inclusive = cycles - frame.entered;
+
+        let mut stack: Vec<u16> = frames.iter().map(|frame| frame.routine).collect();
+        stack.push(frame.routine);
+        if frame.exclusive > 0 {
+            *self.stacks.entry(stack.clone()).or_default() += frame.exclusive;
+        }
+
+        let routine = self.routines.entry(frame.routine).or_default();
+        routine.exclusive += frame.exclusive;
+        if !stack[..stack.len() - 1].contains(&frame.routine) {
+            routine.inclusive += inclusive;
+        }
+        if let [.., caller, callee] = stack[..] {
+            let recursive = stack[..stack.len() - 1]
+                .windows(2)
+                .any(|pair| pair == [caller, callee]);
+            if !recursive {
+                self.calls.entry((caller, callee)).or_default().cycles += inclusive;
+            }
+        }
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            addresses: vec![AddressProfile::default(); 0x10000],
+            totals: Totals::default(),
+            frames: Vec::new(),
+            instructions: 0,
+            cycles: 0,
+            symbols: Symbols::new(),
+        }
+    }
+
+    /// Names routines and addresses in the report and the collapsed stacks
+    /// instead of printing them in hex.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Accounts for an instruction executed by the CPU, the stack pointers
+    /// are the ones before any interrupt it serviced and after it ran.
+    pub(crate) fn record(&mut self, outcome: &StepOutcome, stack_before: u8, stack_after: u8) {
+        if self.frames.is_empty() {
+            let frame = self.frame(outcome.pc_before, None);
+            self.frames.push(frame);
+        }
+        if outcome.interrupt.is_some() {
+            // the interrupt sequence is the handler's first cycles
+            let frame = self.frame(outcome.pc_before, Some(stack_before));
+            self.totals.enter(&mut self.frames, frame);
+        }
+
+        let cycles = outcome.cycles as u64;
+        self.instructions += 1;
+        self.cycles += cycles;
+        let address = &mut self.addresses[outcome.pc_before as usize];
+        address.count += 1;
+        address.cycles += cycles;
+        if let Some(frame) = self.frames.last_mut() {
+            frame.exclusive += cycles;
+        }
+
+        match outcome.opcode {
+            JSR => {
+                let frame = self.frame(outcome.pc_after, Some(stack_after.wrapping_add(2)));
+                self.totals.enter(&mut self.frames, frame);
+            }
+            BRK if outcome.interrupt.is_none() => {
+                let frame = self.frame(outcome.pc_after, Some(stack_after.wrapping_add(3)));
+                self.totals.enter(&mut self.frames, frame);
+            }
+            // a return closes every frame the stack pointer went past, so a
+            // routine that drops its return address ends with its caller
+            RTS | RTI => {
+                while self
+                    .frames
+                    .last()
+                    .and_then(|frame| frame.stack_pointer)
+                    .is_some_and(|stack_pointer| stack_pointer <= stack_after)
+                {
+                    self.totals.leave(&mut self.frames, self.cycles);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn frame(&self, routine: u16, stack_pointer: Option<u8>) -> Frame {
+        Frame {
+            routine,
+            stack_pointer,
+            entered: self.cycles,
+            exclusive: 0,
+        }
+    }
+
+    /// The totals as if every routine being executed returned now
+    fn settled(&self) -> Totals {
+        let mut totals = self.totals.clone();
+        let mut frames = self.frames.clone();
+        while !frames.is_empty() {
+            totals.leave(&mut frames, self.cycles);
+        }
+        totals
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn address(&self, address: u16) -> AddressProfile {
+        self.addresses[address as usize]
+    }
+
+    /// Addresses executed, the most cycles first
+    pub fn hotspots(&self) -> Vec<(u16, AddressProfile)> {
+        let mut hotspots: Vec<_> = self
+            .addresses
+            .iter()
+            .enumerate()
+            .filter(|(_, profile)| profile.count > 0)
+            .map(|(address, profile)| (address as u16, *profile))
+            .collect();
+        hotspots.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(&b.0)));
+        hotspots
+    }
+
+    /// Routines entered, the most inclusive cycles first. The routines still
+    /// running count up to now.
+    pub fn routines(&self) -> Vec<(u16, RoutineProfile)> {
+        let mut routines: Vec<_> = self.settled().routines.into_iter().collect();
+        routines.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(&b.0)));
+        routines
+    }
+
+    /// Caller and callee pairs, the most cycles first
+    pub fn calls(&self) -> Vec<((u16, u16), CallProfile)> {
+        let mut calls: Vec<_> = self.settled().calls.into_iter().collect();
+        calls.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(&b.0)));
+        calls
+    }
+
+    fn name(&self, address: u16) -> String {
+        match self.symbols.get(&address) {
+            Some(name) => name.clone(),
+            None => format!("${:04X}", address),
+        }
+    }
+
+    /// Routines, calls and the hottest addresses, `limit` lines of each
+    pub fn report(&self, limit: usize) -> String {
+        let percent = |cycles: u64| match self.cycles {
+            0 => 0.0,
+            total => cycles as f64 * 100.0 / total as f64,
+        };
+        let mut report = String::new();
+        let _ = writeln!(
+            report,
+            "{} instructions, {} cycles",
+            self.instructions, self.cycles
+        );
+
+        let _ = writeln!(
+            report,
+            "\nroutine          calls        inclusive        exclusive"
+        );
+        for (address, routine) in self.routines().into_iter().take(limit) {
+            let _ = writeln!(
+                report,
+                "{:<12} {:>9} {:>9} {:>5.1}% {:>9} {:>5.1}%",
+                self.name(address),
+                routine.calls,
+                routine.inclusive,
+                percent(routine.inclusive),
+                routine.exclusive,
+                percent(routine.exclusive)
+            );
+        }
+
+        let _ = writeln!(
+            report,
+            "\ncaller       callee           calls           cycles"
+        );
+        for ((caller, callee), call) in self.calls().into_iter().take(limit) {
+            let _ = writeln!(
+                report,
+                "{:<12} {:<12} {:>9} {:>9} {:>5.1}%",
+                self.name(caller),
+                self.name(callee),
+                call.calls,
+                call.cycles,
+                percent(call.cycles)
+            );
+        }
+
+        let _ = writeln!(report, "\naddress          count           cycles");
+        for (address, profile) in self.hotspots().into_iter().take(limit) {
+            let _ = writeln!(
+                report,
+                "{:<12} {:>9} {:>9} {:>5.1}%",
+                self.name(address),
+                profile.count,
+                profile.cycles,
+                percent(profile.cycles)
+            );
+        }
+        report
+    }
+
+    /// One line per chain of calls, the routines outermost first separated
+    /// by semicolons, then the exclusive cycles of the innermost one.
+    pub fn collapsed_stacks(&self) -> String {
+        let mut lines: Vec<String> = self
+            .settled()
+            .stacks
+            .into_iter()
+            .map(|(stack, cycles)| {
+                let names: Vec<String> = stack.iter().map(|&routine| self.name(routine)).collect();
+                format!("{} {}\n", names.join(";"), cycles)
+            })
+            .collect();
+        lines.sort();
+        lines.concat()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{asm, Interrupt, Mem, CPU};
+
+    const PROGRAM: &str = "
+        .org $0600
+main:   jsr outer
+        jsr inner
+        brk
+outer:  jsr inner
+        nop
+        rts
+inner:  ldx #2
+loop:   dex
+        bne loop
+        rts
+";
+
+    fn profile(source: &str) -> Profiler {
+        let program = asm::assemble(source).unwrap();
+        let mut cpu = CPU::new();
+        cpu.load(program.bytes);
+        cpu.reset();
+        cpu.stop_on_brk = true;
+        let mut profiler = Profiler::new();
+        profiler.set_symbols(
+            program
+                .symbols
+                .into_iter()
+                .map(|(name, address)| (address, name))
+                .collect(),
+        );
+        cpu.set_profiler(Some(profiler));
+        cpu.run().unwrap();
+        cpu.take_profiler().unwrap()
+    }
+
+    #[test]
+    fn test_address_counts() {
+        let profiler = profile(PROGRAM);
+        // DEX runs twice per call, BNE is taken once for 3 cycles then falls through
+        assert_eq!(profiler.address(0x060e).count, 4);
+        assert_eq!(profiler.address(0x060e).cycles, 8);
+        assert_eq!(
+            profiler.address(0x060f),
+            AddressProfile {
+                count: 4,
+                cycles: 10
+            }
+        );
+        assert_eq!(profiler.address(0x0606), AddressProfile::default());
+        assert_eq!(profiler.instructions(), 17);
+        assert_eq!(profiler.cycles(), 60);
+        assert_eq!(profiler.hotspots()[0].0, 0x0611);
+    }
+
+    #[test]
+    fn test_call_graph() {
+        let profiler = profile(PROGRAM);
+        let routine = |calls, inclusive, exclusive| RoutineProfile {
+            calls,
+            inclusive,
+            exclusive,
+        };
+        assert_eq!(
+            profiler.routines(),
+            vec![
+                (0x0600, routine(0, 60, 12)),
+                (0x060c, routine(2, 34, 34)),
+                (0x0607, routine(1, 31, 14)),
+            ]
+        );
+        let call = |calls, cycles| CallProfile { calls, cycles };
+        assert_eq!(
+            profiler.calls(),
+            vec![
+                ((0x0600, 0x0607), call(1, 31)),
+                ((0x0600, 0x060c), call(1, 17)),
+                ((0x0607, 0x060c), call(1, 17)),
+            ]
+        );
+        assert_eq!(
+            profiler.collapsed_stacks(),
+            "main 12\nmain;inner 17\nmain;outer 14\nmain;outer;inner 17\n"
+        );
+
+        let report = profiler.report(2);
+        assert!(report.starts_with("17 instructions, 60 cycles\n"));
+        assert!(report.contains("\nmain                 0        60 100.0%        12  20.0%\n"));
+        assert!(!report.contains("\nouter "));
+    }
+
+    #[test]
+    fn test_recursion_is_counted_once() {
+        // count calls itself until X wraps to zero
+        let profiler = profile(
+            "
+        .org $0600
+main:   ldx #3
+        jsr count
+        brk
+count:  dex
+        beq done
+        jsr count
+done:   rts
+",
+        );
+        let (_, count) = profiler.routines()[1];
+        assert_eq!(count.calls, 3);
+        assert_eq!(count.inclusive + 2 + 6, profiler.cycles());
+        assert_eq!(profiler.collapsed_stacks().lines().count(), 4);
+    }
+
+    #[test]
+    fn test_interrupt_handler_frame() {
+        let mut cpu = CPU::new();
+        // NOP forever, the NMI handler at $0700 is INX, RTI
+        cpu.load(vec![0xea, 0x4c, 0x00, 0x06]);
+        cpu.mem_write(0x0700, 0xe8);
+        cpu.mem_write(0x0701, 0x40);
+        cpu.mem_write_u16(0xfffa, 0x0700);
+        cpu.reset();
+        cpu.set_profiler(Some(Profiler::new()));
+        cpu.step().unwrap();
+        cpu.set_nmi(true);
+        let outcome = cpu.step().unwrap();
+        assert_eq!(outcome.interrupt, Some(Interrupt::Nmi));
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+
+        let profiler = cpu.profiler().unwrap();
+        assert_eq!(
+            profiler.calls(),
+            vec![(
+                (0x0600, 0x0700),
+                CallProfile {
+                    calls: 1,
+                    cycles: 7 + 2 + 6
+                }
+            )]
+        );
+        assert_eq!(profiler.collapsed_stacks(), "$0600 5\n$0600;$0700 15\n");
+    }
+}
@@ -0,0 +1,278 @@
+//! Rewinding through a ring buffer of snapshots.
+//!
+//! [`Rewind`] takes a save state every `interval` instructions and
+//! keeps the last `capacity` of them. Everything written into memory from
+//! outside the CPU (key presses, random numbers) goes through
+//! [`Rewind::input`] so it can be replayed: stepping back restores the
+//! nearest earlier snapshot and executes forward again to the exact
+//! instruction, with the same inputs at the same points.
+//!
+//! ```
+//! use cpu::rewind::Rewind;
+//! use cpu::CPU;
+//!
+//! let mut cpu = CPU::new();
+//! cpu.load(vec![0xe8, 0x4c, 0x00, 0x06]); // INX, JMP $0600
+//! cpu.reset();
+//! let mut rewind = Rewind::new(100, 10);
+//! for _ in 0..1000 {
+//!     cpu.step().unwrap();
+//!     rewind.record(&cpu).unwrap();
+//! }
+//! assert_eq!(cpu.register_x, 0xf4);
+//!
+//! rewind.rewind(&mut cpu, 250).unwrap();
+//! assert_eq!(cpu.register_x, 0x77);
+//! ```
+
+use crate::{Mem, CPU};
+use std::collections::VecDeque;
+use std::io;
+
+pub struct Rewind {
+    interval: u64,
+    capacity: usize,
+    snapshots: VecDeque<Snapshot>,
+    inputs: VecDeque<Input>,
+    /// Instructions recorded since the history started
+    position: u64,
+}
+
+struct Snapshot {
+    position: u64,
+    /// Save state, its RAM section is compressed
+    state: Vec<u8>,
+}
+
+/// A byte written into memory from outside the CPU
+struct Input {
+    position: u64,
+    addr: u16,
+    value: u8,
+}
+
+impl Rewind {
+    /// Keeps `capacity` snapshots taken every `interval` instructions, the
+    /// history then goes back at least `interval * (capacity - 1)` instructions.
+    /// A frontend running a fixed number of instructions per frame takes a
+    /// snapshot every N frames with an interval of N times that number.
+    pub fn new(interval: u64, capacity: usize) -> Self {
+        assert!(
+            interval > 0 && capacity > 0,
+            "rewind needs an interval and a capacity"
+        );
+        Rewind {
+            interval,
+            capacity,
+            snapshots: VecDeque::with_capacity(capacity),
+            inputs: VecDeque::new(),
+            position: 0,
+        }
+    }
+
+    /// Number of instructions recorded, less the ones rewound
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// The furthest back `rewind_to` can go, `None` until the first snapshot
+    pub fn earliest(&self) -> Option<u64> {
+        self.snapshots.front().map(|snapshot| snapshot.position)
+    }
+
+    /// Size of the snapshots in bytes
+    pub fn snapshot_bytes(&self) -> usize {
+        self.snapshots
+            .iter()
+            .map(|snapshot| snapshot.state.len())
+            .sum()
+    }
+
+    /// Counts the instruction the CPU just executed and takes a snapshot
+    /// when one is due. To be called after every instruction, from the
+    /// callback of `run_with_callback` for instance.
+    pub fn record<B: Mem>(&mut self, cpu: &CPU<B>) -> io::Result<()> {
+        self.position += 1;
+        let due = match self.snapshots.back() {
+            Some(last) => self.position - last.position >= self.interval,
+            None => true,
+        };
+        if !due {
+            return Ok(());
+        }
+
+        let mut state = Vec::new();
+        cpu.save_state(&mut state)?;
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+            let earliest = self
+                .snapshots
+                .front()
+                .map_or(self.position, |snapshot| snapshot.position);
+            while self
+                .inputs
+                .front()
+                .is_some_and(|input| input.position < earliest)
+            {
+                self.inputs.pop_front();
+            }
+        }
+        self.snapshots.push_back(Snapshot {
+            position: self.position,
+            state,
+        });
+        Ok(())
+    }
+
+    /// Writes `value` at `addr` on behalf of a device outside the CPU and
+    /// remembers it for replay.
+    pub fn input<B: Mem>(&mut self, cpu: &mut CPU<B>, addr: u16, value: u8) {
+        cpu.mem_write(addr, value);
+        self.inputs.push_back(Input {
+            position: self.position,
+            addr,
+            value,
+        });
+    }
+
+    /// Steps back `instructions` instructions, or as far as the history goes.
+    /// Returns the position reached.
+    pub fn rewind<B: Mem>(&mut self, cpu: &mut CPU<B>, instructions: u64) -> io::Result<u64> {
+        let earliest = match self.earliest() {
+            Some(earliest) => earliest,
+            None => return Ok(self.position),
+        };
+        let target = self.position.saturating_sub(instructions).max(earliest);
+        self.rewind_to(cpu, target)
+    }
+
+    /// Brings the CPU back to the state it had at `position`, the history
+    /// after it is dropped. Positions before `earliest` are out of reach.
+    pub fn rewind_to<B: Mem>(&mut self, cpu: &mut CPU<B>, position: u64) -> io::Result<u64> {
+        if position > self.position {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "can not rewind to the future",
+            ));
+        }
+        let snapshot = self
+            .snapshots
+            .iter()
+            .rev()
+            .find(|snapshot| snapshot.position <= position)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "position out of the history")
+            })?;
+        cpu.load_state(&mut snapshot.state.as_slice())?;
+
+        // the trace was written the first time around
+        let tracer = cpu.tracer.take();
+        let mut inputs = self
+            .inputs
+            .iter()
+            .skip_while(|input| input.position < snapshot.position)
+            .peekable();
+        let mut result = Ok(());
+        for current in snapshot.position..=position {
+            while let Some(input) = inputs.next_if(|input| input.position == current) {
+                cpu.mem_write(input.addr, input.value);
+            }
+            if current < position {
+                if let Err(error) = cpu.step() {
+                    result = Err(io::Error::other(error));
+                    break;
+                }
+            }
+        }
+        cpu.tracer = tracer;
+        result?;
+
+        while self
+            .snapshots
+            .back()
+            .is_some_and(|snapshot| snapshot.position > position)
+        {
+            self.snapshots.pop_back();
+        }
+        while self
+            .inputs
+            .back()
+            .is_some_and(|input| input.position > position)
+        {
+            self.inputs.pop_back();
+        }
+        self.position = position;
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Adds the byte a fake device writes at $fe into A, forever
+    fn summing_cpu() -> CPU {
+        let mut cpu = CPU::new();
+        // loop: CLC, ADC $FE, STA $0200,X, INX, JMP loop
+        cpu.load(vec![
+            0x18, 0x65, 0xfe, 0x9d, 0x00, 0x02, 0xe8, 0x4c, 0x00, 0x06,
+        ]);
+        cpu.reset();
+        cpu
+    }
+
+    fn state(cpu: &CPU) -> Vec<u8> {
+        let mut state = Vec::new();
+        cpu.save_state(&mut state).unwrap();
+        state
+    }
+
+    #[test]
+    fn test_rewind_replays_inputs() {
+        let mut cpu = summing_cpu();
+        let mut rewind = Rewind::new(64, 8);
+        let mut states = Vec::new();
+        for i in 0..400u64 {
+            states.push(state(&cpu));
+            cpu.step().unwrap();
+            rewind.record(&cpu).unwrap();
+            if i % 3 == 0 {
+                rewind.input(&mut cpu, 0xfe, (i * 7) as u8);
+            }
+        }
+        assert_eq!(rewind.position(), 400);
+
+        // states[n] is the state after instruction n, the inputs written at n included
+        let state_at = |position: usize| states.get(position).cloned();
+        assert_eq!(rewind.rewind(&mut cpu, 37).unwrap(), 363);
+        assert_eq!(Some(state(&cpu)), state_at(363));
+        assert_eq!(rewind.rewind_to(&mut cpu, 300).unwrap(), 300);
+        assert_eq!(Some(state(&cpu)), state_at(300));
+        assert!(rewind.rewind_to(&mut cpu, 301).is_err());
+
+        // the history goes on from the rewound position
+        cpu.step().unwrap();
+        rewind.record(&cpu).unwrap();
+        rewind.rewind(&mut cpu, 1).unwrap();
+        assert_eq!(Some(state(&cpu)), state_at(300));
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut cpu = summing_cpu();
+        let mut rewind = Rewind::new(10, 4);
+        for i in 0..1000 {
+            cpu.step().unwrap();
+            rewind.record(&cpu).unwrap();
+            rewind.input(&mut cpu, 0xfe, i as u8);
+        }
+        assert_eq!(rewind.snapshots.len(), 4);
+        assert_eq!(rewind.earliest(), Some(961));
+        assert!(rewind.inputs.len() <= 40);
+        // a 64KiB RAM that is mostly zeroes
+        assert!(rewind.snapshot_bytes() < 8 * 1024);
+
+        assert_eq!(rewind.rewind(&mut cpu, 500).unwrap(), 961);
+        assert_eq!(cpu.mem_read(0xfe), 960u64 as u8);
+    }
+}
@@ -0,0 +1,419 @@
+//! Versioned binary save states.
+//!
+//! A state is a header followed by tagged sections, integers are little endian:
+//!
+//! ```text
+//! b"6502"   magic
+//! u16       format version
+//! then for every section:
+//! [u8; 4]   tag
+//! u32       payload length
+//! [u8]      payload
+//! ```
+//!
+//! The CPU writes its registers in a `CPU ` section and the bus appends its
+//! own through [`Mem::save_sections`]. A device looks its sections up by tag
+//! when loading, so sections nobody asks for are ignored and devices can be
+//! added without breaking existing states.
+//!
+//! Large sections such as the 64KiB of RAM are run length encoded with
+//! [`compress`], which keeps a state of mostly untouched memory to a few KiB.
+
+use crate::{CpuFlags, Mem, CPU};
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 4] = b"6502";
+
+/// Current format version, bumped whenever the layout of a section changes.
+/// Version 2 compresses the RAM section.
+pub const VERSION: u16 = 2;
+
+const CPU_TAG: [u8; 4] = *b"CPU ";
+const CPU_LEN: usize = 17;
+
+/// Sink for the sections of a save state.
+pub struct StateWriter<'a> {
+    output: &'a mut dyn Write,
+}
+
+impl StateWriter<'_> {
+    pub fn section(&mut self, tag: [u8; 4], payload: &[u8]) -> io::Result<()> {
+        self.output.write_all(&tag)?;
+        self.output
+            .write_all(&(payload.len() as u32).to_le_bytes())?;
+        self.output.write_all(payload)
+    }
+}
+
+/// Sections of a save state being loaded.
+#[derive(Clone)]
+pub struct StateReader {
+    version: u16,
+    sections: Vec<([u8; 4], Vec<u8>)>,
+}
+
+impl StateReader {
+    fn parse(input: &mut dyn Read) -> io::Result<Self> {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+
+        if data.len() < 6 || &data[..4] != MAGIC {
+            return Err(invalid("not a save state"));
+        }
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version > VERSION {
+            return Err(invalid("save state written by a newer version"));
+        }
+
+        let mut sections = Vec::new();
+        let mut rest = &data[6..];
+        while !rest.is_empty() {
+            if rest.len() < 8 {
+                return Err(invalid("truncated section header"));
+            }
+            let tag = [rest[0], rest[1], rest[2], rest[3]];
+            let len = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+            rest = &rest[8..];
+            if rest.len() < len {
+                return Err(invalid("truncated section"));
+            }
+            sections.push((tag, rest[..len].to_vec()));
+            rest = &rest[len..];
+        }
+
+        Ok(StateReader { version, sections })
+    }
+
+    /// Format version the state was written with
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Takes the payload of the first section tagged `tag` that has not been
+    /// taken yet, so a device mapped several times gets its sections back in
+    /// the order they were saved.
+    pub fn section(&mut self, tag: [u8; 4]) -> io::Result<Vec<u8>> {
+        match self.sections.iter().position(|(t, _)| *t == tag) {
+            Some(idx) => Ok(self.sections.remove(idx).1),
+            None => Err(invalid(format!(
+                "missing section {}",
+                String::from_utf8_lossy(&tag)
+            ))),
+        }
+    }
+}
+
+/// Error for a state that can not be loaded
+pub fn invalid<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Run length encoding: a control byte below 0x80 is followed by that many
+/// plus one literal bytes, one from 0x80 up repeats the next byte that many
+/// minus 0x7e times. Memory is mostly long runs of untouched bytes.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut compressed = Vec::new();
+    let mut literals: Vec<u8> = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let run = data[i..]
+            .iter()
+            .take(129)
+            .take_while(|&&byte| byte == data[i])
+            .count();
+        if run >= 2 {
+            flush_literals(&mut compressed, &mut literals);
+            compressed.push((run + 0x7e) as u8);
+            compressed.push(data[i]);
+            i += run;
+        } else {
+            literals.push(data[i]);
+            if literals.len() == 128 {
+                flush_literals(&mut compressed, &mut literals);
+            }
+            i += 1;
+        }
+    }
+    flush_literals(&mut compressed, &mut literals);
+    compressed
+}
+
+fn flush_literals(compressed: &mut Vec<u8>, literals: &mut Vec<u8>) {
+    if !literals.is_empty() {
+        compressed.push(literals.len() as u8 - 1);
+        compressed.append(literals);
+    }
+}
+
+/// Reverses [`compress`]
+pub fn decompress(compressed: &[u8]) -> io::Result<Vec<u8>> {
+    let truncated = || invalid("truncated compressed section");
+    let mut data = Vec::new();
+    let mut i = 0;
+    while i < compressed.len() {
+        let control = compressed[i] as usize;
+        if control < 0x80 {
+            let literals = compressed
+                .get(i + 1..i + 2 + control)
+                .ok_or_else(truncated)?;
+            data.extend_from_slice(literals);
+            i += 2 + control;
+        } else {
+            let byte = *compressed.get(i + 1).ok_or_else(truncated)?;
+            data.resize(data.len() + control - 0x7e, byte);
+            i += 2;
+        }
+    }
+    Ok(data)
+}
+
+impl<B: Mem> CPU<B> {
+    /// Writes the registers, the interrupt lines and the state of the bus.
+    ///
+    /// A state can only be taken between two instructions, not while `clock`
+    /// is in the middle of one.
+    pub fn save_state<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        if self.in_flight.is_some() {
+            return Err(io::Error::other("an instruction is in flight"));
+        }
+
+        output.write_all(MAGIC)?;
+        output.write_all(&VERSION.to_le_bytes())?;
+
+        let mut payload = Vec::with_capacity(CPU_LEN);
+        payload.extend_from_slice(&[
+            self.register_a,
+            self.register_x,
+            self.register_y,
+            self.status.bits(),
+            self.stack_pointer,
+        ]);
+        payload.extend_from_slice(&self.program_counter.to_le_bytes());
+        payload.extend_from_slice(&self.cycles.to_le_bytes());
+        payload.push(self.variant as u8);
+        payload.push(
+            self.waiting as u8
+                | (self.nmi_line as u8) << 1
+                | (self.nmi_pending as u8) << 2
+                | (self.irq_line as u8) << 3,
+        );
+
+        let mut state = StateWriter { output };
+        state.section(CPU_TAG, &payload)?;
+        self.bus.save_sections(&mut state)
+    }
+
+    /// Restores a state written by [`CPU::save_state`]. The state must come
+    /// from a CPU of the same variant.
+    pub fn load_state<R: Read>(&mut self, input: &mut R) -> io::Result<()> {
+        let mut state = StateReader::parse(input)?;
+
+        let payload = state.section(CPU_TAG)?;
+        if payload.len() != CPU_LEN {
+            return Err(invalid("bad CPU section"));
+        }
+        if payload[15] != self.variant as u8 {
+            return Err(invalid(format!(
+                "save state is not for a {:?}",
+                self.variant
+            )));
+        }
+
+        // the sections are all checked before anything is restored
+        self.bus.check_sections(&mut state.clone())?;
+        self.bus.load_sections(&mut state)?;
+
+        self.register_a = payload[0];
+        self.register_x = payload[1];
+        self.register_y = payload[2];
+        self.status = CpuFlags::from_bits_truncate(payload[3]);
+        self.stack_pointer = payload[4];
+        self.program_counter = u16::from_le_bytes([payload[5], payload[6]]);
+        let mut cycles = [0; 8];
+        cycles.copy_from_slice(&payload[7..15]);
+        self.cycles = u64::from_le_bytes(cycles);
+        self.waiting = payload[16] & 0x01 != 0;
+        self.nmi_line = payload[16] & 0x02 != 0;
+        self.nmi_pending = payload[16] & 0x04 != 0;
+        self.irq_line = payload[16] & 0x08 != 0;
+        self.in_flight = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{MappedBus, Rom};
+    use crate::CpuVariant;
+
+    #[test]
+    fn test_state_round_trip() {
+        // LDX #$00; loop: INX; STX $0200,X; JMP loop
+        let program = vec![0xa2, 0x00, 0xe8, 0x9d, 0x00, 0x02, 0x4c, 0x02, 0x06];
+        let mut cpu = CPU::new();
+        cpu.load(program);
+        cpu.reset();
+        for _ in 0..100 {
+            cpu.step().unwrap();
+        }
+
+        let mut saved = Vec::new();
+        cpu.save_state(&mut saved).unwrap();
+        // the 64KiB of RAM are compressed
+        assert!(saved.len() < 2048);
+        for _ in 0..100 {
+            cpu.step().unwrap();
+        }
+
+        let mut restored = CPU::new();
+        restored.load_state(&mut saved.as_slice()).unwrap();
+        for _ in 0..100 {
+            restored.step().unwrap();
+        }
+
+        assert_eq!(restored.register_x, cpu.register_x);
+        assert_eq!(restored.program_counter, cpu.program_counter);
+        assert_eq!(restored.status, cpu.status);
+        assert_eq!(restored.cycles, cpu.cycles);
+        for addr in 0x0200..0x0300 {
+            assert_eq!(restored.mem_peek(addr), cpu.mem_peek(addr));
+        }
+    }
+
+    struct Latch {
+        value: u8,
+    }
+
+    impl Mem for Latch {
+        fn mem_read(&mut self, _addr: u16) -> u8 {
+            self.value
+        }
+
+        fn mem_write(&mut self, _addr: u16, data: u8) {
+            self.value = data;
+        }
+
+        fn mem_peek(&self, _addr: u16) -> u8 {
+            self.value
+        }
+
+        fn save_sections(&self, state: &mut StateWriter) -> io::Result<()> {
+            state.section(*b"LTCH", &[self.value])
+        }
+
+        fn check_sections(&self, state: &mut StateReader) -> io::Result<()> {
+            match state.section(*b"LTCH")?.len() {
+                1 => Ok(()),
+                _ => Err(invalid("bad LTCH section")),
+            }
+        }
+
+        fn load_sections(&mut self, state: &mut StateReader) -> io::Result<()> {
+            self.value = state.section(*b"LTCH")?[0];
+            Ok(())
+        }
+    }
+
+    fn mapped_bus() -> MappedBus {
+        let mut bus = MappedBus::new();
+        bus.map(0x4000..=0x4000, Latch { value: 0 });
+        bus.map(0x4001..=0x4001, Latch { value: 0 });
+        bus.map(0x8000..=0xffff, Rom::new(vec![0xea; 0x100]));
+        bus
+    }
+
+    #[test]
+    fn test_devices_save_their_sections() {
+        let mut cpu = CPU::with_bus(mapped_bus());
+        cpu.mem_write(0x4000, 0x12);
+        cpu.mem_write(0x4001, 0x34);
+        cpu.mem_write(0x10, 0x56);
+
+        let mut saved = Vec::new();
+        cpu.save_state(&mut saved).unwrap();
+
+        let mut restored = CPU::with_bus(mapped_bus());
+        restored.load_state(&mut saved.as_slice()).unwrap();
+        assert_eq!(restored.mem_peek(0x4000), 0x12);
+        assert_eq!(restored.mem_peek(0x4001), 0x34);
+        assert_eq!(restored.mem_peek(0x10), 0x56);
+    }
+
+    #[test]
+    fn test_failed_load_changes_nothing() {
+        let mut cpu = CPU::with_bus(mapped_bus());
+        cpu.mem_write(0x4000, 0x12);
+        cpu.mem_write(0x4001, 0x34);
+        cpu.mem_write(0x10, 0x56);
+        cpu.register_a = 0x78;
+        let mut saved = Vec::new();
+        cpu.save_state(&mut saved).unwrap();
+        // the last section is the second latch, give it a payload of two bytes
+        saved.truncate(saved.len() - 5);
+        saved.extend_from_slice(&[2, 0, 0, 0, 0x9a, 0xbc]);
+
+        let mut target = CPU::with_bus(mapped_bus());
+        target.mem_write(0x4000, 0x01);
+        target.mem_write(0x4001, 0x02);
+        target.mem_write(0x10, 0x03);
+        let error = target.load_state(&mut saved.as_slice()).unwrap_err();
+        assert_eq!(error.to_string(), "bad LTCH section");
+        assert_eq!(target.mem_peek(0x4000), 0x01);
+        assert_eq!(target.mem_peek(0x4001), 0x02);
+        assert_eq!(target.mem_peek(0x10), 0x03);
+        assert_eq!(target.register_a, 0);
+    }
+
+    #[test]
+    fn test_compression_round_trip() {
+        let mut data = vec![0; 1000];
+        data.extend((0..=255).cycle().take(300));
+        data.extend([7, 7, 1, 7]);
+        let compressed = compress(&data);
+        assert!(compressed.len() < 350);
+        assert_eq!(decompress(&compressed).unwrap(), data);
+        assert_eq!(decompress(&[]).unwrap(), Vec::<u8>::new());
+        assert!(decompress(&[0x05, 1, 2]).is_err());
+    }
+
+    #[test]
+    fn test_loads_version_1_states() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x1234, 0x56);
+        let mut saved = Vec::new();
+        cpu.save_state(&mut saved).unwrap();
+
+        // the header and the CPU section, followed by the RAM stored raw
+        let mut old = saved[..6 + 8 + CPU_LEN].to_vec();
+        old[4..6].copy_from_slice(&1u16.to_le_bytes());
+        let mut memory = vec![0; 0x10000];
+        memory[0x1234] = 0x56;
+        StateWriter { output: &mut old }
+            .section(*b"RAM ", &memory)
+            .unwrap();
+
+        let mut restored = CPU::new();
+        restored.load_state(&mut old.as_slice()).unwrap();
+        assert_eq!(restored.mem_peek(0x1234), 0x56);
+    }
+
+    #[test]
+    fn test_rejects_invalid_states() {
+        let mut cpu = CPU::new();
+        let mut saved = Vec::new();
+        cpu.save_state(&mut saved).unwrap();
+
+        let error = cpu.load_state(&mut &b"nope"[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let truncated = &saved[..saved.len() - 1];
+        assert!(cpu.load_state(&mut &truncated[..]).is_err());
+
+        let mut cmos = CPU::with_variant(CpuVariant::Wdc65C02);
+        assert!(cmos.load_state(&mut saved.as_slice()).is_err());
+    }
+}
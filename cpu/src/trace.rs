@@ -0,0 +1,211 @@
+//! Execution traces in the layout of nestest.log, one line per instruction:
+//!
+//! ```text
+//! C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7
+//! ```
+//!
+//! Operands are shown with the address they resolve to and the value stored
+//! there, read with `mem_peek` so tracing has no effect on devices. The PPU
+//! column of the reference logs is left out, there is no PPU in this crate.
+
+use crate::opcode::OpCode;
+use crate::{AddressingMode, Mem, CPU};
+
+/// Formats the instruction at the program counter and the registers before it executes.
+pub fn trace<B: Mem>(cpu: &CPU<B>) -> String {
+    let pc = cpu.program_counter;
+    let code = cpu.mem_peek(pc);
+
+    let (len, asm) = match cpu.variant().decode(code) {
+        Some(opcode) => {
+            // nestest marks the undocumented opcodes with a star
+            let marker = if opcode.undocumented { '*' } else { ' ' };
+            let asm = format!("{}{} {}", marker, opcode.mnemonic, operand(cpu, opcode));
+            (opcode.len, asm)
+        }
+        None => (1, " ???".to_string()),
+    };
+
+    let bytes: Vec<String> = (0..len)
+        .map(|i| format!("{:02X}", cpu.mem_peek(pc.wrapping_add(i as u16))))
+        .collect();
+
+    format!(
+        "{:04X}  {:8} {:32} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+        pc,
+        bytes.join(" "),
+        asm.trim_end(),
+        cpu.register_a,
+        cpu.register_x,
+        cpu.register_y,
+        cpu.status.bits(),
+        cpu.stack_pointer,
+        cpu.cycles
+    )
+}
+
+fn operand<B: Mem>(cpu: &CPU<B>, opcode: &OpCode) -> String {
+    let pc = cpu.program_counter;
+    let byte = cpu.mem_peek(pc.wrapping_add(1));
+    let word = u16::from_le_bytes([byte, cpu.mem_peek(pc.wrapping_add(2))]);
+    let peek = |addr: u16| cpu.mem_peek(addr);
+    let peek_u16 = |lo: u16, hi: u16| u16::from_le_bytes([peek(lo), peek(hi)]);
+    let zero_page_pointer = |ptr: u8| peek_u16(ptr as u16, ptr.wrapping_add(1) as u16);
+    let relative = |next: u16, offset: u8| next.wrapping_add(offset as i8 as u16);
+
+    match opcode.mode {
+        AddressingMode::Implied => String::new(),
+        AddressingMode::Accumulator => "A".to_string(),
+        AddressingMode::Immediate => format!("#${:02X}", byte),
+        AddressingMode::ZeroPage => format!("${:02X} = {:02X}", byte, peek(byte as u16)),
+        AddressingMode::ZeroPage_X | AddressingMode::ZeroPage_Y => {
+            let (index, name) = if opcode.mode == AddressingMode::ZeroPage_X {
+                (cpu.register_x, 'X')
+            } else {
+                (cpu.register_y, 'Y')
+            };
+            let addr = byte.wrapping_add(index);
+            format!(
+                "${:02X},{} @ {:02X} = {:02X}",
+                byte,
+                name,
+                addr,
+                peek(addr as u16)
+            )
+        }
+        // jump targets are not operands
+        AddressingMode::Absolute if opcode.mnemonic == "JMP" || opcode.mnemonic == "JSR" => {
+            format!("${:04X}", word)
+        }
+        AddressingMode::Absolute => format!("${:04X} = {:02X}", word, peek(word)),
+        AddressingMode::Absolute_X | AddressingMode::Absolute_Y => {
+            let (index, name) = if opcode.mode == AddressingMode::Absolute_X {
+                (cpu.register_x, 'X')
+            } else {
+                (cpu.register_y, 'Y')
+            };
+            let addr = word.wrapping_add(index as u16);
+            format!("${:04X},{} @ {:04X} = {:02X}", word, name, addr, peek(addr))
+        }
+        AddressingMode::Indirect_X => {
+            let ptr = byte.wrapping_add(cpu.register_x);
+            let addr = zero_page_pointer(ptr);
+            format!(
+                "(${:02X},X) @ {:02X} = {:04X} = {:02X}",
+                byte,
+                ptr,
+                addr,
+                peek(addr)
+            )
+        }
+        AddressingMode::Indirect_Y => {
+            let base = zero_page_pointer(byte);
+            let addr = base.wrapping_add(cpu.register_y as u16);
+            format!(
+                "(${:02X}),Y = {:04X} @ {:04X} = {:02X}",
+                byte,
+                base,
+                addr,
+                peek(addr)
+            )
+        }
+        AddressingMode::ZeroPage_Indirect => {
+            let addr = zero_page_pointer(byte);
+            format!("(${:02X}) = {:04X} = {:02X}", byte, addr, peek(addr))
+        }
+        AddressingMode::Relative => format!("${:04X}", relative(pc.wrapping_add(2), byte)),
+        AddressingMode::Indirect => {
+            let hi = if word & 0x00ff == 0x00ff && cpu.variant().has_jmp_indirect_bug() {
+                word & 0xff00
+            } else {
+                word.wrapping_add(1)
+            };
+            format!("(${:04X}) = {:04X}", word, peek_u16(word, hi))
+        }
+        AddressingMode::Absolute_Indexed_Indirect => {
+            let ptr = word.wrapping_add(cpu.register_x as u16);
+            let addr = peek_u16(ptr, ptr.wrapping_add(1));
+            format!("(${:04X},X) = {:04X}", word, addr)
+        }
+        AddressingMode::ZeroPage_Relative => {
+            let offset = peek(pc.wrapping_add(2));
+            let target = relative(pc.wrapping_add(3), offset);
+            format!("${:02X},${:04X}", byte, target)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    fn cpu_at(pc: u16, program: &[u8]) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.reset();
+        for (i, byte) in program.iter().enumerate() {
+            cpu.mem_write(pc + i as u16, *byte);
+        }
+        cpu.program_counter = pc;
+        cpu
+    }
+
+    #[test]
+    fn test_format_matches_nestest() {
+        let cpu = cpu_at(0xc000, &[0x4c, 0xf5, 0xc5]);
+        assert_eq!(
+            trace(&cpu),
+            "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7"
+        );
+
+        let mut cpu = cpu_at(0x0064, &[0x11, 0x33]);
+        cpu.mem_write(0x33, 0x00);
+        cpu.mem_write(0x34, 0x04);
+        cpu.mem_write(0x0405, 0xaa);
+        cpu.register_y = 5;
+        assert_eq!(
+            trace(&cpu),
+            "0064  11 33     ORA ($33),Y = 0400 @ 0405 = AA  A:00 X:00 Y:05 P:24 SP:FD CYC:7"
+        );
+
+        let cpu = cpu_at(0x0600, &[0x04, 0xa9]);
+        assert_eq!(
+            trace(&cpu),
+            "0600  04 A9    *NOP $A9 = 00                    A:00 X:00 Y:00 P:24 SP:FD CYC:7"
+        );
+    }
+
+    /// Trace output that stays readable after being handed to the CPU
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_tracer_logs_every_instruction() {
+        let output = Shared::default();
+        let mut cpu = cpu_at(0x0600, &[0xa2, 0x01, 0xca, 0x88]);
+        cpu.set_tracer(Some(Box::new(output.clone())));
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        cpu.set_tracer(None);
+        cpu.step().unwrap();
+
+        let log = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert_eq!(
+            log,
+            "0600  A2 01     LDX #$01                        A:00 X:00 Y:00 P:24 SP:FD CYC:7\n\
+             0602  CA        DEX                             A:00 X:01 Y:00 P:24 SP:FD CYC:9\n"
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use crate::opcode::{self, OpCode};
+use std::fmt;
+use std::str::FromStr;
+
+/// The flavour of 6502 being emulated. It is chosen when the `CPU` is built
+/// and decides the opcode table used by the decoder along with the handful of
+/// behaviours that differ between chips.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CpuVariant {
+    /// The original MOS 6502, undocumented opcodes included
+    #[default]
+    Nmos6502,
+    /// The NES CPU: an NMOS 6502 core without the decimal mode circuitry
+    Ricoh2A03,
+    /// The WDC 65C02 CMOS redesign
+    Wdc65C02,
+}
+
+impl CpuVariant {
+    /// Whether ADC and SBC honour the decimal flag
+    pub fn has_decimal_mode(self) -> bool {
+        self != CpuVariant::Ricoh2A03
+    }
+
+    /// Whether the undocumented NMOS opcodes (LAX, SAX, DCP...) are decoded.
+    /// On the 65C02 they are all NOPs.
+    pub fn has_undocumented_opcodes(self) -> bool {
+        self != CpuVariant::Wdc65C02
+    }
+
+    /// Whether `JMP ($xxFF)` fetches the high byte from `$xx00` instead of the next page
+    pub fn has_jmp_indirect_bug(self) -> bool {
+        self != CpuVariant::Wdc65C02
+    }
+
+    /// Whether BRK and hardware interrupts clear the decimal flag
+    pub fn clears_decimal_on_interrupt(self) -> bool {
+        self == CpuVariant::Wdc65C02
+    }
+
+    /// Whether this is a CMOS part, with its extra instructions and fixed decimal mode flags
+    pub fn is_cmos(self) -> bool {
+        self == CpuVariant::Wdc65C02
+    }
+
+    /// Every opcode of this variant, in opcode order
+    pub fn opcodes(self) -> impl Iterator<Item = &'static OpCode> {
+        self.opcode_table().iter().flatten()
+    }
+
+    /// Looks up the opcode decoded from `code`
+    pub fn decode(self, code: u8) -> Option<&'static OpCode> {
+        self.opcode_table()[code as usize].as_ref()
+    }
+
+    fn opcode_table(self) -> &'static [Option<OpCode>; 256] {
+        if self.is_cmos() {
+            &opcode::CMOS_OPCODE_TABLE
+        } else {
+            &opcode::OPCODE_TABLE
+        }
+    }
+}
+
+/// Why a variant name could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownVariant {
+    pub name: String,
+}
+
+impl fmt::Display for UnknownVariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown variant {}", self.name)
+    }
+}
+
+impl std::error::Error for UnknownVariant {}
+
+/// Parses the names the tools take on their command line: `6502`, `2a03` or `65c02`.
+impl FromStr for CpuVariant {
+    type Err = UnknownVariant;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "6502" => Ok(CpuVariant::Nmos6502),
+            "2a03" => Ok(CpuVariant::Ricoh2A03),
+            "65c02" => Ok(CpuVariant::Wdc65C02),
+            _ => Err(UnknownVariant {
+                name: name.to_string(),
+            }),
+        }
+    }
+}
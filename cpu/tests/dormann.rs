@@ -0,0 +1,102 @@
+//! Klaus Dormann's 6502 test suites,
+//! https://github.com/Klaus2m5/6502_65C02_functional_tests
+//!
+//! The binaries are not part of the repository, see `tests/fixtures/README.md`,
+//! so the tests are ignored by default. `tests/fixtures/fetch.sh` downloads the
+//! functional test, the decimal test has to be assembled. Once the binaries are
+//! in place run them with `cargo test --release --test dormann -- --ignored`, a
+//! missing binary fails the test.
+
+use cpu::{Mem, CPU};
+use std::fs;
+use std::path::PathBuf;
+
+/// The functional test runs about 30 million instructions before reaching its success trap
+const MAX_INSTRUCTIONS: u64 = 100_000_000;
+
+/// `6502_functional_test.bin` is a full 64KiB image starting with the zero page
+const FUNCTIONAL_START: u16 = 0x0400;
+/// Address of the success trap in the prebuilt binary, a failing check traps anywhere else
+const FUNCTIONAL_SUCCESS: u16 = 0x3469;
+
+/// `6502_decimal_test.bin` is assembled at $0200
+const DECIMAL_ORIGIN: u16 = 0x0200;
+/// The decimal test ends on `end_of_test`, which assembles to the 65C02 STP opcode
+const DECIMAL_END: u8 = 0xdb;
+/// Zero page byte the decimal test clears when every result was right
+const DECIMAL_ERROR: u16 = 0x000b;
+
+fn fixture(name: &str) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    fs::read(&path)
+        .unwrap_or_else(|e| panic!("{}: {}, see tests/fixtures/README.md", path.display(), e))
+}
+
+fn load(image: &[u8], origin: u16) -> CPU {
+    let mut cpu = CPU::new();
+    for (i, byte) in image.iter().enumerate() {
+        cpu.mem_write(origin.wrapping_add(i as u16), *byte);
+    }
+    cpu
+}
+
+/// Runs until the program traps in a jump or branch to itself, or until
+/// `end` returns true. Returns the address it stopped at.
+fn run_to_trap<F>(cpu: &mut CPU, end: F) -> u16
+where
+    F: Fn(&CPU) -> bool,
+{
+    for _ in 0..MAX_INSTRUCTIONS {
+        let pc = cpu.program_counter;
+        if end(cpu) {
+            return pc;
+        }
+        if let Err(e) = cpu.step() {
+            panic!("{} (after {} cycles)", e, cpu.cycles);
+        }
+        if cpu.program_counter == pc {
+            return pc;
+        }
+    }
+    panic!("no trap after {} instructions", MAX_INSTRUCTIONS);
+}
+
+#[test]
+#[ignore = "needs tests/fixtures/fetch.sh"]
+fn functional_test() {
+    let image = fixture("6502_functional_test.bin");
+    let mut cpu = load(&image, 0x0000);
+    cpu.program_counter = FUNCTIONAL_START;
+
+    let trap = run_to_trap(&mut cpu, |_| false);
+    assert_eq!(
+        trap,
+        FUNCTIONAL_SUCCESS,
+        "functional test trapped at ${:04x}, A=${:02x} X=${:02x} Y=${:02x} P=${:02x}",
+        trap,
+        cpu.register_a,
+        cpu.register_x,
+        cpu.register_y,
+        cpu.status.bits()
+    );
+}
+
+#[test]
+#[ignore = "needs tests/fixtures/6502_decimal_test.bin"]
+fn decimal_test() {
+    let image = fixture("6502_decimal_test.bin");
+    let mut cpu = load(&image, DECIMAL_ORIGIN);
+    cpu.program_counter = DECIMAL_ORIGIN;
+
+    let end = run_to_trap(&mut cpu, |cpu| {
+        cpu.mem_peek(cpu.program_counter) == DECIMAL_END
+    });
+    assert_eq!(
+        cpu.mem_peek(DECIMAL_ERROR),
+        0,
+        "decimal test failed, stopped at ${:04x}",
+        end
+    );
+}
@@ -0,0 +1,54 @@
+#!/bin/sh
+# Downloads the test fixtures that are not vendored, see README.md.
+#
+#     tests/fixtures/fetch.sh [tests per opcode]
+#
+# The SingleStepTests files hold 10000 tests per opcode, only the first ones
+# are kept, 100 by default. The Dormann decimal test has no prebuilt binary,
+# it has to be assembled by hand.
+
+set -eu
+cd "$(dirname "$0")"
+TESTS=${1:-100}
+
+DORMANN=https://raw.githubusercontent.com/Klaus2m5/6502_65C02_functional_tests/master
+NESTEST=https://www.qmtpro.com/~nes/misc
+SINGLE_STEP=https://raw.githubusercontent.com/SingleStepTests/65x02/main
+
+download() {
+    echo "$2"
+    curl -fsSL -o "$2" "$1"
+}
+
+# keeps the first $TESTS tests of a SingleStepTests file
+trim() {
+    python3 - "$1" "$TESTS" <<'EOF'
+import json, sys
+with open(sys.argv[1]) as f:
+    tests = json.load(f)[:int(sys.argv[2])]
+with open(sys.argv[1], "w") as f:
+    f.write("[\n" + ",\n".join(json.dumps(test) for test in tests) + "\n]\n")
+EOF
+}
+
+download "$DORMANN/bin_files/6502_functional_test.bin" 6502_functional_test.bin
+download "$NESTEST/nestest.nes" nestest.nes
+download "$NESTEST/nestest.log" nestest.log
+
+# opcodes the crate reports as errors instead of executing: the NMOS JAMs and STP
+for cpu in 6502 nes6502 wdc65c02; do
+    case $cpu in
+        wdc65c02) stops="db" ;;
+        *) stops="02 12 22 32 42 52 62 72 92 b2 d2 f2" ;;
+    esac
+    mkdir -p "single_step/upstream/$cpu"
+    for code in $(seq 0 255); do
+        opcode=$(printf %02x "$code")
+        if echo "$stops" | grep -qw "$opcode"; then
+            continue
+        fi
+        file="single_step/upstream/$cpu/$opcode.json"
+        download "$SINGLE_STEP/$cpu/v1/$opcode.json" "$file"
+        trim "$file"
+    done
+done
@@ -0,0 +1,577 @@
+#!/usr/bin/env python3
+"""Generates the SingleStepTests fixtures of every opcode but the JAMs.
+
+The expected states and bus activity come from the model below, written from
+the cycle by cycle tables of 64doc (http://www.6502.org/users/andre/petindex/
+local/64doc.txt) and the decimal mode appendix of
+http://www.6502.org/tutorials/decimal_mode.html, independently of the crate.
+Every test starts from random registers and memory, the generator is seeded so
+running it again gives the same files.
+
+    python3 generate.py [tests per opcode]
+
+The hand checked tests of `hand/<opcode>.json` come first in the 6502 files.
+"""
+
+import json
+import os
+import random
+import sys
+
+C, Z, I, D, B, U, V, N = 0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80
+
+# addressing mode of every opcode, the ones missing jam the CPU
+MODES = {}
+NAMES = {}
+
+
+def define(name, mode, *codes):
+    for code in codes:
+        assert code not in MODES, hex(code)
+        MODES[code] = mode
+        NAMES[code] = name
+
+
+# the eight modes of the `cc = 01` and `cc = 11` columns
+GROUP = ("izx", "zp", "imm", "abs", "izy", "zpx", "absy", "absx")
+
+
+def group(name, base, skip=()):
+    for i, mode in enumerate(GROUP):
+        if i not in skip:
+            define(name, mode, base + i * 4)
+
+
+for name, base in (("ORA", 0x01), ("AND", 0x21), ("EOR", 0x41), ("ADC", 0x61),
+                   ("LDA", 0xA1), ("CMP", 0xC1), ("SBC", 0xE1)):
+    group(name, base)
+group("STA", 0x81, skip=(2,))
+for name, base in (("SLO", 0x03), ("RLA", 0x23), ("SRE", 0x43), ("RRA", 0x63),
+                   ("DCP", 0xC3), ("ISC", 0xE3)):
+    group(name, base, skip=(2,))
+define("SAX", "izx", 0x83)
+define("SAX", "zp", 0x87)
+define("SAX", "abs", 0x8F)
+define("SAX", "zpy", 0x97)
+define("LAX", "izx", 0xA3)
+define("LAX", "zp", 0xA7)
+define("LAX", "abs", 0xAF)
+define("LAX", "izy", 0xB3)
+define("LAX", "zpy", 0xB7)
+define("LAX", "absy", 0xBF)
+define("SHA", "izy", 0x93)
+define("SHA", "absy", 0x9F)
+define("TAS", "absy", 0x9B)
+define("SHY", "absx", 0x9C)
+define("SHX", "absy", 0x9E)
+define("LAS", "absy", 0xBB)
+
+for name, base in (("ASL", 0x06), ("ROL", 0x26), ("LSR", 0x46), ("ROR", 0x66)):
+    define(name, "zp", base)
+    define(name, "acc", base + 0x04)
+    define(name, "abs", base + 0x08)
+    define(name, "zpx", base + 0x10)
+    define(name, "absx", base + 0x18)
+for name, base in (("DEC", 0xC6), ("INC", 0xE6)):
+    define(name, "zp", base)
+    define(name, "abs", base + 0x08)
+    define(name, "zpx", base + 0x10)
+    define(name, "absx", base + 0x18)
+define("STX", "zp", 0x86)
+define("STX", "abs", 0x8E)
+define("STX", "zpy", 0x96)
+define("STY", "zp", 0x84)
+define("STY", "abs", 0x8C)
+define("STY", "zpx", 0x94)
+define("LDX", "imm", 0xA2)
+define("LDX", "zp", 0xA6)
+define("LDX", "abs", 0xAE)
+define("LDX", "zpy", 0xB6)
+define("LDX", "absy", 0xBE)
+define("LDY", "imm", 0xA0)
+define("LDY", "zp", 0xA4)
+define("LDY", "abs", 0xAC)
+define("LDY", "zpx", 0xB4)
+define("LDY", "absx", 0xBC)
+define("CPX", "imm", 0xE0)
+define("CPX", "zp", 0xE4)
+define("CPX", "abs", 0xEC)
+define("CPY", "imm", 0xC0)
+define("CPY", "zp", 0xC4)
+define("CPY", "abs", 0xCC)
+define("BIT", "zp", 0x24)
+define("BIT", "abs", 0x2C)
+
+for name, code in (("BPL", 0x10), ("BMI", 0x30), ("BVC", 0x50), ("BVS", 0x70),
+                   ("BCC", 0x90), ("BCS", 0xB0), ("BNE", 0xD0), ("BEQ", 0xF0)):
+    define(name, "rel", code)
+for name, code in (("CLC", 0x18), ("SEC", 0x38), ("CLI", 0x58), ("SEI", 0x78),
+                   ("CLV", 0xB8), ("CLD", 0xD8), ("SED", 0xF8), ("DEY", 0x88),
+                   ("TYA", 0x98), ("TAY", 0xA8), ("INY", 0xC8), ("INX", 0xE8),
+                   ("TXA", 0x8A), ("TXS", 0x9A), ("TAX", 0xAA), ("TSX", 0xBA),
+                   ("DEX", 0xCA), ("NOP", 0xEA)):
+    define(name, "imp", code)
+define("NOP", "imp", 0x1A, 0x3A, 0x5A, 0x7A, 0xDA, 0xFA)
+define("NOP", "imm", 0x80, 0x82, 0x89, 0xC2, 0xE2)
+define("NOP", "zp", 0x04, 0x44, 0x64)
+define("NOP", "zpx", 0x14, 0x34, 0x54, 0x74, 0xD4, 0xF4)
+define("NOP", "abs", 0x0C)
+define("NOP", "absx", 0x1C, 0x3C, 0x5C, 0x7C, 0xDC, 0xFC)
+define("ANC", "imm", 0x0B, 0x2B)
+define("ALR", "imm", 0x4B)
+define("ARR", "imm", 0x6B)
+define("XAA", "imm", 0x8B)
+define("LXA", "imm", 0xAB)
+define("SBX", "imm", 0xCB)
+define("SBC", "imm", 0xEB)
+for name, code in (("BRK", 0x00), ("PHP", 0x08), ("PLP", 0x28), ("PHA", 0x48),
+                   ("PLA", 0x68), ("JSR", 0x20), ("RTI", 0x40), ("RTS", 0x60)):
+    define(name, "stack", code)
+define("JMP", "abs", 0x4C)
+define("JMP", "ind", 0x6C)
+
+JAMS = {0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xB2, 0xD2, 0xF2}
+assert len(MODES) == 256 - len(JAMS) and not JAMS & set(MODES)
+
+READS = {"LDA", "LDX", "LDY", "LAX", "AND", "ORA", "EOR", "ADC", "SBC", "CMP",
+         "CPX", "CPY", "BIT", "NOP", "LAS", "ANC", "ALR", "ARR", "XAA", "LXA",
+         "SBX"}
+WRITES = {"STA", "STX", "STY", "SAX"}
+MODIFIES = {"ASL", "LSR", "ROL", "ROR", "INC", "DEC", "SLO", "RLA", "SRE",
+            "RRA", "DCP", "ISC"}
+STORES_AND_HIGH = {"SHA", "SHX", "SHY", "TAS"}
+# constant OR-ed into A by XAA and LXA, the one most chips have
+MAGIC = 0xEE
+
+LENGTHS = {"imp": 1, "acc": 1, "imm": 2, "zp": 2, "zpx": 2, "zpy": 2, "izx": 2,
+           "izy": 2, "rel": 2, "abs": 3, "absx": 3, "absy": 3, "ind": 3}
+STACK_LENGTHS = {0x00: 2, 0x20: 3}
+
+
+class Cpu:
+    def __init__(self, rng, decimal):
+        self.rng = rng
+        self.decimal = decimal
+        self.memory = {}
+        self.initial = {}
+        self.cycles = []
+
+    # bus
+
+    def peek(self, addr):
+        if addr not in self.memory:
+            self.memory[addr] = self.initial[addr] = self.rng.randrange(256)
+        return self.memory[addr]
+
+    def read(self, addr):
+        value = self.peek(addr)
+        self.cycles.append([addr, value, "read"])
+        return value
+
+    def write(self, addr, value):
+        self.peek(addr)
+        self.memory[addr] = value
+        self.cycles.append([addr, value, "write"])
+
+    def fetch(self):
+        value = self.read(self.pc)
+        self.pc = (self.pc + 1) & 0xFFFF
+        return value
+
+    def push(self, value):
+        self.write(0x100 | self.s, value)
+        self.s = (self.s - 1) & 0xFF
+
+    def pull(self):
+        self.s = (self.s + 1) & 0xFF
+        return self.read(0x100 | self.s)
+
+    # flags
+
+    def flag(self, mask):
+        return 1 if self.p & mask else 0
+
+    def set(self, mask, on):
+        self.p = self.p | mask if on else self.p & ~mask & 0xFF
+
+    def nz(self, value):
+        self.set(N, value & 0x80)
+        self.set(Z, value == 0)
+        return value
+
+    # addressing
+
+    def indexed(self, base, index, kind):
+        """Adds an index to `base`, a read that does not cross a page skips
+        the read at the unfixed address."""
+        fixed = (base + index) & 0xFFFF
+        unfixed = (base & 0xFF00) | (fixed & 0xFF)
+        if kind != "read" or unfixed != fixed:
+            self.read(unfixed)
+        return fixed
+
+    def address(self, mode, kind):
+        if mode == "zp":
+            return self.fetch()
+        if mode in ("zpx", "zpy"):
+            base = self.fetch()
+            self.read(base)
+            return (base + (self.x if mode == "zpx" else self.y)) & 0xFF
+        if mode == "abs":
+            lo = self.fetch()
+            return self.fetch() << 8 | lo
+        if mode in ("absx", "absy"):
+            lo = self.fetch()
+            base = self.fetch() << 8 | lo
+            return self.indexed(base, self.x if mode == "absx" else self.y, kind)
+        if mode == "izx":
+            pointer = self.fetch()
+            self.read(pointer)
+            pointer = (pointer + self.x) & 0xFF
+            lo = self.read(pointer)
+            return self.read((pointer + 1) & 0xFF) << 8 | lo
+        if mode == "izy":
+            pointer = self.fetch()
+            lo = self.read(pointer)
+            base = self.read((pointer + 1) & 0xFF) << 8 | lo
+            return self.indexed(base, self.y, kind)
+        raise ValueError(mode)
+
+    def base_and_index(self, mode):
+        """Operand of the SHA/SHX/SHY/TAS stores, before indexing"""
+        if mode == "izy":
+            pointer = self.fetch()
+            lo = self.read(pointer)
+            return self.read((pointer + 1) & 0xFF) << 8 | lo, self.y
+        lo = self.fetch()
+        base = self.fetch() << 8 | lo
+        return base, self.x if mode == "absx" else self.y
+
+    # arithmetic
+
+    def adc(self, value):
+        a, carry = self.a, self.flag(C)
+        binary = a + value + carry
+        if not (self.decimal and self.p & D):
+            self.set(C, binary > 0xFF)
+            self.set(V, ~(a ^ value) & (a ^ binary) & 0x80)
+            self.a = self.nz(binary & 0xFF)
+            return
+        lo = (a & 0x0F) + (value & 0x0F) + carry
+        if lo >= 0x0A:
+            lo = ((lo + 0x06) & 0x0F) + 0x10
+        result = (a & 0xF0) + (value & 0xF0) + lo
+        signed = signed_byte(a & 0xF0) + signed_byte(value & 0xF0) + lo
+        self.set(N, signed & 0x80)
+        self.set(V, signed < -128 or signed > 127)
+        self.set(Z, binary & 0xFF == 0)
+        if result >= 0xA0:
+            result += 0x60
+        self.set(C, result >= 0x100)
+        self.a = result & 0xFF
+
+    def sbc(self, value):
+        a, carry = self.a, self.flag(C)
+        binary = a - value - (1 - carry)
+        self.set(C, binary >= 0)
+        self.set(V, (a ^ value) & (a ^ binary) & 0x80)
+        self.nz(binary & 0xFF)
+        if not (self.decimal and self.p & D):
+            self.a = binary & 0xFF
+            return
+        lo = (a & 0x0F) - (value & 0x0F) + carry - 1
+        if lo < 0:
+            lo = ((lo - 0x06) & 0x0F) - 0x10
+        result = (a & 0xF0) - (value & 0xF0) + lo
+        if result < 0:
+            result -= 0x60
+        self.a = result & 0xFF
+
+    def compare(self, register, value):
+        self.set(C, register >= value)
+        self.nz((register - value) & 0xFF)
+
+    def arr(self, value):
+        t = self.a & value
+        result = t >> 1 | self.flag(C) << 7
+        if not (self.decimal and self.p & D):
+            self.a = self.nz(result)
+            self.set(C, result & 0x40)
+            self.set(V, (result >> 6 ^ result >> 5) & 1)
+            return
+        self.nz(result)
+        self.set(V, (t ^ result) & 0x40)
+        if (t & 0x0F) + (t & 0x01) > 0x05:
+            result = (result & 0xF0) | ((result + 0x06) & 0x0F)
+        high = (t & 0xF0) + (t & 0x10) > 0x50
+        if high:
+            result = (result + 0x60) & 0xFF
+        self.set(C, high)
+        self.a = result
+
+    def read_op(self, name, value):
+        if name in ("LDA", "LDX", "LDY", "LAX"):
+            self.nz(value)
+            if name in ("LDA", "LAX"):
+                self.a = value
+            if name in ("LDX", "LAX"):
+                self.x = value
+            if name == "LDY":
+                self.y = value
+        elif name == "AND":
+            self.a = self.nz(self.a & value)
+        elif name == "ORA":
+            self.a = self.nz(self.a | value)
+        elif name == "EOR":
+            self.a = self.nz(self.a ^ value)
+        elif name == "ADC":
+            self.adc(value)
+        elif name == "SBC":
+            self.sbc(value)
+        elif name == "CMP":
+            self.compare(self.a, value)
+        elif name == "CPX":
+            self.compare(self.x, value)
+        elif name == "CPY":
+            self.compare(self.y, value)
+        elif name == "BIT":
+            self.set(Z, self.a & value == 0)
+            self.set(N, value & 0x80)
+            self.set(V, value & 0x40)
+        elif name == "LAS":
+            self.a = self.x = self.s = self.nz(value & self.s)
+        elif name == "ANC":
+            self.a = self.nz(self.a & value)
+            self.set(C, self.a & 0x80)
+        elif name == "ALR":
+            t = self.a & value
+            self.set(C, t & 1)
+            self.a = self.nz(t >> 1)
+        elif name == "ARR":
+            self.arr(value)
+        elif name == "XAA":
+            self.a = self.nz((self.a | MAGIC) & self.x & value)
+        elif name == "LXA":
+            self.a = self.x = self.nz((self.a | MAGIC) & value)
+        elif name == "SBX":
+            t = self.a & self.x
+            self.set(C, t >= value)
+            self.x = self.nz((t - value) & 0xFF)
+
+    def modify_op(self, name, value):
+        if name in ("ASL", "SLO"):
+            self.set(C, value & 0x80)
+            value = (value << 1) & 0xFF
+        elif name in ("LSR", "SRE"):
+            self.set(C, value & 1)
+            value >>= 1
+        elif name in ("ROL", "RLA"):
+            carry = self.flag(C)
+            self.set(C, value & 0x80)
+            value = (value << 1 | carry) & 0xFF
+        elif name in ("ROR", "RRA"):
+            carry = self.flag(C)
+            self.set(C, value & 1)
+            value = value >> 1 | carry << 7
+        elif name in ("INC", "ISC"):
+            value = (value + 1) & 0xFF
+        elif name in ("DEC", "DCP"):
+            value = (value - 1) & 0xFF
+
+        if name in ("ASL", "LSR", "ROL", "ROR", "INC", "DEC"):
+            self.nz(value)
+        elif name == "SLO":
+            self.a = self.nz(self.a | value)
+        elif name == "RLA":
+            self.a = self.nz(self.a & value)
+        elif name == "SRE":
+            self.a = self.nz(self.a ^ value)
+        elif name == "RRA":
+            self.adc(value)
+        elif name == "DCP":
+            self.compare(self.a, value)
+        elif name == "ISC":
+            self.sbc(value)
+        return value
+
+    def implied(self, name):
+        self.read(self.pc)
+        if name in ("CLC", "SEC"):
+            self.set(C, name == "SEC")
+        elif name in ("CLI", "SEI"):
+            self.set(I, name == "SEI")
+        elif name in ("CLD", "SED"):
+            self.set(D, name == "SED")
+        elif name == "CLV":
+            self.set(V, False)
+        elif name == "INX":
+            self.x = self.nz((self.x + 1) & 0xFF)
+        elif name == "DEX":
+            self.x = self.nz((self.x - 1) & 0xFF)
+        elif name == "INY":
+            self.y = self.nz((self.y + 1) & 0xFF)
+        elif name == "DEY":
+            self.y = self.nz((self.y - 1) & 0xFF)
+        elif name == "TAX":
+            self.x = self.nz(self.a)
+        elif name == "TAY":
+            self.y = self.nz(self.a)
+        elif name == "TXA":
+            self.a = self.nz(self.x)
+        elif name == "TYA":
+            self.a = self.nz(self.y)
+        elif name == "TSX":
+            self.x = self.nz(self.s)
+        elif name == "TXS":
+            self.s = self.x
+
+    def stack(self, code):
+        if code == 0x00:
+            self.fetch()
+            self.push(self.pc >> 8)
+            self.push(self.pc & 0xFF)
+            self.push(self.p | B | U)
+            self.set(I, True)
+            lo = self.read(0xFFFE)
+            self.pc = self.read(0xFFFF) << 8 | lo
+        elif code == 0x20:
+            lo = self.fetch()
+            self.read(0x100 | self.s)
+            self.push(self.pc >> 8)
+            self.push(self.pc & 0xFF)
+            self.pc = self.read(self.pc) << 8 | lo
+        elif code in (0x08, 0x48):
+            self.read(self.pc)
+            self.push(self.p | B | U if code == 0x08 else self.a)
+        else:
+            self.read(self.pc)
+            self.read(0x100 | self.s)
+            if code == 0x68:
+                self.a = self.nz(self.pull())
+            elif code == 0x28:
+                self.p = (self.pull() & ~B & 0xFF) | U
+            elif code == 0x40:
+                self.p = (self.pull() & ~B & 0xFF) | U
+                lo = self.pull()
+                self.pc = self.pull() << 8 | lo
+            elif code == 0x60:
+                lo = self.pull()
+                self.pc = self.pull() << 8 | lo
+                self.read(self.pc)
+                self.pc = (self.pc + 1) & 0xFFFF
+
+    def branch(self, name):
+        offset = self.fetch()
+        flag = {"BPL": N, "BMI": N, "BVC": V, "BVS": V,
+                "BCC": C, "BCS": C, "BNE": Z, "BEQ": Z}[name]
+        taken = bool(self.p & flag) == (name in ("BMI", "BVS", "BCS", "BEQ"))
+        if not taken:
+            return
+        self.read(self.pc)
+        target = (self.pc + signed_byte(offset)) & 0xFFFF
+        if target & 0xFF00 != self.pc & 0xFF00:
+            self.read((self.pc & 0xFF00) | (target & 0xFF))
+        self.pc = target
+
+    def execute(self):
+        code = self.fetch()
+        name, mode = NAMES[code], MODES[code]
+        if mode == "stack":
+            self.stack(code)
+        elif mode == "rel":
+            self.branch(name)
+        elif mode == "imp":
+            self.implied(name)
+        elif mode == "acc":
+            self.read(self.pc)
+            self.a = self.modify_op(name, self.a)
+        elif name == "JMP":
+            lo = self.fetch()
+            hi = self.read(self.pc)
+            target = hi << 8 | lo
+            if mode == "ind":
+                lo = self.read(target)
+                hi = self.read((target & 0xFF00) | ((target + 1) & 0xFF))
+                target = hi << 8 | lo
+            self.pc = target
+        elif mode == "imm":
+            self.read_op(name, self.fetch())
+        elif name in READS:
+            self.read_op(name, self.read(self.address(mode, "read")))
+        elif name in WRITES:
+            addr = self.address(mode, "write")
+            value = {"STA": self.a, "STX": self.x, "STY": self.y,
+                     "SAX": self.a & self.x}[name]
+            self.write(addr, value)
+        elif name in MODIFIES:
+            addr = self.address(mode, "modify")
+            value = self.read(addr)
+            self.write(addr, value)
+            self.write(addr, self.modify_op(name, value))
+        elif name in STORES_AND_HIGH:
+            base, index = self.base_and_index(mode)
+            addr = self.indexed(base, index, "write")
+            if name == "TAS":
+                self.s = self.a & self.x
+            register = {"SHA": self.a & self.x, "SHX": self.x,
+                        "SHY": self.y, "TAS": self.s}[name]
+            value = register & ((base >> 8) + 1) & 0xFF
+            if addr & 0xFF00 != base & 0xFF00:
+                addr = value << 8 | (addr & 0xFF)
+            self.write(addr, value)
+        else:
+            raise ValueError(name)
+
+
+def signed_byte(value):
+    return value - 0x100 if value & 0x80 else value
+
+
+def registers(cpu):
+    return {"pc": cpu.pc, "s": cpu.s, "a": cpu.a, "x": cpu.x, "y": cpu.y,
+            "p": cpu.p}
+
+
+def generate(code, rng, decimal):
+    cpu = Cpu(rng, decimal)
+    cpu.pc = rng.randrange(0x10000)
+    cpu.s, cpu.a, cpu.x, cpu.y = (rng.randrange(256) for _ in range(4))
+    # D is set in about half of the tests, like every other flag
+    cpu.p = rng.randrange(256) & ~B | U
+    cpu.memory[cpu.pc] = cpu.initial[cpu.pc] = code
+    initial = registers(cpu)
+    length = STACK_LENGTHS.get(code, LENGTHS.get(MODES[code], 1))
+    operand = [cpu.peek((cpu.pc + i) & 0xFFFF) for i in range(length)]
+
+    cpu.execute()
+    test = {
+        "name": " ".join("%02x" % byte for byte in operand),
+        "initial": dict(initial, ram=[[a, v] for a, v in cpu.initial.items()]),
+        "final": dict(registers(cpu), ram=[[a, v] for a, v in cpu.memory.items()]),
+        "cycles": cpu.cycles,
+    }
+    return test
+
+
+def main():
+    count = int(sys.argv[1]) if len(sys.argv) > 1 else 10
+    root = os.path.dirname(os.path.abspath(__file__))
+    for directory, decimal in (("6502", True), ("nes6502", False)):
+        os.makedirs(os.path.join(root, directory), exist_ok=True)
+        for code in sorted(MODES):
+            tests = []
+            hand = os.path.join(root, "hand", "%02x.json" % code)
+            if decimal and os.path.exists(hand):
+                with open(hand) as f:
+                    tests.extend(json.load(f))
+            rng = random.Random("%s %02x" % (directory, code))
+            tests.extend(generate(code, rng, decimal) for _ in range(count))
+            path = os.path.join(root, directory, "%02x.json" % code)
+            with open(path, "w") as f:
+                f.write("[\n")
+                f.write(",\n".join(json.dumps(test) for test in tests))
+                f.write("\n]\n")
+
+
+if __name__ == "__main__":
+    main()
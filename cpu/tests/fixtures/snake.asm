@@ -0,0 +1,234 @@
+; Snake, the program the frontends run.
+;
+; W A S D to steer, the screen is the 32x32 pixels at $0200-$05ff.
+
+appleL = $00            ; screen location of the apple, 2 bytes
+snakeDirection = $02    ; 1 up, 2 right, 4 down, 8 left
+snakeLength = $03       ; length of the snake, in bytes
+snakeHeadL = $10        ; screen location of the head, 2 bytes
+snakeBodyStart = $12    ; the segments follow the head
+
+sysRandom = $fe
+sysLastKey = $ff
+
+ASCII_w = $77
+ASCII_a = $61
+ASCII_s = $73
+ASCII_d = $64
+
+movingUp = 1
+movingRight = 2
+movingDown = 4
+movingLeft = 8
+
+        .org $0600
+        jsr init
+        jsr loop
+
+init:
+        jsr initSnake
+        jsr generateApplePosition
+        rts
+
+initSnake:
+        lda #movingRight
+        sta snakeDirection
+        lda #4                  ; two segments
+        sta snakeLength
+        lda #$11
+        sta snakeHeadL
+        lda #$10
+        sta snakeBodyStart
+        lda #$0f
+        sta snakeBodyStart + 2
+        lda #>$0400
+        sta snakeHeadL + 1
+        sta snakeBodyStart + 1
+        sta snakeBodyStart + 3
+        rts
+
+generateApplePosition:
+        lda sysRandom           ; random low byte
+        sta appleL
+        lda sysRandom           ; random high byte, $02 to $05
+        and #$03
+        clc
+        adc #2
+        sta appleL + 1
+        rts
+
+loop:
+        jsr readKeys
+        jsr checkCollision
+        jsr updateSnake
+        jsr drawApple
+        jsr drawSnake
+        jsr spinWheels
+        jmp loop
+
+readKeys:
+        lda sysLastKey
+        cmp #ASCII_w
+        beq @upKey
+        cmp #ASCII_d
+        beq @rightKey
+        cmp #ASCII_s
+        beq @downKey
+        cmp #ASCII_a
+        beq @leftKey
+        rts
+@upKey:
+        lda #movingDown
+        bit snakeDirection
+        bne illegalMove
+        lda #movingUp
+        sta snakeDirection
+        rts
+@rightKey:
+        lda #movingLeft
+        bit snakeDirection
+        bne illegalMove
+        lda #movingRight
+        sta snakeDirection
+        rts
+@downKey:
+        lda #movingUp
+        bit snakeDirection
+        bne illegalMove
+        lda #movingDown
+        sta snakeDirection
+        rts
+@leftKey:
+        lda #movingRight
+        bit snakeDirection
+        bne illegalMove
+        lda #movingLeft
+        sta snakeDirection
+        rts
+illegalMove:
+        rts
+
+checkCollision:
+        jsr checkAppleCollision
+        jsr checkSnakeCollision
+        rts
+
+checkAppleCollision:
+        lda appleL
+        cmp snakeHeadL
+        bne @done
+        lda appleL + 1
+        cmp snakeHeadL + 1
+        bne @done
+        inc snakeLength         ; one more segment
+        inc snakeLength
+        jsr generateApplePosition
+@done:
+        rts
+
+checkSnakeCollision:
+        ldx #2                  ; start with the second segment
+@loop:
+        lda snakeHeadL,x
+        cmp snakeHeadL
+        bne @continue
+        lda snakeHeadL + 1,x
+        cmp snakeHeadL + 1
+        beq @collided
+@continue:
+        inx
+        inx
+        cpx snakeLength
+        beq @done
+        jmp @loop
+@collided:
+        jmp gameOver
+@done:
+        rts
+
+updateSnake:
+        ldx snakeLength
+        dex
+        txa
+@shift:
+        lda snakeHeadL,x
+        sta snakeBodyStart,x
+        dex
+        bpl @shift
+
+        lda snakeDirection
+        lsr a
+        bcs @up
+        lsr a
+        bcs @right
+        lsr a
+        bcs @down
+        lsr a
+        bcs @left
+@up:
+        lda snakeHeadL
+        sec
+        sbc #$20
+        sta snakeHeadL
+        bcc @upRow
+        rts
+@upRow:
+        dec snakeHeadL + 1
+        lda #$01
+        cmp snakeHeadL + 1
+        beq @collision
+        rts
+@right:
+        inc snakeHeadL
+        lda #$1f
+        bit snakeHeadL
+        beq @collision
+        rts
+@down:
+        lda snakeHeadL
+        clc
+        adc #$20
+        sta snakeHeadL
+        bcs @downRow
+        rts
+@downRow:
+        inc snakeHeadL + 1
+        lda #$06
+        cmp snakeHeadL + 1
+        beq @collision
+        rts
+@left:
+        dec snakeHeadL
+        lda snakeHeadL
+        and #$1f
+        cmp #$1f
+        beq @collision
+        rts
+@collision:
+        jmp gameOver
+
+drawApple:
+        ldy #0
+        lda sysRandom
+        sta (appleL),y
+        rts
+
+drawSnake:
+        ldx snakeLength
+        lda #0
+        sta (snakeHeadL,x)      ; erase the end of the tail
+        ldx #0
+        lda #1
+        sta (snakeHeadL,x)      ; paint the head
+        rts
+
+spinWheels:
+        ldx sysLastKey
+@spin:
+        nop
+        nop
+        dex
+        bne @spin
+        rts
+
+gameOver:
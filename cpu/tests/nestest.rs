@@ -0,0 +1,82 @@
+//! Runs nestest.nes in automation mode and compares its trace line by line
+//! with the reference log.
+//!
+//! The ROM and the log are not part of the repository, see
+//! `tests/fixtures/README.md`, so the test is ignored by default.
+//! `tests/fixtures/fetch.sh` downloads them, then run it with
+//! `cargo test --test nestest -- --ignored`, a missing file fails the test.
+
+use cpu::bus::{MappedBus, Rom};
+use cpu::trace::trace;
+use cpu::{CpuVariant, Mem, CPU};
+use std::fs;
+use std::path::PathBuf;
+
+/// Automation mode runs every test without a PPU, starting here
+const AUTOMATION_START: u16 = 0xc000;
+const INES_HEADER_LEN: usize = 16;
+/// nestest stores the code of the first failing official and unofficial test there
+const RESULT_OFFICIAL: u16 = 0x0002;
+const RESULT_UNOFFICIAL: u16 = 0x0003;
+
+fn fixture(name: &str) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    fs::read(&path)
+        .unwrap_or_else(|e| panic!("{}: {}, see tests/fixtures/README.md", path.display(), e))
+}
+
+/// The reference log comes from an emulator with a PPU, its PPU column is dropped
+fn strip_ppu(line: &str) -> String {
+    match (line.find(" PPU:"), line.find(" CYC:")) {
+        (Some(ppu), Some(cyc)) => format!("{}{}", &line[..ppu], &line[cyc..]),
+        _ => line.to_string(),
+    }
+}
+
+#[test]
+#[ignore = "needs tests/fixtures/fetch.sh"]
+fn nestest_golden_trace() {
+    let rom = fixture("nestest.nes");
+    let log = String::from_utf8(fixture("nestest.log")).unwrap();
+
+    // NROM: the 16KiB PRG bank is mirrored over $8000-$FFFF
+    let prg_len = rom[4] as usize * 0x4000;
+    let prg = rom[INES_HEADER_LEN..INES_HEADER_LEN + prg_len].to_vec();
+    let mut bus = MappedBus::new();
+    // APU and I/O registers read back as open bus
+    bus.map(0x4000..=0x401f, Rom::new(vec![0xff]));
+    bus.map(0x8000..=0xffff, Rom::new(prg));
+
+    let mut cpu = CPU::with_bus_and_variant(bus, CpuVariant::Ricoh2A03);
+    cpu.reset();
+    cpu.program_counter = AUTOMATION_START;
+
+    for (number, expected) in log.lines().enumerate() {
+        let expected = strip_ppu(expected.trim_end());
+        let actual = trace(&cpu);
+        assert_eq!(
+            actual,
+            expected,
+            "trace diverges at line {}\nexpected: {}\n  actual: {}",
+            number + 1,
+            expected,
+            actual
+        );
+        if let Err(e) = cpu.step() {
+            panic!("line {}: {}", number + 1, e);
+        }
+    }
+
+    assert_eq!(
+        cpu.mem_peek(RESULT_OFFICIAL),
+        0,
+        "official opcode test failed"
+    );
+    assert_eq!(
+        cpu.mem_peek(RESULT_UNOFFICIAL),
+        0,
+        "unofficial opcode test failed"
+    );
+}
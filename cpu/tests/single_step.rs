@@ -0,0 +1,212 @@
+//! Runs the per-opcode JSON tests of https://github.com/SingleStepTests/65x02
+//! (formerly ProcessorTests) against the cycle stepped core.
+//!
+//! Every `<opcode>.json` file under `tests/fixtures/single_step/<cpu>` holds a
+//! list of tests: the state before and after a single instruction and the bus
+//! access made on every cycle. The 65C02 is not modelled cycle by cycle, so
+//! only its cycle count is compared. A CPU directory that is missing fails the test.
+//!
+//! The upstream files are not vendored, `tests/fixtures/fetch.sh` downloads the
+//! first tests of every opcode into `single_step/upstream` and
+//! `cargo test --release --test single_step -- --ignored` runs them. The
+//! vendored files supplement them: tests made by `generate.py` for the NMOS
+//! opcodes and hand checked ones.
+
+use cpu::{CpuFlags, CpuVariant, Mem, Ram, CPU};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize)]
+struct Test {
+    name: String,
+    initial: State,
+    #[serde(rename = "final")]
+    expected: State,
+    cycles: Vec<(u16, u8, String)>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct State {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+/// Ram that logs the bus activity the way the fixtures describe it
+struct Recorder {
+    ram: Ram,
+    cycles: Vec<(u16, u8, String)>,
+}
+
+impl Mem for Recorder {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let data = self.ram.mem_read(addr);
+        self.cycles.push((addr, data, "read".to_string()));
+        data
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.cycles.push((addr, data, "write".to_string()));
+        self.ram.mem_write(addr, data);
+    }
+
+    fn mem_peek(&self, addr: u16) -> u8 {
+        self.ram.mem_peek(addr)
+    }
+}
+
+/// Fixture directories and the variant their tests were recorded on
+const CPUS: &[(&str, CpuVariant)] = &[
+    ("6502", CpuVariant::Nmos6502),
+    ("nes6502", CpuVariant::Ricoh2A03),
+    ("wdc65c02", CpuVariant::Wdc65C02),
+];
+
+fn run_test(cpu: &mut CPU<Recorder>, test: &Test) -> Result<(), String> {
+    let initial = &test.initial;
+    cpu.program_counter = initial.pc;
+    cpu.stack_pointer = initial.s;
+    cpu.register_a = initial.a;
+    cpu.register_x = initial.x;
+    cpu.register_y = initial.y;
+    cpu.status = CpuFlags::from_bits_truncate(initial.p);
+    for &(addr, data) in &initial.ram {
+        cpu.bus.ram.mem_write(addr, data);
+    }
+    cpu.bus.cycles.clear();
+
+    let mut clocks = 0;
+    let result = loop {
+        clocks += 1;
+        match cpu.clock() {
+            Ok(Some(_)) => break Ok(()),
+            Ok(None) => {}
+            Err(e) => break Err(format!("{}: {}", test.name, e)),
+        }
+    };
+
+    let actual = State {
+        pc: cpu.program_counter,
+        s: cpu.stack_pointer,
+        a: cpu.register_a,
+        x: cpu.register_x,
+        y: cpu.register_y,
+        p: cpu.status.bits(),
+        ram: test
+            .expected
+            .ram
+            .iter()
+            .map(|&(addr, _)| (addr, cpu.bus.ram.mem_peek(addr)))
+            .collect(),
+    };
+
+    // the memory is shared by every test of the file
+    let touched = initial.ram.iter().map(|&(addr, _)| addr);
+    let touched = touched.chain(cpu.bus.cycles.iter().map(|&(addr, _, _)| addr));
+    for addr in touched.collect::<Vec<_>>() {
+        cpu.bus.ram.mem_write(addr, 0);
+    }
+
+    result?;
+    if actual != test.expected {
+        return Err(format!(
+            "{}: expected {:?}, got {:?}",
+            test.name, test.expected, actual
+        ));
+    }
+    // the 65C02 is not modelled cycle by cycle, only its cycle count is checked
+    if cpu.variant().is_cmos() {
+        if clocks != test.cycles.len() {
+            return Err(format!(
+                "{}: expected {} cycles, got {}",
+                test.name,
+                test.cycles.len(),
+                clocks
+            ));
+        }
+    } else if cpu.bus.cycles != test.cycles {
+        return Err(format!(
+            "{}: expected bus activity {:?}, got {:?}",
+            test.name, test.cycles, cpu.bus.cycles
+        ));
+    }
+    Ok(())
+}
+
+/// Runs every test of `file`, returns the number that passed and the failures
+fn run_file(file: &Path, variant: CpuVariant) -> (usize, Vec<String>) {
+    let json = fs::read_to_string(file).unwrap();
+    let tests: Vec<Test> =
+        serde_json::from_str(&json).unwrap_or_else(|e| panic!("{}: {}", file.display(), e));
+
+    let bus = Recorder {
+        ram: Ram::new(),
+        cycles: Vec::new(),
+    };
+    let mut cpu = CPU::with_bus_and_variant(bus, variant);
+
+    let mut passed = 0;
+    let mut failures = Vec::new();
+    for test in &tests {
+        match run_test(&mut cpu, test) {
+            Ok(()) => passed += 1,
+            Err(failure) => failures.push(failure),
+        }
+    }
+    (passed, failures)
+}
+
+/// Runs the files of every CPU directory under `root`, a missing one fails
+fn run_cpus(root: &Path) {
+    let mut failures = Vec::new();
+
+    for &(dir, variant) in CPUS {
+        let mut files: Vec<PathBuf> = fs::read_dir(root.join(dir))
+            .unwrap_or_else(|e| {
+                panic!(
+                    "{}: {}, see tests/fixtures/README.md",
+                    root.join(dir).display(),
+                    e
+                )
+            })
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.retain(|file| file.extension().is_some_and(|ext| ext == "json"));
+        files.sort();
+
+        println!("{}", dir);
+        println!("opcode  passed  failed");
+        for file in &files {
+            let (passed, failed) = run_file(file, variant);
+            let opcode = file.file_stem().unwrap().to_string_lossy();
+            println!("{:>6}  {:>6}  {:>6}", opcode, passed, failed.len());
+            // the first failure of an opcode is usually enough to find the bug
+            if let Some(failure) = failed.into_iter().next() {
+                failures.push(format!("{} {}", dir, failure));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/single_step")
+}
+
+#[test]
+fn single_step_tests() {
+    run_cpus(&fixtures());
+}
+
+/// The upstream files, fetched by `tests/fixtures/fetch.sh`
+#[test]
+#[ignore = "needs tests/fixtures/fetch.sh"]
+fn upstream_single_step_tests() {
+    run_cpus(&fixtures().join("upstream"));
+}
@@ -0,0 +1,614 @@
+//! Debug Adapter Protocol server for `cpu::CPU`.
+//!
+//! Editors launch a program with the `launch` request:
+//!
+//! ```json
+//! { "program": "snake.asm", "stopOnEntry": true }
+//! ```
+//!
+//! `.asm` and `.s` programs are assembled with `cpu::asm`, which gives source
+//! line breakpoints and source positions in stack traces. Anything else is
+//! loaded as a raw binary at `origin`, $0600 by default. Execution starts at
+//! the first byte of the program and ends on a BRK, which is reported as an
+//! exception stop so the final state can be inspected.
+//!
+//! The CPU is the only thread. Its call stack is tracked from the JSR and RTS
+//! instructions executed under the debugger.
+
+pub mod protocol;
+
+use cpu::debug::{Breakpoint, BreakpointHit, BreakpointId, Condition};
+use cpu::{CpuError, CpuVariant, Mem, CPU, DEFAULT_ORIGIN};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+
+/// Instructions run between two looks at the incoming requests, so a
+/// running program can be paused
+const SLICE: u32 = 10_000;
+
+const THREAD_ID: u64 = 1;
+
+/// `variablesReference` of the registers scope
+const REGISTERS: u64 = 1;
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const BRK: u8 = 0x00;
+
+/// Serves a single debug session, until the client disconnects or closes `input`.
+pub fn serve<R, W>(input: R, output: W) -> io::Result<()>
+where
+    R: BufRead + Send + 'static,
+    W: Write,
+{
+    // requests are read on their own thread so they can arrive while the CPU runs
+    let (sender, requests) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = input;
+        while let Ok(Some(message)) = protocol::read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut session = Session::new(output);
+    loop {
+        let request = if session.running.is_some() {
+            match requests.try_recv() {
+                Ok(request) => Some(request),
+                Err(mpsc::TryRecvError::Empty) => None,
+                Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
+            }
+        } else {
+            match requests.recv() {
+                Ok(request) => Some(request),
+                Err(_) => return Ok(()),
+            }
+        };
+
+        match request {
+            Some(request) => {
+                if !session.request(&request)? {
+                    return Ok(());
+                }
+            }
+            None => session.run_slice()?,
+        }
+    }
+}
+
+/// Where a resumed CPU stops on its own, breakpoints aside
+#[derive(Clone, Copy)]
+enum Goal {
+    Continue,
+    /// Back at the instruction after a JSR, in the same frame
+    Return {
+        pc: u16,
+        stack_pointer: u8,
+    },
+    /// Returned from the subroutine that was running: the call stack is
+    /// shallower than `depth`, or the stack pointer moved past the return
+    /// address of its JSR
+    StepOut {
+        depth: usize,
+        stack_pointer: Option<u8>,
+    },
+}
+
+impl Goal {
+    fn reached(self, cpu: &CPU, calls: &[Call]) -> bool {
+        match self {
+            Goal::Continue => false,
+            Goal::Return { pc, stack_pointer } => {
+                cpu.program_counter == pc && cpu.stack_pointer == stack_pointer
+            }
+            Goal::StepOut {
+                depth,
+                stack_pointer,
+            } => {
+                calls.len() < depth
+                    || stack_pointer.is_some_and(|stack_pointer| {
+                        cpu.stack_pointer as u16 > stack_pointer as u16 + 1
+                    })
+            }
+        }
+    }
+}
+
+/// A JSR executed under the debugger that has not returned yet
+struct Call {
+    site: u16,
+    target: u16,
+    /// Stack pointer right after the JSR pushed its return address
+    stack_pointer: u8,
+}
+
+/// The launched program
+struct Program {
+    path: String,
+    entry: u16,
+    /// Address of each source line with code, empty for binaries
+    lines: BTreeMap<usize, u16>,
+    /// Label of each address, empty for binaries
+    labels: HashMap<u16, String>,
+}
+
+struct Session<W> {
+    output: W,
+    seq: u64,
+    cpu: CPU,
+    program: Option<Program>,
+    stop_on_entry: bool,
+    source_breakpoints: Vec<BreakpointId>,
+    instruction_breakpoints: Vec<BreakpointId>,
+    calls: Vec<Call>,
+    running: Option<Goal>,
+}
+
+impl<W: Write> Session<W> {
+    fn new(output: W) -> Self {
+        Session {
+            output,
+            seq: 0,
+            cpu: CPU::new(),
+            program: None,
+            stop_on_entry: false,
+            source_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new(),
+            calls: Vec::new(),
+            running: None,
+        }
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        protocol::write_message(&mut self.output, &message)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn stopped(
+        &mut self,
+        reason: &str,
+        hit: Option<BreakpointHit>,
+        text: Option<String>,
+    ) -> io::Result<()> {
+        self.running = None;
+        let mut body =
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+        if let Some(hit) = hit {
+            body["hitBreakpointIds"] = json!([hit.id]);
+        }
+        if let Some(text) = text {
+            body["text"] = json!(text);
+        }
+        self.event("stopped", body)
+    }
+
+    /// Handles a request, returns whether the session goes on.
+    fn request(&mut self, request: &Value) -> io::Result<bool> {
+        let command = request["command"].as_str().unwrap_or_default().to_string();
+        let arguments = &request["arguments"];
+
+        let stepping = matches!(
+            command.as_str(),
+            "configurationDone" | "continue" | "next" | "stepIn" | "stepOut" | "pause"
+        );
+        let result = match command.as_str() {
+            _ if stepping && self.program.is_none() => Err("no program launched".to_string()),
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsReadMemoryRequest": true,
+            })),
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(arguments),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "continue" => Ok(json!({ "allThreadsContinued": true })),
+            _ if stepping => Ok(json!({})),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "6502" }] })),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(json!({
+                "scopes": [{ "name": "Registers", "variablesReference": REGISTERS, "expensive": false }]
+            })),
+            "variables" => Ok(self.variables(arguments)),
+            "readMemory" => self.read_memory(arguments),
+            "disconnect" => Ok(json!({})),
+            _ => Err(format!("unsupported request {}", command)),
+        };
+
+        let success = result.is_ok();
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": success,
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)?;
+        if !success {
+            return Ok(true);
+        }
+
+        // what follows the response
+        match command.as_str() {
+            "launch" => self.event("initialized", json!({}))?,
+            "configurationDone" if self.stop_on_entry => self.stopped("entry", None, None)?,
+            "configurationDone" | "continue" => self.resume(Goal::Continue)?,
+            "next" => self.next()?,
+            "stepIn" => {
+                let result = self.execute_one();
+                self.stop_after_step(result)?;
+            }
+            "stepOut" => {
+                let goal = Goal::StepOut {
+                    depth: self.calls.len(),
+                    stack_pointer: self.calls.last().map(|call| call.stack_pointer),
+                };
+                self.resume(goal)?
+            }
+            "pause" if self.running.is_some() => self.stopped("pause", None, None)?,
+            "disconnect" => return Ok(false),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let path = arguments["program"]
+            .as_str()
+            .ok_or("missing program")?
+            .to_string();
+        let variant = match arguments["variant"].as_str() {
+            None => CpuVariant::default(),
+            Some(variant) => variant.parse().map_err(|e| format!("{}", e))?,
+        };
+
+        let is_source = matches!(
+            Path::new(&path)
+                .extension()
+                .and_then(|extension| extension.to_str()),
+            Some("asm") | Some("s")
+        );
+        let (origin, bytes, lines, labels) = if is_source {
+            let source = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+            let program = cpu::asm::assemble(&source).map_err(|e| format!("{}: {}", path, e))?;
+            // constants and local labels do not name functions, an address
+            // with several labels gets the first one in alphabetical order
+            let mut labels: HashMap<u16, String> = HashMap::new();
+            for (name, addr) in &program.symbols {
+                if !program.labels.contains(name) || name.contains('@') {
+                    continue;
+                }
+                match labels.get(addr) {
+                    Some(label) if label <= name => {}
+                    _ => {
+                        labels.insert(*addr, name.clone());
+                    }
+                }
+            }
+            (program.origin, program.bytes, program.lines, labels)
+        } else {
+            let origin = match arguments["origin"].as_u64() {
+                Some(origin) if origin <= 0xffff => origin as u16,
+                Some(origin) => return Err(format!("origin {} is past $ffff", origin)),
+                None => DEFAULT_ORIGIN,
+            };
+            let bytes = std::fs::read(&path).map_err(|e| format!("{}: {}", path, e))?;
+            (origin, bytes, BTreeMap::new(), HashMap::new())
+        };
+        if origin as usize + bytes.len() > 0x10000 {
+            return Err(format!("{} does not fit at ${:04x}", path, origin));
+        }
+
+        let mut cpu = CPU::with_variant(variant);
+        for (i, byte) in bytes.iter().enumerate() {
+            cpu.mem_write(origin + i as u16, *byte);
+        }
+        cpu.program_counter = origin;
+        cpu.stop_on_brk = true;
+        self.cpu = cpu;
+        self.calls.clear();
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        self.program = Some(Program {
+            path,
+            entry: origin,
+            lines,
+            labels,
+        });
+        Ok(json!({}))
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        for id in self.source_breakpoints.drain(..) {
+            self.cpu.remove_breakpoint(id);
+        }
+        let path = arguments["source"]["path"].as_str().unwrap_or_default();
+        let lines = match &self.program {
+            Some(program) if same_file(path, &program.path) => program.lines.clone(),
+            _ => BTreeMap::new(),
+        };
+
+        let mut breakpoints = Vec::new();
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let line = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+            // a line without code breaks on the next one that has some
+            let result = match lines.range(line..).next() {
+                Some((line, addr)) => self
+                    .add_breakpoint(*addr, &breakpoint["condition"])
+                    .map(|id| (id, *line)),
+                None => Err("no code on or after this line".to_string()),
+            };
+            breakpoints.push(match result {
+                Ok((id, line)) => {
+                    self.source_breakpoints.push(id);
+                    json!({ "id": id, "verified": true, "line": line })
+                }
+                Err(message) => json!({ "verified": false, "line": line, "message": message }),
+            });
+        }
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        for id in self.instruction_breakpoints.drain(..) {
+            self.cpu.remove_breakpoint(id);
+        }
+
+        let mut breakpoints = Vec::new();
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let reference = breakpoint["instructionReference"]
+                .as_str()
+                .unwrap_or_default();
+            let offset = breakpoint["offset"].as_i64().unwrap_or(0);
+            let result = parse_reference(reference)
+                .map(|addr| (addr as i64 + offset) as u16)
+                .and_then(|addr| self.add_breakpoint(addr, &breakpoint["condition"]));
+            breakpoints.push(match result {
+                Ok(id) => {
+                    self.instruction_breakpoints.push(id);
+                    json!({ "id": id, "verified": true, "instructionReference": reference })
+                }
+                Err(message) => json!({ "verified": false, "message": message }),
+            });
+        }
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    /// Breaks at `addr`, only when `condition` holds if there is one.
+    fn add_breakpoint(&mut self, addr: u16, condition: &Value) -> Result<BreakpointId, String> {
+        let breakpoint = match condition.as_str() {
+            Some(condition) if !condition.trim().is_empty() => {
+                let condition = format!("PC == ${:04x} && ({})", addr, condition);
+                let condition: Condition = condition.parse().map_err(|e| format!("{}", e))?;
+                Breakpoint::Condition(condition)
+            }
+            _ => Breakpoint::Execute(addr),
+        };
+        Ok(self.cpu.add_breakpoint(breakpoint))
+    }
+
+    fn stack_trace(&self) -> Value {
+        let program = match &self.program {
+            Some(program) => program,
+            None => return json!({ "stackFrames": [], "totalFrames": 0 }),
+        };
+        let addresses: HashMap<u16, usize> = program
+            .lines
+            .iter()
+            .map(|(line, addr)| (*addr, *line))
+            .collect();
+        let name = |addr: u16| match program.labels.get(&addr) {
+            Some(label) => label.clone(),
+            None => format!("${:04X}", addr),
+        };
+
+        // innermost first: the current position, then the JSR of every open call
+        let mut positions = vec![self.cpu.program_counter];
+        positions.extend(self.calls.iter().rev().map(|call| call.site));
+        let mut functions: Vec<u16> = self.calls.iter().rev().map(|call| call.target).collect();
+        functions.push(program.entry);
+
+        let frames: Vec<Value> = positions
+            .iter()
+            .zip(functions)
+            .enumerate()
+            .map(|(id, (pc, function))| {
+                let mut frame = json!({
+                    "id": id,
+                    "name": name(function),
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": format!("0x{:04X}", pc),
+                });
+                if let Some(line) = addresses.get(pc) {
+                    frame["source"] = json!({ "path": program.path });
+                    frame["line"] = json!(line);
+                    frame["column"] = json!(1);
+                }
+                frame
+            })
+            .collect();
+        json!({ "totalFrames": frames.len(), "stackFrames": frames })
+    }
+
+    fn variables(&self, arguments: &Value) -> Value {
+        if arguments["variablesReference"].as_u64() != Some(REGISTERS) {
+            return json!({ "variables": [] });
+        }
+        let cpu = &self.cpu;
+        let register = |name: &str, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+        let mut pc = register("PC", format!("${:04X}", cpu.program_counter));
+        pc["memoryReference"] = json!(format!("0x{:04X}", cpu.program_counter));
+        json!({
+            "variables": [
+                register("A", format!("${:02X}", cpu.register_a)),
+                register("X", format!("${:02X}", cpu.register_x)),
+                register("Y", format!("${:02X}", cpu.register_y)),
+                register("SP", format!("${:02X}", cpu.stack_pointer)),
+                pc,
+                register("P", format!("${:02X} {}", cpu.status.bits(), cpu.status)),
+                register("cycles", cpu.cycles.to_string()),
+            ]
+        })
+    }
+
+    fn read_memory(&self, arguments: &Value) -> Result<Value, String> {
+        let reference = arguments["memoryReference"]
+            .as_str()
+            .ok_or("missing memoryReference")?;
+        let start = parse_reference(reference)? as i64 + arguments["offset"].as_i64().unwrap_or(0);
+        let count = arguments["count"].as_i64().unwrap_or(0);
+        // what lies outside of the address space is unreadable
+        let end = (start + count).clamp(0, 0x10000);
+        let start = start.clamp(0, 0x10000);
+        let bytes: Vec<u8> = (start..end)
+            .map(|addr| self.cpu.mem_peek(addr as u16))
+            .collect();
+        Ok(json!({
+            "address": format!("0x{:04X}", start),
+            "data": base64(&bytes),
+            "unreadableBytes": count - bytes.len() as i64,
+        }))
+    }
+
+    /// Steps over a JSR by running until it returns, anything else is a single step.
+    fn next(&mut self) -> io::Result<()> {
+        let pc = self.cpu.program_counter;
+        if self.cpu.mem_peek(pc) == JSR {
+            let stack_pointer = self.cpu.stack_pointer;
+            self.resume(Goal::Return {
+                pc: pc.wrapping_add(3),
+                stack_pointer,
+            })
+        } else {
+            let result = self.execute_one();
+            self.stop_after_step(result)
+        }
+    }
+
+    /// Executes the instruction the CPU is stopped on, breakpoints on it do
+    /// not fire again, then keeps running towards `goal`.
+    fn resume(&mut self, goal: Goal) -> io::Result<()> {
+        if self.cpu.mem_peek(self.cpu.program_counter) == BRK {
+            return self.stopped("exception", None, Some("BRK".to_string()));
+        }
+        match self.execute_one() {
+            Err(error) => self.stopped("exception", None, Some(error.to_string())),
+            Ok(()) if goal.reached(&self.cpu, &self.calls) => self.stopped("step", None, None),
+            Ok(()) => {
+                self.running = Some(goal);
+                Ok(())
+            }
+        }
+    }
+
+    fn stop_after_step(&mut self, result: Result<(), CpuError>) -> io::Result<()> {
+        match result {
+            Ok(()) => self.stopped("step", None, None),
+            Err(error) => self.stopped("exception", None, Some(error.to_string())),
+        }
+    }
+
+    fn execute_one(&mut self) -> Result<(), CpuError> {
+        let pc = self.cpu.program_counter;
+        let code = self.cpu.mem_peek(pc);
+        self.cpu.step()?;
+        track_call(&mut self.calls, pc, code, &self.cpu);
+        Ok(())
+    }
+
+    /// Runs the CPU for a slice of instructions towards its goal.
+    fn run_slice(&mut self) -> io::Result<()> {
+        let goal = match self.running {
+            Some(goal) => goal,
+            None => return Ok(()),
+        };
+
+        let calls = &mut self.calls;
+        let mut next = (
+            self.cpu.program_counter,
+            self.cpu.mem_peek(self.cpu.program_counter),
+        );
+        let mut budget = SLICE;
+        let mut reached = false;
+        let result = self.cpu.run_with_callback(|cpu| {
+            let (pc, code) = next;
+            track_call(calls, pc, code, cpu);
+            next = (cpu.program_counter, cpu.mem_peek(cpu.program_counter));
+            budget -= 1;
+            reached = goal.reached(cpu, calls);
+            !reached && budget > 0
+        });
+
+        match result {
+            Err(error) => self.stopped("exception", None, Some(error.to_string())),
+            Ok(Some(hit)) => self.stopped("breakpoint", Some(hit), None),
+            Ok(None) if reached => self.stopped("step", None, None),
+            Ok(None) if budget == 0 => Ok(()),
+            Ok(None) => self.stopped("exception", None, Some("BRK".to_string())),
+        }
+    }
+}
+
+/// Updates the call stack after the instruction `code` at `pc` executed.
+fn track_call(calls: &mut Vec<Call>, pc: u16, code: u8, cpu: &CPU) {
+    match code {
+        JSR => calls.push(Call {
+            site: pc,
+            target: cpu.program_counter,
+            stack_pointer: cpu.stack_pointer,
+        }),
+        RTS => {
+            calls.pop();
+        }
+        _ => {}
+    }
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    match (Path::new(a).canonicalize(), Path::new(b).canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Parses the `0x0600` memory and instruction references this server hands out.
+fn parse_reference(reference: &str) -> Result<u16, String> {
+    let digits = reference
+        .strip_prefix("0x")
+        .or_else(|| reference.strip_prefix('$'))
+        .unwrap_or(reference);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address {}", reference))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
@@ -0,0 +1,40 @@
+//! Debug adapter for the 6502 CPU, speaking the Debug Adapter Protocol on
+//! stdio, or on a localhost TCP port with `--port`:
+//!
+//!     dap [--port 4711]
+
+use std::io::{self, BufReader};
+use std::net::TcpListener;
+use std::process;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.as_slice() {
+        [] => dap::serve(BufReader::new(io::stdin()), io::stdout()),
+        [flag, port] if flag == "--port" => match port.parse::<u16>() {
+            Ok(port) => serve_tcp(port),
+            Err(_) => usage(),
+        },
+        _ => usage(),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+/// Serves the sessions of the clients connecting to the port, one at a time.
+fn serve_tcp(port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("listening on 127.0.0.1:{}", listener.local_addr()?.port());
+    for stream in listener.incoming() {
+        let stream = stream?;
+        dap::serve(BufReader::new(stream.try_clone()?), stream)?;
+    }
+    Ok(())
+}
+
+fn usage() -> ! {
+    eprintln!("usage: dap [--port PORT]");
+    process::exit(2);
+}
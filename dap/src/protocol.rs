@@ -0,0 +1,49 @@
+//! Framing of Debug Adapter Protocol messages: a `Content-Length` header,
+//! an empty line, then that many bytes of JSON.
+
+use serde_json::Value;
+use std::io::{self, BufRead, Write};
+
+/// Reads the next message, `None` once the input is closed.
+pub fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            let value = value
+                .trim()
+                .parse()
+                .map_err(|_| invalid("invalid Content-Length"))?;
+            length = Some(value);
+        }
+    }
+
+    let length = length.ok_or_else(|| invalid("missing Content-Length"))?;
+    let mut content = vec![0; length];
+    input.read_exact(&mut content)?;
+    serde_json::from_slice(&content)
+        .map(Some)
+        .map_err(|e| invalid(&e.to_string()))
+}
+
+pub fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+    write!(
+        output,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    output.flush()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,269 @@
+//! Drives the `dap` binary over stdio the way an editor would.
+
+use dap::protocol::{read_message, write_message};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::io::BufReader;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+const PROGRAM: &str = "\
+        .org $0600
+start:  ldx #3
+        stx $10
+        jsr double
+        lda $10
+        cmp #$30
+        brk
+double: jsr shift
+        asl $10
+        rts
+shift:  asl $10
+        rts
+";
+
+struct Client {
+    child: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+    seq: u64,
+    /// Events received while waiting for a response
+    events: VecDeque<Value>,
+}
+
+impl Client {
+    fn start() -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_dap"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        Client {
+            input: child.stdin.take().unwrap(),
+            output: BufReader::new(child.stdout.take().unwrap()),
+            child,
+            seq: 0,
+            events: VecDeque::new(),
+        }
+    }
+
+    fn receive(&mut self) -> Value {
+        read_message(&mut self.output)
+            .unwrap()
+            .expect("the server closed its output")
+    }
+
+    /// Sends a request and returns the body of its successful response.
+    fn request(&mut self, command: &str, arguments: Value) -> Value {
+        self.seq += 1;
+        let request = json!({ "seq": self.seq, "type": "request", "command": command, "arguments": arguments });
+        write_message(&mut self.input, &request).unwrap();
+        loop {
+            let message = self.receive();
+            if message["type"] == "event" {
+                self.events.push_back(message);
+                continue;
+            }
+            assert_eq!(message["request_seq"], self.seq);
+            assert_eq!(message["success"], true, "{} failed: {}", command, message);
+            return message["body"].clone();
+        }
+    }
+
+    fn event(&mut self, name: &str) -> Value {
+        let event = match self.events.pop_front() {
+            Some(event) => event,
+            None => self.receive(),
+        };
+        assert_eq!(event["event"], name, "unexpected event {}", event);
+        event["body"].clone()
+    }
+
+    /// Name and line of every frame, innermost first
+    fn frames(&mut self) -> Vec<(String, u64)> {
+        let trace = self.request("stackTrace", json!({ "threadId": 1 }));
+        trace["stackFrames"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|frame| {
+                (
+                    frame["name"].as_str().unwrap().to_string(),
+                    frame["line"].as_u64().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn register(&mut self, name: &str) -> String {
+        let variables = self.request("variables", json!({ "variablesReference": 1 }));
+        let variable = variables["variables"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|variable| variable["name"] == name)
+            .unwrap()
+            .clone();
+        variable["value"].as_str().unwrap().to_string()
+    }
+}
+
+fn frames(expected: &[(&str, u64)]) -> Vec<(String, u64)> {
+    expected
+        .iter()
+        .map(|(name, line)| (name.to_string(), *line))
+        .collect()
+}
+
+#[test]
+fn test_debug_session() {
+    let path = std::env::temp_dir().join(format!("dap-session-{}.asm", std::process::id()));
+    std::fs::write(&path, PROGRAM).unwrap();
+    let path = path.to_str().unwrap().to_string();
+
+    let mut client = Client::start();
+    let capabilities = client.request("initialize", json!({ "adapterID": "6502" }));
+    assert_eq!(capabilities["supportsInstructionBreakpoints"], true);
+    client.request("launch", json!({ "program": path, "stopOnEntry": true }));
+    client.event("initialized");
+
+    // line 11 is `shift`, the instruction breakpoint is the CMP once A holds $0C
+    let breakpoints = client.request(
+        "setBreakpoints",
+        json!({ "source": { "path": path }, "breakpoints": [{ "line": 11 }, { "line": 13 }] }),
+    );
+    assert_eq!(breakpoints["breakpoints"][0]["verified"], true);
+    assert_eq!(breakpoints["breakpoints"][0]["line"], 11);
+    assert_eq!(breakpoints["breakpoints"][1]["verified"], false);
+    let shift = breakpoints["breakpoints"][0]["id"].clone();
+    let breakpoints = client.request(
+        "setInstructionBreakpoints",
+        json!({ "breakpoints": [{ "instructionReference": "0x0609", "condition": "A == $0C" }] }),
+    );
+    let compare = breakpoints["breakpoints"][0]["id"].clone();
+
+    client.request("configurationDone", json!({}));
+    assert_eq!(client.event("stopped")["reason"], "entry");
+    assert_eq!(client.frames(), frames(&[("start", 2)]));
+
+    client.request("next", json!({ "threadId": 1 }));
+    client.event("stopped");
+    client.request("next", json!({ "threadId": 1 }));
+    assert_eq!(client.event("stopped")["reason"], "step");
+    assert_eq!(client.frames(), frames(&[("start", 4)]));
+
+    client.request("stepIn", json!({ "threadId": 1 }));
+    client.event("stopped");
+    assert_eq!(client.frames(), frames(&[("double", 8), ("start", 4)]));
+
+    client.request("continue", json!({ "threadId": 1 }));
+    let stopped = client.event("stopped");
+    assert_eq!(stopped["reason"], "breakpoint");
+    assert_eq!(stopped["hitBreakpointIds"], json!([shift]));
+    assert_eq!(
+        client.frames(),
+        frames(&[("shift", 11), ("double", 8), ("start", 4)])
+    );
+
+    client.request("stepOut", json!({ "threadId": 1 }));
+    client.event("stopped");
+    assert_eq!(client.frames(), frames(&[("double", 9), ("start", 4)]));
+    client.request("stepOut", json!({ "threadId": 1 }));
+    client.event("stopped");
+    assert_eq!(client.frames(), frames(&[("start", 5)]));
+
+    client.request("continue", json!({ "threadId": 1 }));
+    let stopped = client.event("stopped");
+    assert_eq!(stopped["hitBreakpointIds"], json!([compare]));
+    assert_eq!(client.register("A"), "$0C");
+    assert_eq!(client.register("PC"), "$0609");
+
+    let memory = client.request(
+        "readMemory",
+        json!({ "memoryReference": "0x0010", "count": 2 }),
+    );
+    assert_eq!(memory["address"], "0x0010");
+    assert_eq!(memory["data"], "DAA=");
+
+    client.request("continue", json!({ "threadId": 1 }));
+    let stopped = client.event("stopped");
+    assert_eq!(stopped["reason"], "exception");
+    assert_eq!(stopped["text"], "BRK");
+
+    client.request("disconnect", json!({}));
+    assert!(client.child.wait().unwrap().success());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_step_over_subroutine() {
+    let path = std::env::temp_dir().join(format!("dap-next-{}.asm", std::process::id()));
+    std::fs::write(&path, PROGRAM).unwrap();
+    let path = path.to_str().unwrap().to_string();
+
+    let mut client = Client::start();
+    client.request("initialize", json!({}));
+    client.request("launch", json!({ "program": path }));
+    client.event("initialized");
+    client.request(
+        "setInstructionBreakpoints",
+        json!({ "breakpoints": [{ "instructionReference": "0x0604" }] }),
+    );
+    client.request("configurationDone", json!({}));
+    assert_eq!(client.event("stopped")["reason"], "breakpoint");
+
+    // the whole subroutine runs, its result is in memory
+    client.request("next", json!({ "threadId": 1 }));
+    assert_eq!(client.event("stopped")["reason"], "step");
+    assert_eq!(client.frames(), frames(&[("start", 5)]));
+    let memory = client.request(
+        "readMemory",
+        json!({ "memoryReference": "0x0010", "count": 1 }),
+    );
+    assert_eq!(memory["data"], "DA==");
+
+    client.request("disconnect", json!({}));
+    assert!(client.child.wait().unwrap().success());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_step_out_of_subroutine_using_the_stack() {
+    let path = std::env::temp_dir().join(format!("dap-step-out-{}.asm", std::process::id()));
+    std::fs::write(
+        &path,
+        "\
+        .org $0600
+start:  jsr sub
+        brk
+sub:    pha
+        nop
+        pla
+        nop
+        rts
+entry = sub
+",
+    )
+    .unwrap();
+    let path = path.to_str().unwrap().to_string();
+
+    let mut client = Client::start();
+    client.request("initialize", json!({}));
+    client.request("launch", json!({ "program": path }));
+    client.event("initialized");
+    client.request(
+        "setBreakpoints",
+        json!({ "source": { "path": path }, "breakpoints": [{ "line": 5 }] }),
+    );
+    client.request("configurationDone", json!({}));
+    assert_eq!(client.event("stopped")["reason"], "breakpoint");
+    assert_eq!(client.frames(), frames(&[("sub", 5), ("start", 2)]));
+
+    // the PLA raises the stack pointer without leaving `sub`
+    client.request("stepOut", json!({ "threadId": 1 }));
+    assert_eq!(client.event("stopped")["reason"], "step");
+    assert_eq!(client.frames(), frames(&[("start", 3)]));
+
+    client.request("disconnect", json!({}));
+    assert!(client.child.wait().unwrap().success());
+    std::fs::remove_file(&path).unwrap();
+}